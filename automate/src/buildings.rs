@@ -0,0 +1,1968 @@
+use crate::BOM_CHAR;
+use crate::config::{
+    BuildingsMode, Config, DuplicatesMode, QuoteStyle, RegionStateKeys, ReservesMode, Rounding,
+    SplitTarget, region_state_tag,
+};
+use crate::game_data::read_pdx_file;
+use crate::output::Output;
+use crate::quotes::{is_bare_value, normalize_quotes, quote_region_state_keys, quotes_values};
+use crate::report::{
+    FileReport, OwnerReport, Report, ScaledReport, SkippedEntry, SplitReport, TargetReport,
+};
+use crate::script::ScriptDecision;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tiger_lib::Token;
+use tiger_lib::block::{BV, Block, BlockItem, Field};
+
+/// One entry of a building's `add_ownership`
+pub struct Owner {
+    /// The owning building type, or `None` for direct country and company ownership
+    pub building: Option<String>,
+    /// The owning company type, for company ownership
+    pub company: Option<String>,
+    pub country: String,
+    pub levels: u16,
+    pub region: Option<String>,
+}
+
+/// Full-line comments of a buildings file, which are kept in the modded file
+#[derive(Default)]
+pub struct SourceComments {
+    /// Comments before the `BUILDINGS` block
+    pub header: Vec<String>,
+    /// Comments inside each state's block or between it and the previous state,
+    /// by its `s:STATE_*` key
+    pub states: HashMap<String, Vec<String>>,
+}
+
+impl SourceComments {
+    /// Reads the comments of the buildings file `contents`
+    pub fn read(contents: &str) -> Self {
+        let mut comments = Self::default();
+        let mut pending = Vec::new();
+        let mut state: Option<&str> = None;
+        let mut depth = 0;
+        for line in contents.lines() {
+            let line = line.trim_start_matches(BOM_CHAR).trim();
+            if line.starts_with('#') {
+                match state {
+                    Some(state) => comments
+                        .states
+                        .entry(state.to_string())
+                        .or_default()
+                        .push(line.to_string()),
+                    None if depth == 0 => comments.header.push(line.to_string()),
+                    None => pending.push(line.to_string()),
+                }
+                continue;
+            }
+
+            let code = line.split('#').next().unwrap_or_default();
+            if depth == 1 && code.starts_with("s:") {
+                let name = code.split(['=', ' ', '\t']).next().unwrap_or_default();
+                state = Some(name);
+                comments
+                    .states
+                    .entry(name.to_string())
+                    .or_default()
+                    .append(&mut pending);
+            }
+            for c in code.chars() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => (),
+                }
+            }
+            if depth <= 1 {
+                state = None;
+            }
+        }
+        comments
+    }
+}
+
+/// Generates the modded buildings files for each of `in_paths`.
+/// Output files are only written if none of the input files failed.
+pub fn run_buildings(in_paths: &[PathBuf], config: &Config, output: &mut Output) -> Report {
+    // Files a previous run generated into the input directory, as when the input and output
+    // are the same mod, are skipped instead of splitting their buildings a second time
+    let mut report = Report::default();
+    let mut generated = Vec::new();
+    for in_path in in_paths {
+        if let Some(source) = output.generated_from(in_path, in_paths) {
+            let mut file_report = FileReport::new(in_path.clone());
+            file_report.warnings.push(format!(
+                "skipped {}, it was generated from {} by a previous run",
+                in_path.display(),
+                source.display()
+            ));
+            report.files.push(file_report);
+            generated.push(in_path);
+        }
+    }
+    let in_paths = in_paths
+        .iter()
+        .filter(|in_path| !generated.contains(in_path))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let changes = cross_file_changes(&in_paths, config);
+    let progress = output.begin_run(in_paths.len());
+    let mut total_buildings_split = 0;
+    for in_path in &in_paths {
+        let mut file_report = FileReport::new(in_path.clone());
+        if let Err(err) =
+            generate_buildings_file(in_path, config, &changes, output, &mut file_report)
+        {
+            file_report.error = Some(format!("{:#}", err));
+        }
+
+        total_buildings_split += file_report.splits.len();
+        report.files.push(file_report);
+        progress.set_message(format!("{} buildings split", total_buildings_split));
+        progress.inc(1);
+    }
+    report.commit(output);
+    progress.finish();
+
+    report
+}
+
+/// Building owners of levels, by the `STATE_*` key of their region, country and building type
+type OwnerKey = (String, String, String);
+
+/// What splitting the buildings of every input file changes in other files
+#[derive(Default)]
+pub struct CrossFileChanges {
+    /// `STATE_*` keys of the states that get modded buildings, with each of those buildings
+    pub modded_buildings: HashSet<(String, String)>,
+    /// Share of the levels each ownership building owned that it still owns after splitting,
+    /// for those whose owned levels change
+    pub ownership_scales: HashMap<OwnerKey, f32>,
+    /// Modded buildings that each company type owns levels of after splitting
+    pub company_buildings: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Splits the buildings of `in_paths` without writing them to find what changes across files
+pub fn cross_file_changes(in_paths: &[PathBuf], config: &Config) -> CrossFileChanges {
+    let mut changes = CrossFileChanges::default();
+    let mut owned_before: HashMap<OwnerKey, i32> = HashMap::new();
+    let mut splits = Vec::new();
+    for in_path in in_paths {
+        // Files that fail are reported when they are generated
+        let Ok(contents) = read_pdx_file(in_path) else {
+            continue;
+        };
+        let mut file_report = FileReport::new(in_path.clone());
+        if create_modded_buildings_file(
+            &contents,
+            &SourceComments::default(),
+            config,
+            None,
+            None,
+            &mut std::io::sink(),
+            &mut file_report,
+        )
+        .is_err()
+        {
+            continue;
+        }
+
+        for (owner, levels) in owned_levels(&contents, &config.ownership_buildings) {
+            *owned_before.entry(owner).or_default() += i32::from(levels);
+        }
+        for split in &file_report.splits {
+            let state = split.state.strip_prefix("s:").unwrap_or(&split.state);
+            for (i, target) in split.targets.iter().enumerate() {
+                if target.levels > 0 {
+                    changes
+                        .modded_buildings
+                        .insert((state.to_string(), target.building.clone()));
+                }
+                for owner in &split.owners {
+                    if let Some(company) = &owner.company
+                        && owner.moved_levels[i] > 0
+                    {
+                        changes
+                            .company_buildings
+                            .entry(company.clone())
+                            .or_default()
+                            .insert(target.building.clone());
+                    }
+                }
+            }
+        }
+        splits.append(&mut file_report.splits);
+    }
+
+    // Owners only move to the target's ownership building if their region gets the target,
+    // which is only known once every file is split
+    let mut owned_change: HashMap<OwnerKey, i32> = HashMap::new();
+    for split in &splits {
+        for (i, target) in split.targets.iter().enumerate() {
+            for owner in &split.owners {
+                let (Some(owner_type), Some(region)) = (&owner.building, &owner.region) else {
+                    continue;
+                };
+                let target_in_region = changes
+                    .modded_buildings
+                    .contains(&(region.clone(), target.building.clone()));
+                let new_owner_type = config.owner_type_for(
+                    owner_type,
+                    &split.building,
+                    &target.building,
+                    target_in_region,
+                );
+                if new_owner_type == owner_type {
+                    continue;
+                }
+                let moved = i32::from(owner.moved_levels[i]);
+                for (owner_type, change) in [(owner_type.as_str(), -moved), (new_owner_type, moved)]
+                {
+                    if config
+                        .ownership_buildings
+                        .iter()
+                        .any(|building| building == owner_type)
+                    {
+                        let owner = (
+                            region.clone(),
+                            owner.country.clone(),
+                            owner_type.to_string(),
+                        );
+                        *owned_change.entry(owner).or_default() += change;
+                    }
+                }
+            }
+        }
+    }
+
+    for (owner, change) in owned_change {
+        let before = owned_before.get(&owner).copied().unwrap_or_default();
+        if change != 0 && before > 0 {
+            let scale = (before + change).max(0) as f32 / before as f32;
+            changes.ownership_scales.insert(owner, scale);
+        }
+    }
+    changes
+}
+
+/// Levels of every building in `contents` owned by buildings of `owner_types`
+fn owned_levels(contents: &Block, owner_types: &[String]) -> Vec<(OwnerKey, u16)> {
+    let mut owned = Vec::new();
+    let Some(buildings) = contents.get_field_block("BUILDINGS") else {
+        return owned;
+    };
+    for (state_name, state_block) in buildings.iter_definitions() {
+        for (_, region_state_block) in state_block.iter_definitions() {
+            // Files that fail are reported when they are generated
+            let Ok(entries) = building_entries(region_state_block, DuplicatesMode::Separate) else {
+                continue;
+            };
+            for &building in entries.iter().flatten() {
+                let Some(Ok(owners)) = building
+                    .get_field_block("add_ownership")
+                    .map(|add_ownership| parse_owners(add_ownership, state_name.as_str()))
+                else {
+                    continue;
+                };
+                for owner in owners {
+                    if let (Some(owner_type), Some(region)) = (owner.building, owner.region)
+                        && owner_types.contains(&owner_type)
+                    {
+                        owned.push(((region, owner.country, owner_type), owner.levels));
+                    }
+                }
+            }
+        }
+    }
+    owned
+}
+
+/// Generates the modded buildings file for `in_path` and hands it to `output`
+fn generate_buildings_file(
+    in_path: &Path,
+    config: &Config,
+    changes: &CrossFileChanges,
+    output: &mut Output,
+    file_report: &mut FileReport,
+) -> anyhow::Result<()> {
+    let contents = read_pdx_file(in_path)?;
+    let source = std::fs::read_to_string(in_path)?;
+    let comments = SourceComments::read(&source);
+
+    let out_name = output.file_name(in_path);
+    let existing = match output.merge_path(&out_name) {
+        Some(path) => Some(read_pdx_file(&path)?),
+        None => None,
+    };
+    let mut buffer = Vec::new();
+    create_modded_buildings_file(
+        &contents,
+        &comments,
+        config,
+        Some(changes),
+        existing.as_ref(),
+        &mut buffer,
+        file_report,
+    )?;
+    let buffer = normalize_quotes(
+        &String::from_utf8_lossy(&buffer),
+        config.quotes,
+        config.quotes == QuoteStyle::Input && quotes_values(&source),
+    );
+    let buffer = quote_region_state_keys(
+        &buffer,
+        match config.region_state_keys {
+            RegionStateKeys::Detect => source.contains("\"region_state:"),
+            RegionStateKeys::Unquoted => false,
+            RegionStateKeys::Quoted => true,
+        },
+    );
+    output.write(
+        &out_name,
+        buffer.as_bytes(),
+        &format!("{} buildings split", file_report.splits.len()),
+    )?;
+    file_report.output = Some(out_name);
+
+    Ok(())
+}
+
+/// Writes the modded buildings history for `contents` with its `comments` to `out_file`,
+/// recording the buildings that were split in `report`.
+/// Levels owned by the split building itself are only owned by the modded building instead
+/// where the `changes` of every file have it, or always if they aren't given, and ownership
+/// buildings are rescaled to the levels they own after the `changes`.
+/// With `sparse` patches, only the states and region states with changed buildings are written.
+/// Merging into the `existing` output keeps everything in it except the generated buildings.
+pub fn create_modded_buildings_file(
+    contents: &Block,
+    comments: &SourceComments,
+    config: &Config,
+    changes: Option<&CrossFileChanges>,
+    existing: Option<&Block>,
+    out_file: &mut impl Write,
+    report: &mut FileReport,
+) -> anyhow::Result<()> {
+    write!(out_file, "{}", BOM_CHAR)?;
+    for comment in &comments.header {
+        writeln!(out_file, "{}", comment)?;
+    }
+    writeln!(out_file, "BUILDINGS={{")?;
+
+    let buildings = contents
+        .get_field_block("BUILDINGS")
+        .ok_or_else(|| anyhow::anyhow!("Missing BUILDINGS block"))?;
+    let existing = existing.and_then(|existing| existing.get_field_block("BUILDINGS"));
+    // States and region states are sorted so the output doesn't depend on the input's order
+    let mut states = buildings
+        .iter_assignments_and_definitions()
+        .collect::<Vec<_>>();
+    states.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    for &(state_name, state_block) in &states {
+        let existing_state =
+            existing.and_then(|existing| existing.get_field_block(state_name.as_str()));
+        let mut state_out = Vec::new();
+        let mut region_states = state_block
+            .get_block()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "State {} at line {} is not a block",
+                    state_name.as_str(),
+                    state_name.loc.line
+                )
+            })?
+            .iter_assignments_and_definitions()
+            .collect::<Vec<_>>();
+        region_states.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        for &(region_state_name, region_state_block) in &region_states {
+            let region_state_block = region_state_block.get_block().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} at line {} in {} is not a block",
+                    region_state_name.as_str(),
+                    region_state_name.loc.line,
+                    state_name.as_str()
+                )
+            })?;
+            let mut region_state_out = Vec::new();
+            let out_file = &mut region_state_out;
+            writeln!(out_file, "\t\t{} = {{", region_state_name.as_str())?;
+            let entries =
+                building_entries(region_state_block, config.duplicates).map_err(|err| {
+                    anyhow::anyhow!(
+                        "{} in {} {}",
+                        err,
+                        state_name.as_str(),
+                        region_state_name.as_str()
+                    )
+                })?;
+            // What each split entry is replaced with, written once every entry is split
+            let mut split_entries = vec![None; entries.len()];
+            // Building types written by this region state, which replace them when merging
+            let mut generated_types = Vec::new();
+            for (entry, split_entry) in entries.iter().zip(&mut split_entries) {
+                let splits_before = report.splits.len();
+                let scaled_before = report.scaled.len();
+                // Splits the entry into `split_entry`, or leaves it alone by returning early
+                let mut split_entry_levels = || -> anyhow::Result<()> {
+                    let mut entry_out = Vec::new();
+                    let out_file = &mut entry_out;
+
+                    // Check if this building is of a split type
+                    let building = entry[0];
+                    let building_type = entry_building_type(entry);
+                    if config.warn_unknown_countries {
+                        warn_unknown_countries(
+                            entry,
+                            state_name.as_str(),
+                            region_state_name.as_str(),
+                            config,
+                            report,
+                        );
+                    }
+                    if config
+                        .never_touch
+                        .iter()
+                        .any(|never_touch| never_touch == building_type)
+                    {
+                        report.warnings.push(format!(
+                            "left {} in {} {} untouched, it is in `never_touch`",
+                            building_type,
+                            state_name.as_str(),
+                            region_state_name.as_str()
+                        ));
+                        return Ok(());
+                    }
+                    let ownership_scale = changes.and_then(|changes| {
+                        let state = state_name.as_str();
+                        let tag = region_state_name.as_str();
+                        changes.ownership_scales.get(&(
+                            state.strip_prefix("s:").unwrap_or(state).to_string(),
+                            format!("c:{}", region_state_tag(tag)),
+                            building_type.to_string(),
+                        ))
+                    });
+                    if (ownership_scale.is_some() || config.splits.contains_key(building_type))
+                        && let Some(levels) = scripted_levels(entry)
+                    {
+                        report.warnings.push(format!(
+                            "left {} in {} {} as it was, its levels {} are a script value",
+                            building_type,
+                            state_name.as_str(),
+                            region_state_name.as_str(),
+                            levels
+                        ));
+                        return Ok(());
+                    }
+                    if let Some(&scale) = ownership_scale {
+                        let mut owners =
+                            entry_owners(entry, state_name.as_str(), region_state_name.as_str())?;
+                        sort_owners(&mut owners);
+                        let total_levels = owned_total(
+                            &owners,
+                            building_type,
+                            state_name.as_str(),
+                            region_state_name.as_str(),
+                        )?;
+                        let levels = (f32::from(total_levels) * scale).round() as u16;
+                        if levels == 0 {
+                            report.warnings.push(format!(
+                                "removed {} in {} {}, it owns none of its {} levels anymore",
+                                building_type,
+                                state_name.as_str(),
+                                region_state_name.as_str(),
+                                total_levels
+                            ));
+                            *split_entry = Some(entry_out);
+                            generated_types.push(building_type);
+                        } else if levels != total_levels {
+                            report.warnings.push(format!(
+                            "changed {} in {} {} from {} to {} levels, matching the levels it owns",
+                            building_type,
+                            state_name.as_str(),
+                            region_state_name.as_str(),
+                            total_levels,
+                            levels
+                        ));
+                            write_rescaled_building(out_file, building, &owners, levels)?;
+                            *split_entry = Some(entry_out);
+                            generated_types.push(building_type);
+                        }
+                        return Ok(());
+                    }
+                    let Some(split_rule) = config.splits.get(building_type) else {
+                        return Ok(());
+                    };
+
+                    // Check if this building has the minimum number of levels for splitting
+                    let mut original_owners =
+                        entry_owners(entry, state_name.as_str(), region_state_name.as_str())?;
+                    let total_building_levels = owned_total(
+                        &original_owners,
+                        building_type,
+                        state_name.as_str(),
+                        region_state_name.as_str(),
+                    )?;
+                    if !config.splits_country(region_state_name.as_str()) {
+                        report.skipped.push(SkippedEntry {
+                            state: state_name.to_string(),
+                            region_state: region_state_name.to_string(),
+                            building: building_type.to_string(),
+                            reason: "country is not split".to_string(),
+                        });
+                        return Ok(());
+                    }
+                    if let Some(reason) = split_rule.skip_reason(
+                        state_name.as_str(),
+                        region_state_name.as_str(),
+                        total_building_levels,
+                        &config.game_data,
+                    ) {
+                        report.skipped.push(SkippedEntry {
+                            state: state_name.to_string(),
+                            region_state: region_state_name.to_string(),
+                            building: building_type.to_string(),
+                            reason,
+                        });
+                        return Ok(());
+                    }
+                    if let Some(scale) = split_rule.scale {
+                        sort_owners(&mut original_owners);
+                        let levels =
+                            round_levels(f32::from(total_building_levels) * scale, config.rounding)
+                                .max(1);
+                        if levels != total_building_levels {
+                            report.scaled.push(ScaledReport {
+                                state: state_name.to_string(),
+                                region_state: region_state_name.to_string(),
+                                building: building_type.to_string(),
+                                total_levels: total_building_levels,
+                                levels,
+                            });
+                            write_rescaled_building(out_file, building, &original_owners, levels)?;
+                            *split_entry = Some(entry_out);
+                            generated_types.push(building_type);
+                        }
+                        return Ok(());
+                    }
+                    let (keep, targets) =
+                        split_rule.division_for(state_name.as_str(), region_state_name.as_str());
+                    // Scaling the weights of the targets is the same as inversely scaling `keep`
+                    let keep =
+                        keep / split_rule.scaling_factor(state_name.as_str(), &config.game_data);
+                    let mut target_levels = target_levels(
+                        total_building_levels,
+                        keep,
+                        targets,
+                        split_rule.min_split_levels,
+                        config.rounding,
+                    );
+                    if target_levels.iter().all(|&levels| levels == 0)
+                        && split_rule
+                            .partial_min_levels
+                            .is_some_and(|min_levels| total_building_levels >= min_levels)
+                        && let Some(largest) = (0..targets.len())
+                            .rev()
+                            .max_by(|&a, &b| targets[a].weight.total_cmp(&targets[b].weight))
+                    {
+                        // Small buildings still get some of the modded industry
+                        target_levels[largest] = 1;
+                    }
+                    limit_moved_levels(
+                        &mut target_levels,
+                        targets,
+                        split_rule
+                            .min_moved_levels
+                            .map(|min_levels| min_levels.min(total_building_levels)),
+                        split_rule.max_moved_levels,
+                    );
+                    if let Some(script) = &config.script {
+                        match script
+                            .decide(
+                                state_name.as_str(),
+                                region_state_name.as_str(),
+                                building,
+                                &original_owners,
+                                total_building_levels,
+                                targets,
+                                &target_levels,
+                            )
+                            .map_err(|err| {
+                                anyhow::anyhow!(
+                                    "{} for {} at line {} in {} {}",
+                                    err,
+                                    building_type,
+                                    building.loc.line,
+                                    state_name.as_str(),
+                                    region_state_name.as_str()
+                                )
+                            })? {
+                            ScriptDecision::Rule => (),
+                            ScriptDecision::Skip => {
+                                report.skipped.push(SkippedEntry {
+                                    state: state_name.to_string(),
+                                    region_state: region_state_name.to_string(),
+                                    building: building_type.to_string(),
+                                    reason: "left alone by the split script".to_string(),
+                                });
+                                return Ok(());
+                            }
+                            ScriptDecision::Levels(levels) => target_levels = levels,
+                        }
+                    }
+                    if target_levels.iter().all(|&levels| levels == 0) {
+                        report.skipped.push(SkippedEntry {
+                            state: state_name.to_string(),
+                            region_state: region_state_name.to_string(),
+                            building: building_type.to_string(),
+                            reason: format!("too few levels to split ({})", total_building_levels),
+                        });
+                        return Ok(());
+                    }
+
+                    // Split the building, using a weighted approach for assigning owners
+                    sort_owners(&mut original_owners);
+                    // Levels moved to each target by each owner, taking each target's levels
+                    // from what the owners have left after the previous targets
+                    let mut remaining = original_owners
+                        .iter()
+                        .map(|owner| owner.levels)
+                        .collect::<Vec<_>>();
+                    let mut moved_per_target = Vec::new();
+                    let mut rng = config.owner_seed.map(|seed| {
+                        SeededRng::new(
+                            seed,
+                            &[
+                                state_name.as_str(),
+                                region_state_name.as_str(),
+                                building_type,
+                            ],
+                        )
+                    });
+                    for &levels in &target_levels {
+                        let mut moved = match &mut rng {
+                            Some(rng) => random_apportion(levels, &remaining, rng),
+                            None => apportion(levels, &remaining),
+                        };
+                        enforce_owner_minimum(&mut moved, &remaining, split_rule.min_owner_levels);
+                        for (remaining, moved) in remaining.iter_mut().zip(&mut moved) {
+                            *moved = (*moved).min(*remaining);
+                            *remaining -= *moved;
+                        }
+                        moved_per_target.push(moved);
+                    }
+
+                    // The base building keeps what the owners didn't give, so levels are only lost
+                    // if a target didn't get the levels it should have
+                    let moved_levels = moved_per_target
+                        .iter()
+                        .map(|moved| bounded_levels(sum_levels(moved)))
+                        .collect::<Vec<_>>();
+                    if moved_levels != target_levels {
+                        let problem = format!(
+                            "levels of {} in {} {} aren't conserved, {} levels should have moved {:?} to its targets but moved {:?}",
+                            building_type,
+                            state_name.as_str(),
+                            region_state_name.as_str(),
+                            total_building_levels,
+                            target_levels,
+                            moved_levels
+                        );
+                        if !config.exact_levels {
+                            anyhow::bail!("{}", problem);
+                        }
+                        report.warnings.push(problem);
+                        target_levels = moved_levels;
+                    }
+                    report.splits.push(SplitReport {
+                        state: state_name.to_string(),
+                        region_state: region_state_name.to_string(),
+                        building: building_type.to_string(),
+                        total_levels: total_building_levels,
+                        targets: targets
+                            .iter()
+                            .zip(&target_levels)
+                            .map(|(target, &levels)| TargetReport {
+                                building: target.building.clone(),
+                                levels,
+                            })
+                            .collect(),
+                        owners: original_owners
+                            .iter()
+                            .enumerate()
+                            .map(|(i, owner)| OwnerReport {
+                                building: owner.building.clone(),
+                                company: owner.company.clone(),
+                                country: owner.country.clone(),
+                                region: owner.region.clone(),
+                                levels: owner.levels,
+                                moved_levels: moved_per_target
+                                    .iter()
+                                    .map(|moved| moved[i])
+                                    .collect(),
+                            })
+                            .collect(),
+                    });
+
+                    let reserves = building.get_field_value("reserves");
+                    let invalid_reserves = |err: anyhow::Error| {
+                        anyhow::anyhow!(
+                            "{} of {} at line {} in {} {}",
+                            err,
+                            building_type,
+                            building.loc.line,
+                            state_name.as_str(),
+                            region_state_name.as_str()
+                        )
+                    };
+                    let reserves_mode = split_rule.reserves.unwrap_or(config.reserves);
+                    let production_methods = building
+                        .get_field_block("activate_production_methods")
+                        .map(|block| {
+                            block
+                                .iter_values()
+                                .map(|pm| pm.as_str())
+                                .collect::<Vec<_>>()
+                        })
+                        .unwrap_or_default();
+
+                    // Create the basic building, without the owners that gave up all their levels,
+                    // unless every level was split off
+                    if remaining.iter().any(|&levels| levels > 0) {
+                        writeln!(out_file, "\t\t\tcreate_building = {{")?;
+                        writeln!(out_file, "\t\t\t\tbuilding = \"{}\"", building_type)?;
+                        writeln!(out_file, "\t\t\t\tadd_ownership = {{")?;
+                        for (owner, &levels) in original_owners.iter().zip(&remaining) {
+                            if levels == 0 {
+                                continue;
+                            }
+
+                            write_owner(out_file, owner, owner.building.as_deref(), levels)?;
+                        }
+                        writeln!(out_file, "\t\t\t\t}}")?;
+                        if reserves_mode == ReservesMode::Proportional
+                            && let Some(reserves) = reserves
+                        {
+                            let levels = bounded_levels(sum_levels(&remaining));
+                            writeln!(
+                                out_file,
+                                "\t\t\t\treserves = {}",
+                                proportional_reserves(
+                                    reserves.as_str(),
+                                    levels,
+                                    total_building_levels
+                                )
+                                .map_err(invalid_reserves)?
+                            )?;
+                        }
+                        write_production_methods(out_file, &production_methods)?;
+                        for Field(key, cmp, value) in building.iter_fields() {
+                            if KNOWN_FIELDS.contains(&key.as_str()) {
+                                continue;
+                            }
+                            write!(out_file, "\t\t\t\t{} {} ", key, cmp)?;
+                            write_bv(out_file, value, 4)?;
+                            writeln!(out_file)?;
+                        }
+                        writeln!(out_file, "\t\t\t}}")?;
+                    }
+
+                    // Create the modded buildings
+                    for ((target, &levels), moved) in
+                        targets.iter().zip(&target_levels).zip(&moved_per_target)
+                    {
+                        if levels == 0 {
+                            continue;
+                        }
+
+                        writeln!(out_file, "\t\t\tcreate_building = {{")?;
+                        writeln!(out_file, "\t\t\t\tbuilding = \"{}\"", target.building)?;
+                        writeln!(out_file, "\t\t\t\tadd_ownership = {{")?;
+                        // Levels owned by countries directly that go to the state's country
+                        let mut local_levels = 0;
+                        for (owner, &moved_levels) in original_owners.iter().zip(moved) {
+                            if moved_levels == 0 {
+                                continue;
+                            }
+                            if split_rule.local_ownership
+                                && owner.building.is_none()
+                                && owner.company.is_none()
+                            {
+                                local_levels += moved_levels;
+                                continue;
+                            }
+
+                            let target_in_region = changes.is_none_or(|changes| {
+                                owner.region.as_ref().is_some_and(|region| {
+                                    changes
+                                        .modded_buildings
+                                        .contains(&(region.clone(), target.building.clone()))
+                                })
+                            });
+                            let owner_type = owner.building.as_deref().map(|owner_type| {
+                                config.owner_type_for(
+                                    owner_type,
+                                    building_type,
+                                    &target.building,
+                                    target_in_region,
+                                )
+                            });
+                            write_owner(out_file, owner, owner_type, moved_levels)?;
+                        }
+                        if local_levels > 0 {
+                            let tag = region_state_name.as_str();
+                            let local_owner = Owner {
+                                building: None,
+                                company: None,
+                                country: format!("c:{}", region_state_tag(tag)),
+                                levels: local_levels,
+                                region: None,
+                            };
+                            write_owner(out_file, &local_owner, None, local_levels)?;
+                        }
+                        writeln!(out_file, "\t\t\t\t}}")?;
+                        let target_reserves = match (target.reserves, reserves, reserves_mode) {
+                            (Some(fixed), _, _) => Some(fixed.to_string()),
+                            (None, Some(reserves), ReservesMode::Copy) => {
+                                Some(reserves.as_str().to_string())
+                            }
+                            (None, Some(reserves), ReservesMode::Proportional) => Some(
+                                proportional_reserves(
+                                    reserves.as_str(),
+                                    levels,
+                                    total_building_levels,
+                                )
+                                .map_err(invalid_reserves)?,
+                            ),
+                            (None, None, _) => None,
+                        };
+                        if let Some(target_reserves) = target_reserves {
+                            writeln!(out_file, "\t\t\t\treserves = {}", target_reserves)?;
+                        }
+                        let target_production_methods = production_methods
+                            .iter()
+                            .filter_map(|&pm| target.production_methods.get(pm).map(String::as_str))
+                            .collect::<Vec<_>>();
+                        write_production_methods(out_file, &target_production_methods)?;
+                        writeln!(out_file, "\t\t\t}}")?;
+                    }
+                    *split_entry = Some(entry_out);
+                    generated_types.push(building_type);
+                    generated_types.extend(targets.iter().map(|target| target.building.as_str()));
+                    Ok(())
+                };
+                if let Err(err) = split_entry_levels() {
+                    if !config.keep_going {
+                        return Err(err);
+                    }
+                    report.splits.truncate(splits_before);
+                    report.scaled.truncate(scaled_before);
+                    report.invalid.push(SkippedEntry {
+                        state: state_name.to_string(),
+                        region_state: region_state_name.to_string(),
+                        building: entry_building_type(entry).to_string(),
+                        reason: format!("{:#}", err),
+                    });
+                }
+            }
+            let existing_region_state = existing_state.and_then(|existing_state| {
+                existing_state.get_field_block(region_state_name.as_str())
+            });
+
+            if config.buildings_mode == BuildingsMode::Replace {
+                // Write everything in the region state, with split entries where they were
+                write_replaced_items(out_file, region_state_block, &entries, &split_entries, 3)?;
+                generated_types.extend(entries.iter().map(|entry| entry_building_type(entry)));
+                write_kept_items(out_file, existing_region_state, &generated_types)?;
+                writeln!(out_file, "\t\t}}")?;
+                state_out.write_all(&region_state_out)?;
+                continue;
+            }
+
+            // Remove each split building once, then create its entries again
+            let mut removed = Vec::new();
+            for (entry, split_entry) in entries.iter().zip(&split_entries) {
+                let building_type = entry_building_type(entry);
+                if split_entry.is_none() || removed.contains(&building_type) {
+                    continue;
+                }
+                removed.push(building_type);
+
+                // Removing the building also removes the entries in conditional blocks,
+                // so they are created again in the same conditional blocks
+                writeln!(out_file, "\t\t\tremove_building = {}", building_type)?;
+                write_patched_items(
+                    out_file,
+                    region_state_block,
+                    building_type,
+                    &entries,
+                    &split_entries,
+                    3,
+                )?;
+            }
+            generated_types.extend(removed);
+            let kept = write_kept_items(out_file, existing_region_state, &generated_types)?;
+            writeln!(out_file, "\t\t}}")?;
+            if !config.sparse || kept || split_entries.iter().any(Option::is_some) {
+                state_out.write_all(&region_state_out)?;
+            }
+        }
+        // Region states that were only in the existing output are kept as they were
+        for (region_state_name, region_state_block) in sorted_definitions(existing_state) {
+            if !region_states
+                .iter()
+                .any(|(name, _)| name.as_str() == region_state_name.as_str())
+            {
+                write!(state_out, "\t\t{} = ", region_state_name)?;
+                write_block(&mut state_out, region_state_block, 2)?;
+                writeln!(state_out)?;
+            }
+        }
+        if config.sparse && state_out.is_empty() {
+            continue;
+        }
+
+        for comment in comments
+            .states
+            .get(state_name.as_str())
+            .into_iter()
+            .flatten()
+        {
+            writeln!(out_file, "\t{}", comment)?;
+        }
+        writeln!(out_file, "\t{} = {{", state_name.as_str())?;
+        out_file.write_all(&state_out)?;
+        writeln!(out_file, "\t}}")?;
+    }
+    for (state_name, state_block) in sorted_definitions(existing) {
+        if !states
+            .iter()
+            .any(|(name, _)| name.as_str() == state_name.as_str())
+        {
+            write!(out_file, "\t{} = ", state_name)?;
+            write_block(out_file, state_block, 1)?;
+            writeln!(out_file)?;
+        }
+    }
+
+    writeln!(out_file, "}}")?;
+
+    Ok(())
+}
+
+/// The definitions of `block`, if any, sorted by their keys
+fn sorted_definitions(block: Option<&Block>) -> Vec<(&Token, &Block)> {
+    let mut definitions = block
+        .into_iter()
+        .flat_map(Block::iter_definitions)
+        .collect::<Vec<_>>();
+    definitions.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    definitions
+}
+
+/// Writes the items of the `existing` output's region state that don't remove or create one
+/// of the `generated_types`, which were added by hand, returning whether there were any
+fn write_kept_items(
+    out_file: &mut impl Write,
+    existing: Option<&Block>,
+    generated_types: &[&str],
+) -> anyhow::Result<bool> {
+    let mut kept = false;
+    for item in existing.into_iter().flat_map(Block::iter_items) {
+        let building_type = match item {
+            BlockItem::Field(Field(key, _, BV::Value(building)))
+                if key.as_str() == "remove_building" =>
+            {
+                Some(building.as_str())
+            }
+            BlockItem::Field(Field(key, _, BV::Block(building)))
+                if key.as_str() == "create_building" =>
+            {
+                building
+                    .get_field_value("building")
+                    .map(|building| building.as_str())
+            }
+            _ => None,
+        };
+        if building_type.is_some_and(|building_type| generated_types.contains(&building_type)) {
+            continue;
+        }
+
+        write!(out_file, "\t\t\t")?;
+        write_item(out_file, item, 3)?;
+        writeln!(out_file)?;
+        kept = true;
+    }
+    Ok(kept)
+}
+
+/// Writes `building` again with `levels` levels, split over its `owners` by their levels
+fn write_rescaled_building(
+    out_file: &mut impl Write,
+    building: &Block,
+    owners: &[Owner],
+    levels: u16,
+) -> anyhow::Result<()> {
+    let owner_levels = owners.iter().map(|owner| owner.levels).collect::<Vec<_>>();
+    let total_levels = bounded_levels(sum_levels(&owner_levels));
+    let mut shares = apportion(levels.min(total_levels), &owner_levels);
+    // Levels added to a growing building are apportioned by the owners' levels as well
+    let mut added = levels.saturating_sub(total_levels);
+    while added > 0 {
+        let step = added.min(total_levels);
+        for (share, extra) in shares.iter_mut().zip(apportion(step, &owner_levels)) {
+            *share += extra;
+        }
+        added -= step;
+    }
+
+    writeln!(out_file, "\t\t\tcreate_building = {{")?;
+    for Field(key, cmp, value) in building.iter_fields() {
+        match key.as_str() {
+            "add_ownership" => {
+                writeln!(out_file, "\t\t\t\tadd_ownership = {{")?;
+                for (owner, &levels) in owners.iter().zip(&shares) {
+                    if levels > 0 {
+                        write_owner(out_file, owner, owner.building.as_deref(), levels)?;
+                    }
+                }
+                writeln!(out_file, "\t\t\t\t}}")?;
+            }
+            "level" => writeln!(out_file, "\t\t\t\tlevel = {}", levels)?,
+            _ => {
+                write!(out_file, "\t\t\t\t{} {} ", key, cmp)?;
+                write_bv(out_file, value, 4)?;
+                writeln!(out_file)?;
+            }
+        }
+    }
+    writeln!(out_file, "\t\t\t}}")?;
+    Ok(())
+}
+
+/// Keys of the conditional blocks whose `create_building` entries are split like the others
+const CONDITIONAL_KEYS: &[&str] = &["if", "else_if", "else"];
+
+/// The `create_building` blocks of a `region_state` and the conditional blocks in it, grouped
+/// into the entries that are split, which have several blocks when merging the duplicates
+/// of a building
+fn building_entries(
+    region_state: &Block,
+    duplicates: DuplicatesMode,
+) -> anyhow::Result<Vec<Vec<&Block>>> {
+    let mut entries = Vec::new();
+    add_building_entries(region_state, duplicates, &mut entries)?;
+    Ok(entries)
+}
+
+/// Adds the entries of `block` to `entries`, only merging duplicates within the same block
+/// since the entries of a conditional block may not be created
+fn add_building_entries<'a>(
+    block: &'a Block,
+    duplicates: DuplicatesMode,
+    entries: &mut Vec<Vec<&'a Block>>,
+) -> anyhow::Result<()> {
+    let mut block_entries: Vec<usize> = Vec::new();
+    for (token, value) in block.iter_assignments_and_definitions() {
+        if CONDITIONAL_KEYS.contains(&token.as_str())
+            && let Some(conditional) = value.get_block()
+        {
+            add_building_entries(conditional, duplicates, entries)?;
+            continue;
+        }
+        if token.as_str() != "create_building" {
+            continue;
+        }
+
+        let Some(building) = value.get_block() else {
+            anyhow::bail!("create_building at line {} is not a block", token.loc.line);
+        };
+        if building.get_field_value("building").is_none() {
+            anyhow::bail!(
+                "create_building at line {} has no building type",
+                token.loc.line
+            );
+        }
+        if duplicates == DuplicatesMode::Merge
+            && let Some(&index) = block_entries.iter().find(|&&index| {
+                entry_building_type(&entries[index]) == entry_building_type(&[building])
+            })
+        {
+            entries[index].push(building);
+        } else {
+            block_entries.push(entries.len());
+            entries.push(vec![building]);
+        }
+    }
+    Ok(())
+}
+
+/// Index of the entry of `entries` starting with `building`,
+/// or `None` for blocks merged into an earlier entry, which are written with it
+fn entry_index(entries: &[Vec<&Block>], building: &Block) -> Option<usize> {
+    entries
+        .iter()
+        .position(|entry| std::ptr::eq(entry[0], building))
+}
+
+/// Writes the entry at `index` of `entries` indented by `depth` tabs, as it was split
+/// if it is one of the `split_entries` and as it was otherwise
+fn write_entry(
+    out_file: &mut impl Write,
+    entries: &[Vec<&Block>],
+    split_entries: &[Option<Vec<u8>>],
+    index: usize,
+    depth: usize,
+) -> anyhow::Result<()> {
+    let indent = "\t".repeat(depth);
+    match &split_entries[index] {
+        // Split entries are written for the top level of a region state
+        Some(split) => {
+            let extra_indent = "\t".repeat(depth.saturating_sub(3));
+            for line in split.split_inclusive(|&byte| byte == b'\n') {
+                out_file.write_all(extra_indent.as_bytes())?;
+                out_file.write_all(line)?;
+            }
+        }
+        None => {
+            for building in &entries[index] {
+                write!(out_file, "{}create_building = ", indent)?;
+                write_block(out_file, building, depth)?;
+                writeln!(out_file)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes every item of `block` indented by `depth` tabs, with the split entries in place of
+/// the entries they were split from, also in the conditional blocks
+fn write_replaced_items(
+    out_file: &mut impl Write,
+    block: &Block,
+    entries: &[Vec<&Block>],
+    split_entries: &[Option<Vec<u8>>],
+    depth: usize,
+) -> anyhow::Result<()> {
+    let indent = "\t".repeat(depth);
+    for item in block.iter_items() {
+        match item {
+            BlockItem::Field(Field(key, _, BV::Block(building)))
+                if key.as_str() == "create_building" =>
+            {
+                if let Some(index) = entry_index(entries, building) {
+                    write_entry(out_file, entries, split_entries, index, depth)?;
+                }
+            }
+            BlockItem::Field(Field(key, cmp, BV::Block(conditional)))
+                if CONDITIONAL_KEYS.contains(&key.as_str()) =>
+            {
+                writeln!(out_file, "{}{} {} {{", indent, key, cmp)?;
+                write_replaced_items(out_file, conditional, entries, split_entries, depth + 1)?;
+                writeln!(out_file, "{}}}", indent)?;
+            }
+            _ => {
+                write!(out_file, "{}", indent)?;
+                write_item(out_file, item, depth)?;
+                writeln!(out_file)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the entries of `building_type` in `block` indented by `depth` tabs, with the split
+/// entries in place of the entries they were split from, and the conditional blocks with
+/// any of them with only their `limit`, returning whether there were any
+fn write_patched_items(
+    out_file: &mut impl Write,
+    block: &Block,
+    building_type: &str,
+    entries: &[Vec<&Block>],
+    split_entries: &[Option<Vec<u8>>],
+    depth: usize,
+) -> anyhow::Result<bool> {
+    let indent = "\t".repeat(depth);
+    let mut written = false;
+    // The conditional blocks of an `if` and the `else_if` and `else` after it, which are
+    // written together if any of them has entries
+    let mut chain = Vec::new();
+    let mut chain_has_entries = false;
+    for item in block.iter_items() {
+        let conditional = match item {
+            BlockItem::Field(Field(key, cmp, BV::Block(conditional)))
+                if CONDITIONAL_KEYS.contains(&key.as_str()) =>
+            {
+                Some((key, cmp, conditional))
+            }
+            _ => None,
+        };
+        if conditional.is_none_or(|(key, _, _)| key.as_str() == "if") {
+            if chain_has_entries {
+                out_file.write_all(&chain)?;
+                written = true;
+            }
+            chain.clear();
+            chain_has_entries = false;
+        }
+
+        if let Some((key, cmp, conditional)) = conditional {
+            writeln!(chain, "{}{} {} {{", indent, key, cmp)?;
+            if let Some(limit) = conditional.get_field_block("limit") {
+                write!(chain, "{}\tlimit = ", indent)?;
+                write_block(&mut chain, limit, depth + 1)?;
+                writeln!(chain)?;
+            }
+            chain_has_entries |= write_patched_items(
+                &mut chain,
+                conditional,
+                building_type,
+                entries,
+                split_entries,
+                depth + 1,
+            )?;
+            writeln!(chain, "{}}}", indent)?;
+        } else if let BlockItem::Field(Field(key, _, BV::Block(building))) = item
+            && key.as_str() == "create_building"
+            && let Some(index) = entry_index(entries, building)
+            && entry_building_type(&entries[index]) == building_type
+        {
+            write_entry(out_file, entries, split_entries, index, depth)?;
+            written = true;
+        }
+    }
+    if chain_has_entries {
+        out_file.write_all(&chain)?;
+        written = true;
+    }
+    Ok(written)
+}
+
+/// The building type of an entry from `building_entries`
+fn entry_building_type<'a>(entry: &[&'a Block]) -> &'a str {
+    entry[0]
+        .get_field_value("building")
+        .map(|building| building.as_str())
+        .unwrap_or_default()
+}
+
+/// Warns about the owners of the buildings of `entry` in `state` and `region_state`
+/// whose country the game doesn't define
+fn warn_unknown_countries(
+    entry: &[&Block],
+    state: &str,
+    region_state: &str,
+    config: &Config,
+    report: &mut FileReport,
+) {
+    for building in entry {
+        // Invalid owners fail the file if the building is split
+        let Some(Ok(owners)) = building
+            .get_field_block("add_ownership")
+            .map(|add_ownership| parse_owners(add_ownership, state))
+        else {
+            continue;
+        };
+        for owner in owners {
+            let tag = owner.country.strip_prefix("c:").unwrap_or(&owner.country);
+            if !config.game_data.countries.contains(tag) {
+                report.warnings.push(format!(
+                    "{} in {} {} is owned by unknown country {}, the game files may be from another version",
+                    entry_building_type(&[building]),
+                    state,
+                    region_state,
+                    owner.country
+                ));
+            }
+        }
+    }
+}
+
+/// The owners of the buildings of `entry` in `state` and `region_state`, combined
+fn entry_owners(entry: &[&Block], state: &str, region_state: &str) -> anyhow::Result<Vec<Owner>> {
+    let mut owners = Vec::new();
+    for building in entry {
+        let building_owners = match building.get_field_block("add_ownership") {
+            Some(add_ownership) => parse_owners(add_ownership, state),
+            None => implicit_owner(building, region_state),
+        }
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "{} of {} at line {} in {} {}",
+                err,
+                entry_building_type(&[building]),
+                building.loc.line,
+                state,
+                region_state
+            )
+        })?;
+        merge_owners(&mut owners, building_owners);
+    }
+    Ok(owners)
+}
+
+/// Adds `owners` to `merged`, adding the levels of owners that are already in it
+fn merge_owners(merged: &mut Vec<Owner>, owners: Vec<Owner>) {
+    for owner in owners {
+        match merged.iter_mut().find(|merged| {
+            merged.building == owner.building
+                && merged.company == owner.company
+                && merged.country == owner.country
+                && merged.region == owner.region
+        }) {
+            Some(merged) => merged.levels += owner.levels,
+            None => merged.push(owner),
+        }
+    }
+}
+
+/// Sorts `owners` by descending levels, and owners with the same levels by who they are,
+/// so they are written in the same order whatever order the input lists them in
+fn sort_owners(owners: &mut [Owner]) {
+    owners.sort_by(|a, b| {
+        b.levels
+            .cmp(&a.levels)
+            .then_with(|| a.country.cmp(&b.country))
+            .then_with(|| a.building.cmp(&b.building))
+            .then_with(|| a.company.cmp(&b.company))
+            .then_with(|| a.region.cmp(&b.region))
+    });
+}
+
+/// Returns the share of `reserves` of a building with `total_levels` levels
+/// for a part of it with `levels` levels, rounded to hundredths
+fn proportional_reserves(reserves: &str, levels: u16, total_levels: u16) -> anyhow::Result<String> {
+    let reserves: f32 = reserves
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid reserves {}", reserves))?;
+    let share = reserves * f32::from(levels) / f32::from(total_levels);
+    Ok(((share * 100.0).round() / 100.0).to_string())
+}
+
+/// Fields of `create_building` that are rewritten when splitting it,
+/// of which every other one is copied to the split building unchanged
+const KNOWN_FIELDS: &[&str] = &[
+    "building",
+    "add_ownership",
+    "level",
+    "reserves",
+    "activate_production_methods",
+];
+
+/// Writes a script value, or a block as `write_block` does
+fn write_bv(out_file: &mut impl Write, bv: &BV, depth: usize) -> anyhow::Result<()> {
+    match bv {
+        BV::Value(value) => {
+            // The parser drops the quotes, so values the game would read differently
+            // if they were quoted are written as they must have been
+            let value = value.as_str();
+            if is_bare_value(value) {
+                write!(out_file, "{}", value)?;
+            } else {
+                write!(out_file, "\"{}\"", value)?;
+            }
+            Ok(())
+        }
+        BV::Block(block) => write_block(out_file, block, depth),
+    }
+}
+
+/// Writes a script block whose closing brace is indented by `depth` tabs and its lines by one more
+fn write_block(out_file: &mut impl Write, block: &Block, depth: usize) -> anyhow::Result<()> {
+    if let Some(tag) = &block.tag {
+        write!(out_file, "{} ", tag)?;
+    }
+    if block.iter_fields().next().is_none() {
+        write!(out_file, "{{")?;
+        for item in block.iter_items() {
+            if let BlockItem::Value(value) = item {
+                write!(out_file, " ")?;
+                write_bv(out_file, value, depth + 1)?;
+            }
+        }
+        write!(out_file, " }}")?;
+        return Ok(());
+    }
+
+    let indent = "\t".repeat(depth + 1);
+    writeln!(out_file, "{{")?;
+    for item in block.iter_items() {
+        write!(out_file, "{}", indent)?;
+        write_item(out_file, item, depth + 1)?;
+        writeln!(out_file)?;
+    }
+    write!(out_file, "{}}}", "\t".repeat(depth))?;
+    Ok(())
+}
+
+/// Writes an item of a block whose lines are indented by `depth` tabs
+pub fn write_item(out_file: &mut impl Write, item: &BlockItem, depth: usize) -> anyhow::Result<()> {
+    match item {
+        BlockItem::Field(Field(key, cmp, value)) => {
+            write!(out_file, "{} {} ", key, cmp)?;
+            write_bv(out_file, value, depth)
+        }
+        BlockItem::Value(value) => write_bv(out_file, value, depth),
+    }
+}
+
+/// Kinds of `add_ownership` entries, in the order their owners are read
+const OWNER_KINDS: &[&str] = &["building", "country", "company"];
+
+/// Reads the owners of a building in `state` from its `add_ownership` block.
+/// Owning buildings without a `region` are in the same state.
+fn parse_owners(add_ownership: &Block, state: &str) -> anyhow::Result<Vec<Owner>> {
+    if let Some((kind, _)) = add_ownership
+        .iter_assignments_and_definitions()
+        .find(|(kind, _)| !OWNER_KINDS.contains(&kind.as_str()))
+    {
+        anyhow::bail!("Unknown kind of owner {} in add_ownership", kind.as_str());
+    }
+
+    let mut owners = Vec::new();
+    for &kind in OWNER_KINDS {
+        for block in add_ownership.get_field_blocks(kind) {
+            let field = |name: &str| {
+                block
+                    .get_field_value(name)
+                    .map(ToString::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("{} owner is missing its {}", kind, name))
+            };
+            let levels = field("levels")?;
+            owners.push(Owner {
+                building: if kind == "building" {
+                    Some(field("type")?)
+                } else {
+                    None
+                },
+                company: if kind == "company" {
+                    Some(field("type")?)
+                } else {
+                    None
+                },
+                country: field("country")?,
+                levels: parse_levels(&levels)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid owner levels {}", levels))?,
+                region: if kind == "building" {
+                    Some(
+                        field("region").unwrap_or_else(|_| {
+                            state.strip_prefix("s:").unwrap_or(state).to_string()
+                        }),
+                    )
+                } else {
+                    None
+                },
+            });
+        }
+    }
+    Ok(owners)
+}
+
+/// Reads the owner of a building in the older form that only has a `level`, which is owned
+/// by the country of its `region_state`
+fn implicit_owner(building: &Block, region_state: &str) -> anyhow::Result<Vec<Owner>> {
+    let Some(level) = building.get_field_value("level") else {
+        anyhow::bail!("Building has neither add_ownership nor level");
+    };
+    let tag = region_state_tag(region_state);
+    Ok(vec![Owner {
+        building: None,
+        company: None,
+        country: format!("c:{}", tag),
+        levels: parse_levels(level.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid building level {}", level.as_str()))?,
+        region: None,
+    }])
+}
+
+/// Reads a number of levels, rounding those that aren't whole numbers
+fn parse_levels(levels: &str) -> Option<u16> {
+    levels.parse().ok().or_else(|| {
+        levels
+            .parse::<f32>()
+            .ok()
+            .filter(|levels| (0.0..=f32::from(u16::MAX)).contains(levels))
+            .map(|levels| levels.round() as u16)
+    })
+}
+
+/// The first levels of the owners of an entry, or its `level`, given by a script value
+/// such as `@levels` instead of a number
+fn scripted_levels<'a>(entry: &[&'a Block]) -> Option<&'a str> {
+    entry.iter().copied().find_map(|building| {
+        let mut levels = match building.get_field_block("add_ownership") {
+            Some(add_ownership) => OWNER_KINDS
+                .iter()
+                .flat_map(|&kind| add_ownership.get_field_blocks(kind))
+                .filter_map(|owner| owner.get_field_value("levels"))
+                .collect::<Vec<_>>(),
+            None => building.get_field_value("level").into_iter().collect(),
+        };
+        levels.retain(|levels| levels.as_str().starts_with('@'));
+        levels.first().map(|levels| levels.as_str())
+    })
+}
+
+/// Writes the `activate_production_methods` of a building, if it has any
+fn write_production_methods(
+    out_file: &mut impl Write,
+    production_methods: &[&str],
+) -> anyhow::Result<()> {
+    if production_methods.is_empty() {
+        return Ok(());
+    }
+
+    let quoted: Vec<String> = production_methods
+        .iter()
+        .map(|pm| format!("\"{}\"", pm))
+        .collect();
+    writeln!(
+        out_file,
+        "\t\t\t\tactivate_production_methods = {{ {} }}",
+        quoted.join(" ")
+    )?;
+    Ok(())
+}
+
+/// Writes an `add_ownership` entry giving `levels` levels to `owner`,
+/// as the building type `owner_type` for owners that are buildings
+fn write_owner(
+    out_file: &mut impl Write,
+    owner: &Owner,
+    owner_type: Option<&str>,
+    levels: u16,
+) -> anyhow::Result<()> {
+    if let Some(owner_type) = owner_type {
+        writeln!(out_file, "\t\t\t\t\tbuilding = {{")?;
+        writeln!(out_file, "\t\t\t\t\t\ttype = \"{}\"", owner_type)?;
+        writeln!(out_file, "\t\t\t\t\t\tcountry = \"{}\"", owner.country)?;
+        writeln!(out_file, "\t\t\t\t\t\tlevels = {}", levels)?;
+        let Some(region) = &owner.region else {
+            anyhow::bail!("Owning {} of {} has no region", owner_type, owner.country);
+        };
+        writeln!(out_file, "\t\t\t\t\t\tregion = \"{}\"", region)?;
+        writeln!(out_file, "\t\t\t\t\t}}")?;
+    } else if let Some(company) = &owner.company {
+        writeln!(out_file, "\t\t\t\t\tcompany = {{")?;
+        writeln!(out_file, "\t\t\t\t\t\ttype = \"{}\"", company)?;
+        writeln!(out_file, "\t\t\t\t\t\tcountry = \"{}\"", owner.country)?;
+        writeln!(out_file, "\t\t\t\t\t\tlevels = {}", levels)?;
+        writeln!(out_file, "\t\t\t\t\t}}")?;
+    } else {
+        writeln!(out_file, "\t\t\t\t\tcountry = {{")?;
+        writeln!(out_file, "\t\t\t\t\t\tcountry = \"{}\"", owner.country)?;
+        writeln!(out_file, "\t\t\t\t\t\tlevels = {}", levels)?;
+        writeln!(out_file, "\t\t\t\t\t}}")?;
+    }
+    Ok(())
+}
+
+/// Subtracted before rounding the levels of each target to the nearest level,
+/// so that targets only get a level for a share of at least 0.6 of one
+const ROUNDING_BIAS: f32 = 0.1;
+
+/// Returns how many of `total_levels` levels are moved to each of `targets`,
+/// with `keep` being the weight of the levels that stay the source building.
+/// Targets that would get fewer than `min_split_levels` levels get none.
+fn target_levels(
+    total_levels: u16,
+    keep: f32,
+    targets: &[SplitTarget],
+    min_split_levels: u16,
+    rounding: Rounding,
+) -> Vec<u16> {
+    let total_weight = keep + targets.iter().map(|target| target.weight).sum::<f32>();
+    let quotas = targets
+        .iter()
+        .map(|target| total_levels as f32 * target.weight / total_weight)
+        .collect::<Vec<_>>();
+    let mut levels = match rounding {
+        Rounding::LargestRemainder => {
+            let moved = (quotas.iter().sum::<f32>() - ROUNDING_BIAS).round() as u16;
+            largest_remainder(moved.min(total_levels), &quotas)
+        }
+        Rounding::Round => quotas
+            .iter()
+            .map(|quota| (quota - ROUNDING_BIAS).round() as u16)
+            .collect(),
+        Rounding::Floor | Rounding::Ceil => quotas
+            .iter()
+            .map(|&quota| round_levels(quota, rounding))
+            .collect(),
+    };
+    for target_levels in &mut levels {
+        if *target_levels < min_split_levels {
+            *target_levels = 0;
+        }
+    }
+
+    // Rounding several targets up can add up to more levels than there are
+    let mut sum = sum_levels(&levels);
+    for target_levels in levels.iter_mut().rev() {
+        let excess =
+            bounded_levels(sum.saturating_sub(u32::from(total_levels))).min(*target_levels);
+        *target_levels -= excess;
+        sum -= u32::from(excess);
+    }
+    levels
+}
+
+/// Takes levels from the targets with the most `levels` until at most `max_levels` are moved,
+/// and adds levels to the targets by their weights until at least `min_levels` are
+fn limit_moved_levels(
+    levels: &mut [u16],
+    targets: &[SplitTarget],
+    min_levels: Option<u16>,
+    max_levels: Option<u16>,
+) {
+    let mut moved = sum_levels(levels);
+    while let Some(max_levels) = max_levels
+        && moved > u32::from(max_levels)
+    {
+        let Some(largest) = (0..levels.len()).rev().max_by_key(|&target| levels[target]) else {
+            break;
+        };
+        levels[largest] -= 1;
+        moved -= 1;
+    }
+    while let Some(min_levels) = min_levels
+        && moved < u32::from(min_levels)
+    {
+        // Each level goes to the target that is furthest below its weight's share
+        let Some(neediest) = (0..levels.len()).rev().max_by(|&a, &b| {
+            let need = |target: usize| targets[target].weight / f32::from(levels[target] + 1);
+            need(a).total_cmp(&need(b))
+        }) else {
+            break;
+        };
+        levels[neediest] += 1;
+        moved += 1;
+    }
+}
+
+/// Distributes `levels` over owners proportionally to their `weights`, which are also
+/// the most levels each owner can give, by largest remainder: every owner gives its
+/// share rounded down, and the levels left over are given one each by the owners with the
+/// largest remainders. Ties go to earlier owners, so owners should be sorted by descending
+/// weight.
+fn apportion(levels: u16, weights: &[u16]) -> Vec<u16> {
+    let total = weights.iter().map(|&weight| u32::from(weight)).sum::<u32>();
+    if total == 0 {
+        return vec![0; weights.len()];
+    }
+    let levels = u32::from(levels).min(total);
+
+    // Shares are levels * weight / total, kept as whole levels and remainders to stay exact
+    let mut shares = weights
+        .iter()
+        .map(|&weight| (levels * u32::from(weight) / total) as u16)
+        .collect::<Vec<_>>();
+    let mut by_remainder = (0..weights.len()).collect::<Vec<_>>();
+    by_remainder
+        .sort_by_key(|&owner| std::cmp::Reverse(levels * u32::from(weights[owner]) % total));
+
+    let leftover = levels as usize
+        - shares
+            .iter()
+            .map(|&share| usize::from(share))
+            .sum::<usize>();
+    for owner in by_remainder.into_iter().take(leftover) {
+        shares[owner] += 1;
+    }
+    shares
+}
+
+/// Moves the `shares` of owners giving fewer than `min_levels` levels to the owners giving
+/// the most, one level at a time and as far as their `weights` allow. Owners are handled
+/// from the smallest share up, so that smaller shares gather in the larger ones, and keep
+/// the part of their share that no other owner can take.
+fn enforce_owner_minimum(shares: &mut [u16], weights: &[u16], min_levels: u16) {
+    let mut below = (0..shares.len())
+        .filter(|&owner| shares[owner] > 0 && shares[owner] < min_levels)
+        .collect::<Vec<_>>();
+    below.sort_by_key(|&owner| shares[owner]);
+    for owner in below {
+        // Earlier owners may have given their levels to this one
+        if shares[owner] >= min_levels {
+            continue;
+        }
+
+        let mut freed = std::mem::take(&mut shares[owner]);
+        while freed > 0 {
+            let Some(taker) = (0..shares.len())
+                .filter(|&taker| {
+                    taker != owner && shares[taker] > 0 && shares[taker] < weights[taker]
+                })
+                .max_by_key(|&taker| (shares[taker], std::cmp::Reverse(taker)))
+            else {
+                break;
+            };
+            shares[taker] += 1;
+            freed -= 1;
+        }
+        shares[owner] = freed;
+    }
+}
+
+/// Gives `levels` levels one at a time to random owners, each picked with a chance
+/// proportional to the levels it has left to give of its `weights`
+fn random_apportion(levels: u16, weights: &[u16], rng: &mut SeededRng) -> Vec<u16> {
+    let mut left = weights.to_vec();
+    let mut shares = vec![0; weights.len()];
+    for _ in 0..levels.min(weights.iter().sum()) {
+        let total = left.iter().map(|&left| u64::from(left)).sum::<u64>();
+        let mut pick = rng.next_u64() % total;
+        for (owner, left) in left.iter_mut().enumerate() {
+            if pick < u64::from(*left) {
+                *left -= 1;
+                shares[owner] += 1;
+                break;
+            }
+            pick -= u64::from(*left);
+        }
+    }
+    shares
+}
+
+/// SplitMix64 generator for randomized owner assignment and deposit placement. It is seeded
+/// separately for every building or deposit by its keys, so a building's owners only depend
+/// on the seed and not on other buildings.
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64, keys: &[&str]) -> Self {
+        // FNV-1a over the keys, mixed into the seed
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        for byte in keys.iter().flat_map(|key| key.bytes().chain([0])) {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        Self(seed ^ hash)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Total of `levels`, which can be more than a building can have
+fn sum_levels(levels: &[u16]) -> u32 {
+    levels.iter().map(|&levels| u32::from(levels)).sum()
+}
+
+/// `levels` that are known to be at most the levels of a building
+fn bounded_levels(levels: u32) -> u16 {
+    u16::try_from(levels).unwrap_or(u16::MAX)
+}
+
+/// Total levels of a building owned by its `owners`, which fails if the levels are more than
+/// a building can have
+fn owned_total(
+    owners: &[Owner],
+    building_type: &str,
+    state: &str,
+    region_state: &str,
+) -> anyhow::Result<u16> {
+    let levels = owners.iter().map(|owner| owner.levels).collect::<Vec<_>>();
+    let total = sum_levels(&levels);
+    u16::try_from(total).map_err(|_| {
+        anyhow::anyhow!(
+            "{} in {} {} has {} levels, more than a building can have",
+            building_type,
+            state,
+            region_state,
+            total
+        )
+    })
+}
+
+fn round_levels(levels: f32, rounding: Rounding) -> u16 {
+    match rounding {
+        Rounding::Floor => levels.floor() as u16,
+        Rounding::Ceil => levels.ceil() as u16,
+        Rounding::Round | Rounding::LargestRemainder => levels.round() as u16,
+    }
+}
+
+/// Distributes `total` levels by rounding each of `quotas` down, then giving the levels
+/// left over one each to the quotas with the largest fractional parts, earlier ones first
+fn largest_remainder(total: u16, quotas: &[f32]) -> Vec<u16> {
+    let mut levels = quotas
+        .iter()
+        .map(|quota| quota.floor() as u16)
+        .collect::<Vec<_>>();
+    let mut by_remainder = (0..quotas.len()).collect::<Vec<_>>();
+    by_remainder.sort_by(|&a, &b| {
+        (quotas[b] - quotas[b].floor()).total_cmp(&(quotas[a] - quotas[a].floor()))
+    });
+
+    let leftover = u32::from(total).saturating_sub(sum_levels(&levels));
+    for i in by_remainder.into_iter().cycle().take(leftover as usize) {
+        levels[i] += 1;
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(shares: &[u16]) -> u16 {
+        shares.iter().sum()
+    }
+
+    fn parse(contents: &str) -> Block {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("buildings.txt");
+        std::fs::write(&path, contents).unwrap();
+        read_pdx_file(&path).unwrap()
+    }
+
+    fn written(block: &Block) -> String {
+        let mut out = Vec::new();
+        write_block(&mut out, block, 0).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn write_block_round_trips_unknown_fields() {
+        let source = parse(
+            "create_building = {
+                building = \"building_textile_mill\"
+                color = hsv { 0.5 0.2 0.1 }
+                label = \"two words\"
+                sizes = { 1 2 }
+            }",
+        );
+        let building = source.get_field_block("create_building").unwrap();
+        let text = written(building);
+        assert!(text.contains("color = hsv { 0.5 0.2 0.1 }"), "{}", text);
+        assert!(text.contains("label = \"two words\""), "{}", text);
+        assert!(text.contains("sizes = { 1 2 }"), "{}", text);
+
+        let reparsed = parse(&format!("create_building = {}", text));
+        let reparsed = reparsed.get_field_block("create_building").unwrap();
+        assert_eq!(written(reparsed), text);
+    }
+
+    #[test]
+    fn apportion_single_owner() {
+        assert_eq!(apportion(3, &[10]), vec![3]);
+        assert_eq!(apportion(10, &[10]), vec![10]);
+    }
+
+    #[test]
+    fn apportion_many_tiny_owners() {
+        let weights = [1; 7];
+        let shares = apportion(3, &weights);
+        assert_eq!(total(&shares), 3);
+        assert!(shares.iter().all(|&share| share <= 1));
+        assert_eq!(shares, vec![1, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn apportion_zero_weights() {
+        assert_eq!(apportion(5, &[0, 0, 0]), vec![0, 0, 0]);
+        assert_eq!(apportion(5, &[]), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn apportion_more_levels_than_weights() {
+        let weights = [4, 2, 1];
+        let shares = apportion(20, &weights);
+        assert_eq!(total(&shares), 7);
+        assert_eq!(shares, weights.to_vec());
+    }
+
+    #[test]
+    fn apportion_ties_are_stable() {
+        let weights = [3, 3, 3, 3];
+        let shares = apportion(6, &weights);
+        assert_eq!(total(&shares), 6);
+        assert_eq!(shares, vec![2, 2, 1, 1]);
+        for _ in 0..10 {
+            assert_eq!(apportion(6, &weights), shares);
+        }
+    }
+
+    #[test]
+    fn apportion_sums_to_levels() {
+        let weights = [17, 9, 5, 3, 1, 1];
+        for levels in 0..=40 {
+            let shares = apportion(levels, &weights);
+            assert_eq!(total(&shares), levels.min(total(&weights)));
+            assert!(
+                shares
+                    .iter()
+                    .zip(&weights)
+                    .all(|(share, weight)| share <= weight)
+            );
+        }
+    }
+
+    #[test]
+    fn enforce_owner_minimum_gathers_tiny_shares() {
+        let weights = [10, 5, 1, 1];
+        let mut shares = apportion(8, &weights);
+        assert_eq!(shares, vec![5, 2, 1, 0]);
+        enforce_owner_minimum(&mut shares, &weights, 2);
+        assert_eq!(shares, vec![6, 2, 0, 0]);
+    }
+
+    #[test]
+    fn enforce_owner_minimum_keeps_what_no_one_can_take() {
+        let weights = [2, 1];
+        let mut shares = vec![2, 1];
+        enforce_owner_minimum(&mut shares, &weights, 2);
+        assert_eq!(shares, vec![2, 1]);
+    }
+
+    #[test]
+    fn largest_remainder_sums_to_total() {
+        let levels = largest_remainder(10, &[3.3, 3.3, 3.4]);
+        assert_eq!(levels, vec![3, 3, 4]);
+        assert_eq!(total(&largest_remainder(7, &[2.5, 2.5, 2.0])), 7);
+    }
+
+    #[test]
+    fn largest_remainder_ties_are_stable() {
+        let levels = largest_remainder(2, &[0.5, 0.5, 0.5]);
+        assert_eq!(levels, vec![1, 1, 0]);
+        for _ in 0..10 {
+            assert_eq!(largest_remainder(2, &[0.5, 0.5, 0.5]), levels);
+        }
+    }
+
+    #[test]
+    fn seeded_rng_is_deterministic() {
+        let mut a = SeededRng::new(42, &["STATE_TEXAS", "building_wheat_farm"]);
+        let mut b = SeededRng::new(42, &["STATE_TEXAS", "building_wheat_farm"]);
+        let mut c = SeededRng::new(42, &["STATE_TEXAS", "building_rye_farm"]);
+        let a = (0..8).map(|_| a.next_u64()).collect::<Vec<_>>();
+        assert_eq!(a, (0..8).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(a, (0..8).map(|_| c.next_u64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn random_apportion_sums_to_levels() {
+        let weights = [5, 3, 1];
+        let mut rng = SeededRng::new(7, &["STATE_OHIO"]);
+        let shares = random_apportion(6, &weights, &mut rng);
+        assert_eq!(total(&shares), 6);
+        assert!(
+            shares
+                .iter()
+                .zip(&weights)
+                .all(|(share, weight)| share <= weight)
+        );
+        let mut rng = SeededRng::new(7, &["STATE_OHIO"]);
+        assert_eq!(random_apportion(6, &weights, &mut rng), shares);
+        assert_eq!(total(&random_apportion(20, &weights, &mut rng)), 9);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::cloned::{Cloning, Definitions, PlannedClone, run_cloned};
+use crate::config::{BuildingDefinition, Config};
+use crate::output::Output;
+use crate::report::Report;
+use std::path::PathBuf;
+use tiger_lib::Token;
+use tiger_lib::block::Eq::Single;
+use tiger_lib::block::{BV, Block, BlockItem, Comparator, Field};
+
+/// Generates a file of the definitions of the modded buildings for each of `in_paths` whose
+/// buildings they are cloned from, for every modded building the input files don't define.
+/// Output files are only written if none of the input files failed.
+pub fn run_buildings_def(in_paths: &[PathBuf], config: &Config, output: &mut Output) -> Report {
+    let cloning = Cloning {
+        kind: "Building",
+        done: "buildings defined",
+        apply: apply_definition,
+        generated: |file_report| &mut file_report.buildings_defined,
+    };
+    run_cloned(
+        in_paths,
+        config,
+        output,
+        |defined| plan_definitions(defined, config),
+        &cloning,
+    )
+}
+
+/// Returns every modded building that the input files don't define, with the one of the
+/// `defined` buildings its definition is cloned from and the fields replacing those of it
+fn plan_definitions<'a>(
+    defined: &Definitions,
+    config: &'a Config,
+) -> anyhow::Result<Vec<PlannedClone<'a, BuildingDefinition>>> {
+    let mut planned = Vec::new();
+    for (building, template) in config.building_templates() {
+        if defined.contains_key(building) {
+            continue;
+        }
+        let Some(template) = template else {
+            anyhow::bail!(
+                "Building {} has no `template` to define it from, and isn't split off from any building",
+                building
+            );
+        };
+        planned.push((
+            building,
+            template,
+            config.building_definitions.get(building),
+        ));
+    }
+    Ok(planned)
+}
+
+/// Replaces the fields of the building `block` that `definition` gives
+fn apply_definition(block: &mut Block, definition: &BuildingDefinition) {
+    let loc = block.loc;
+    let value = |value: &str| BV::Value(Token::new(value, loc));
+    if let Some(building_group) = &definition.building_group {
+        set_field(block, "building_group", value(building_group));
+    }
+    if let Some(groups) = &definition.production_method_groups {
+        let mut list = Block::new(loc);
+        for group in groups {
+            list.add_value(Token::new(group, loc));
+        }
+        set_field(block, "production_method_groups", BV::Block(list));
+    }
+    if let Some(required_construction) = &definition.required_construction {
+        set_field(block, "required_construction", value(required_construction));
+    }
+    if let Some(texture) = &definition.texture {
+        set_field(block, "texture", value(texture));
+    }
+}
+
+/// Sets the field `key` of `block` to `value`, in place of its first field of the key,
+/// or after its other fields if it has none
+fn set_field(block: &mut Block, key: &str, value: BV) {
+    let mut value = Some(value);
+    let items = block.drain().collect::<Vec<_>>();
+    for item in items {
+        match item {
+            BlockItem::Field(Field(field_key, cmp, _)) if field_key.is(key) => {
+                if let Some(value) = value.take() {
+                    block.add_key_bv(field_key, cmp, value);
+                }
+            }
+            item => block.add_item(item),
+        }
+    }
+    if let Some(value) = value {
+        block.add_key_bv(
+            Token::new(key, block.loc),
+            Comparator::Equals(Single),
+            value,
+        );
+    }
+}
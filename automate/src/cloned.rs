@@ -0,0 +1,155 @@
+use crate::buildings::write_item;
+use crate::config::Config;
+use crate::game_data::read_pdx_file;
+use crate::output::Output;
+use crate::report::{FileReport, Report};
+use crate::states::SourceFormat;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tiger_lib::Token;
+use tiger_lib::block::Eq::Single;
+use tiger_lib::block::{BV, Block, BlockItem, Comparator, Field};
+
+/// Definitions of the input files by their key, with the index of the file defining each
+pub type Definitions = HashMap<String, (usize, Block)>;
+
+/// Key of a definition to generate, with the key of the definition it is cloned from
+/// and the rule changing it
+pub type PlannedClone<'a, R> = (&'a str, &'a str, Option<&'a R>);
+
+/// Definitions to generate from each input file, by the index of the file,
+/// with the key of each, the definition it is cloned from and its rule
+type PlannedClones<'a, R> = HashMap<usize, Vec<(&'a str, Block, Option<&'a R>)>>;
+
+/// How a command generates definitions cloned from those of its input files
+pub struct Cloning<A> {
+    /// What the definitions are, such as `Production method`, for errors
+    pub kind: &'static str,
+    /// What was done to the generated definitions, such as `production methods generated`,
+    /// for the progress bar and the summaries of the written files
+    pub done: &'static str,
+    /// Changes a cloned definition by its rule
+    pub apply: A,
+    /// The report's list of generated definitions
+    pub generated: fn(&mut FileReport) -> &mut Vec<String>,
+}
+
+/// Generates a file of the definitions that `plan` gives, given those of the input files, for
+/// each of `in_paths` whose definitions they are cloned from. Output files are only written
+/// if none of the input files failed.
+pub fn run_cloned<'a, R: 'a>(
+    in_paths: &[PathBuf],
+    config: &Config,
+    output: &mut Output,
+    plan: impl FnOnce(&Definitions) -> anyhow::Result<Vec<PlannedClone<'a, R>>>,
+    cloning: &Cloning<impl Fn(&mut Block, &R)>,
+) -> Report {
+    let planned = plan_clones(in_paths, cloning.kind, plan);
+    let progress = output.begin_run(in_paths.len());
+    let mut report = Report::default();
+    let mut total_generated = 0;
+    for (index, in_path) in in_paths.iter().enumerate() {
+        let mut file_report = FileReport::new(in_path.clone());
+        let result = match &planned {
+            Ok(planned) => generate_cloned_file(
+                in_path,
+                planned.get(&index).map(Vec::as_slice).unwrap_or_default(),
+                cloning,
+                config,
+                output,
+                &mut file_report,
+            ),
+            Err(err) => Err(anyhow::anyhow!("{:#}", err)),
+        };
+        if let Err(err) = result {
+            file_report.error = Some(format!("{:#}", err));
+        }
+
+        total_generated += (cloning.generated)(&mut file_report).len();
+        report.files.push(file_report);
+        progress.set_message(format!("{} {}", total_generated, cloning.done));
+        progress.inc(1);
+    }
+    report.commit(output);
+    progress.finish();
+
+    report
+}
+
+/// Finds the definitions of `in_paths` that each definition `plan` gives is cloned from,
+/// by the input file they are defined in
+fn plan_clones<'a, R>(
+    in_paths: &[PathBuf],
+    kind: &str,
+    plan: impl FnOnce(&Definitions) -> anyhow::Result<Vec<PlannedClone<'a, R>>>,
+) -> anyhow::Result<PlannedClones<'a, R>> {
+    let mut defined = HashMap::new();
+    for (index, in_path) in in_paths.iter().enumerate() {
+        for (key, block) in read_pdx_file(in_path)?.iter_definitions() {
+            defined.insert(key.to_string(), (index, block.clone()));
+        }
+    }
+
+    let mut planned: PlannedClones<R> = HashMap::new();
+    for (key, source, rule) in plan(&defined)? {
+        let Some((index, block)) = defined.get(source) else {
+            anyhow::bail!(
+                "{} {} is cloned from {}, which the input files don't define",
+                kind,
+                key,
+                source
+            );
+        };
+        planned
+            .entry(*index)
+            .or_default()
+            .push((key, block.clone(), rule));
+    }
+    Ok(planned)
+}
+
+/// Generates the file of the `planned` definitions cloned from those of `in_path`, changed
+/// by `cloning`, and hands it to `output`, unless there are none
+fn generate_cloned_file<R>(
+    in_path: &Path,
+    planned: &[(&str, Block, Option<&R>)],
+    cloning: &Cloning<impl Fn(&mut Block, &R)>,
+    config: &Config,
+    output: &mut Output,
+    file_report: &mut FileReport,
+) -> anyhow::Result<()> {
+    if planned.is_empty() {
+        return Ok(());
+    }
+
+    let mut buffer = Vec::new();
+    for (key, source, rule) in planned {
+        let mut block = source.clone();
+        if let Some(rule) = rule {
+            (cloning.apply)(&mut block, rule);
+        }
+        let item = BlockItem::Field(Field(
+            Token::new(key, block.loc),
+            Comparator::Equals(Single),
+            BV::Block(block),
+        ));
+        write_item(&mut buffer, &item, 0)?;
+        writeln!(buffer)?;
+        (cloning.generated)(file_report).push(key.to_string());
+    }
+    let format = SourceFormat::read(&std::fs::read(in_path)?, config.line_endings);
+    let out_name = output.file_name(in_path);
+    output.write(
+        &out_name,
+        format.apply(&String::from_utf8_lossy(&buffer)).as_bytes(),
+        &format!(
+            "{} {}",
+            (cloning.generated)(file_report).len(),
+            cloning.done
+        ),
+    )?;
+    file_report.output = Some(out_name);
+
+    Ok(())
+}
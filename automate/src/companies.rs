@@ -0,0 +1,308 @@
+use crate::BOM_CHAR;
+use crate::buildings::cross_file_changes;
+use crate::config::Config;
+use crate::game_data::{definition_files, game_dir_of, read_pdx_file};
+use crate::output::Output;
+use crate::pipeline::BUILDINGS_GAME_DIR;
+use crate::report::{FileReport, Report};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Fields of a company type with the buildings it can own
+const BUILDING_TYPES_FIELDS: &[&str] = &["building_types", "extension_building_types"];
+
+/// Generates the modded company types files for each of `in_paths`, which add the modded
+/// buildings that companies own levels of after splitting the game's buildings to their
+/// `building_types`. Output files are only written if none of the input files failed.
+pub fn run_companies(in_paths: &[PathBuf], config: &Config, output: &mut Output) -> Report {
+    let company_buildings = company_buildings(in_paths, config);
+    let progress = output.begin_run(in_paths.len());
+    let mut report = Report::default();
+    let mut total_companies_modified = 0;
+    for in_path in in_paths {
+        let mut file_report = FileReport::new(in_path.clone());
+        let result = match &company_buildings {
+            Ok(company_buildings) => {
+                generate_companies_file(in_path, company_buildings, output, &mut file_report)
+            }
+            Err(err) => Err(anyhow::anyhow!("{:#}", err)),
+        };
+        if let Err(err) = result {
+            file_report.error = Some(format!("{:#}", err));
+        }
+
+        total_companies_modified += file_report.companies_modified.len();
+        report.files.push(file_report);
+        progress.set_message(format!(
+            "{} company types modified",
+            total_companies_modified
+        ));
+        progress.inc(1);
+    }
+    report.commit(output);
+    progress.finish();
+
+    report
+}
+
+/// Splits the buildings of the game directory that `in_paths` were read from to find
+/// the modded buildings each company type owns levels of
+fn company_buildings(
+    in_paths: &[PathBuf],
+    config: &Config,
+) -> anyhow::Result<BTreeMap<String, BTreeSet<String>>> {
+    let Some(in_path) = in_paths.first() else {
+        return Ok(BTreeMap::new());
+    };
+    let Some(game_dir) = game_dir_of(in_path) else {
+        anyhow::bail!(
+            "Company types must be read from the game directory, whose buildings are split \
+             to find the buildings companies own"
+        );
+    };
+    let buildings_files = definition_files(&game_dir.join(BUILDINGS_GAME_DIR))?;
+    Ok(cross_file_changes(&buildings_files, config).company_buildings)
+}
+
+/// Generates the modded company types file for `in_path` and hands it to `output`,
+/// unless none of its company types own modded buildings
+fn generate_companies_file(
+    in_path: &Path,
+    company_buildings: &BTreeMap<String, BTreeSet<String>>,
+    output: &mut Output,
+    file_report: &mut FileReport,
+) -> anyhow::Result<()> {
+    let out_name = output.file_name(in_path);
+    let mut buffer = Vec::new();
+    if create_modded_companies_file(in_path, company_buildings, &mut buffer, file_report)? {
+        output.write(
+            &out_name,
+            &buffer,
+            &format!(
+                "{} company types modified",
+                file_report.companies_modified.len()
+            ),
+        )?;
+        file_report.output = Some(out_name);
+    }
+
+    Ok(())
+}
+
+/// Writes the company types file at `in_path` to `out_file` with the `company_buildings`
+/// each company type is missing added to its `building_types`, recording the company types
+/// that were modified in `report`.
+/// Returns `false` if no company type of the file is missing any and it should be skipped.
+pub fn create_modded_companies_file(
+    in_path: &Path,
+    company_buildings: &BTreeMap<String, BTreeSet<String>>,
+    out_file: &mut impl Write,
+    report: &mut FileReport,
+) -> anyhow::Result<bool> {
+    let contents = read_pdx_file(in_path)?;
+    let mut missing = HashMap::new();
+    for (company, company_block) in contents.iter_definitions() {
+        let Some(buildings) = company_buildings.get(company.as_str()) else {
+            continue;
+        };
+        let owned = BUILDING_TYPES_FIELDS
+            .iter()
+            .filter_map(|field| company_block.get_field_block(field))
+            .flat_map(|block| block.iter_values())
+            .map(|building| building.as_str())
+            .collect::<Vec<_>>();
+        let added = buildings
+            .iter()
+            .map(String::as_str)
+            .filter(|building| !owned.contains(building))
+            .collect::<Vec<_>>();
+        if added.is_empty() {
+            continue;
+        }
+        if company_block.get_field_block("building_types").is_none() {
+            report.warnings.push(format!(
+                "{} owns levels of {} but has no `building_types` to add them to",
+                company,
+                added.join(", ")
+            ));
+            continue;
+        }
+        missing.insert(company.to_string(), added);
+    }
+    if missing.is_empty() {
+        return Ok(false);
+    }
+
+    let in_data = std::fs::read_to_string(in_path)?;
+    let (modded, modified) = add_building_types(in_data.trim_start_matches(BOM_CHAR), &missing);
+    write!(out_file, "{}{}", BOM_CHAR, modded)?;
+    report.companies_modified.extend(modified);
+
+    Ok(true)
+}
+
+/// Adds the `missing` buildings of each company type to its `building_types` in the company
+/// types file `contents`, after the buildings already in it, returning the new contents and
+/// the company types that were modified
+fn add_building_types(
+    contents: &str,
+    missing: &HashMap<String, Vec<&str>>,
+) -> (String, Vec<String>) {
+    let newline = if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut modded = String::with_capacity(contents.len());
+    let mut modified = Vec::new();
+    let mut written = 0;
+    for (company, open, close) in building_types_lists(contents) {
+        let Some(added) = missing.get(&company) else {
+            continue;
+        };
+        let close_line = contents[..close]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        if contents[close_line..close].trim().is_empty() && close_line > open {
+            // A list over several lines gets a line for each building before its closing brace
+            let indent = &contents[close_line..close];
+            modded.push_str(&contents[written..close_line]);
+            for building in added {
+                modded.push_str(&format!("{}\t{}{}", indent, building, newline));
+            }
+            written = close_line;
+        } else {
+            // Otherwise the buildings go on the line of the closing brace, before it
+            let end = contents[..close].trim_end().len();
+            modded.push_str(&contents[written..end]);
+            modded.push_str(&format!(" {} ", added.join(" ")));
+            written = close;
+        }
+        modified.push(company);
+    }
+    modded.push_str(&contents[written..]);
+    (modded, modified)
+}
+
+/// Company types of the company types file `contents` with the positions of the opening
+/// and closing braces of their `building_types`, skipping comments and quoted strings
+fn building_types_lists(contents: &str) -> Vec<(String, usize, usize)> {
+    let mut lists = Vec::new();
+    // Keys of the blocks that the current position is in, with where they were opened
+    let mut blocks: Vec<(&str, usize)> = Vec::new();
+    let mut key = "";
+    let mut word_start = None;
+    let mut chars = contents.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let ends_word = c.is_whitespace() || ['=', '{', '}', '#', '"'].contains(&c);
+        if ends_word && let Some(start) = word_start.take() {
+            key = &contents[start..index];
+        }
+        match c {
+            '#' => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+            '"' => {
+                let start = index + 1;
+                while chars.next_if(|&(_, c)| c != '"').is_some() {}
+                let end = chars.next().map_or(contents.len(), |(end, _)| end);
+                key = &contents[start..end];
+            }
+            '=' => (),
+            '{' => blocks.push((std::mem::take(&mut key), index)),
+            '}' => {
+                if let Some((block_key, open)) = blocks.pop()
+                    && block_key == "building_types"
+                    && let [(company, _)] = blocks.as_slice()
+                {
+                    lists.push((company.to_string(), open, index));
+                }
+            }
+            _ if ends_word => (),
+            _ => {
+                if word_start.is_none() {
+                    word_start = Some(index);
+                }
+            }
+        }
+    }
+    lists
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn added(contents: &str) -> (String, Vec<String>) {
+        let missing = HashMap::from([(
+            "company_basic_food".to_string(),
+            vec!["building_distillery", "building_cannery"],
+        )]);
+        add_building_types(contents, &missing)
+    }
+
+    #[test]
+    fn single_line_list() {
+        let (modded, modified) =
+            added("company_basic_food = {\n\tbuilding_types = { building_food_industry }\n}\n");
+        assert_eq!(
+            modded,
+            "company_basic_food = {\n\tbuilding_types = { building_food_industry building_distillery building_cannery }\n}\n"
+        );
+        assert_eq!(modified, vec!["company_basic_food"]);
+    }
+
+    #[test]
+    fn multi_line_list() {
+        let (modded, _) = added(
+            "company_basic_food = {\n\tbuilding_types = {\n\t\tbuilding_food_industry\n\t}\n}\n",
+        );
+        assert_eq!(
+            modded,
+            "company_basic_food = {\n\tbuilding_types = {\n\t\tbuilding_food_industry\n\t\tbuilding_distillery\n\t\tbuilding_cannery\n\t}\n}\n"
+        );
+
+        // The first building can be on the line of the opening brace,
+        // and the last on the line of the closing one
+        let (modded, _) = added(
+            "company_basic_food = {\r\n\tbuilding_types = { building_food_industry\r\n\t}\r\n}\r\n",
+        );
+        assert_eq!(
+            modded,
+            "company_basic_food = {\r\n\tbuilding_types = { building_food_industry\r\n\t\tbuilding_distillery\r\n\t\tbuilding_cannery\r\n\t}\r\n}\r\n"
+        );
+        let (modded, _) = added(
+            "company_basic_food = {\n\tbuilding_types = {\n\t\tbuilding_food_industry }\n}\n",
+        );
+        assert_eq!(
+            modded,
+            "company_basic_food = {\n\tbuilding_types = {\n\t\tbuilding_food_industry building_distillery building_cannery }\n}\n"
+        );
+    }
+
+    #[test]
+    fn only_the_company_types_list() {
+        let contents = "company_basic_food\n= {\n\ticon = \"gfx/a { b.dds\"\n\tbuilding_types = { # a } comment\n\t\tbuilding_food_industry # {\n\t}\n\textension_building_types = { building_port }\n}\ncompany_basic_textiles = {\n\tbuilding_types = { building_textile_mill }\n}\n";
+        let (modded, modified) = added(contents);
+        assert_eq!(
+            modded,
+            contents.replace(
+                "building_food_industry # {\n",
+                "building_food_industry # {\n\t\tbuilding_distillery\n\t\tbuilding_cannery\n"
+            )
+        );
+        assert_eq!(modified, vec!["company_basic_food"]);
+    }
+
+    #[test]
+    fn commented_out_list() {
+        let contents = "company_basic_food = {\n\t# building_types = { building_port }\n\tbuilding_types = { building_food_industry }\n}\n";
+        let (modded, _) = added(contents);
+        assert_eq!(
+            modded,
+            contents.replace(
+                "{ building_food_industry }",
+                "{ building_food_industry building_distillery building_cannery }"
+            )
+        );
+    }
+}
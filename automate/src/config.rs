@@ -0,0 +1,2412 @@
+use crate::game_data::{GameData, building_definitions};
+use crate::input::build_glob_set;
+use crate::script::SplitScript;
+use clap::{Args, Subcommand, ValueEnum};
+use globset::GlobSet;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Ratios config file in the working directory, used if no other file is given
+pub const RATIOS_FILE: &str = "ratios.toml";
+
+/// Names of the generators that output layouts can be given for
+const GENERATORS: &[&str] = &[
+    "buildings",
+    "states",
+    "companies",
+    "state-history",
+    "production-methods",
+    "buildings-def",
+];
+
+/// The ratios used when there is no ratios config file
+const DEFAULT_RATIOS: &str = include_str!("../ratios.toml");
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Config file with the ratios buildings are split by and the arable resources
+    /// added to states, in TOML or JSON.
+    /// Defaults to `ratios.toml` in the working directory if it exists,
+    /// and otherwise to the built-in ratios.
+    #[arg(long, value_name = "PATH")]
+    pub ratios: Option<PathBuf>,
+
+    /// How the split levels of each target are rounded to whole levels,
+    /// overriding the `rounding` of the ratios config
+    #[arg(long, value_enum)]
+    pub rounding: Option<Rounding>,
+
+    /// Game version whose ratios are used for the buildings it lists,
+    /// overriding the `game_version` of the ratios config
+    #[arg(long, value_name = "VERSION")]
+    pub game_version: Option<String>,
+
+    /// Seed for randomly picking which owners give up the split levels, instead of taking them
+    /// from every owner by its share. Overrides the `owner_seed` of the ratios config.
+    #[arg(long, value_name = "SEED")]
+    pub owner_seed: Option<u64>,
+
+    /// Seed for randomly placing the `random_deposits` of the ratios config,
+    /// overriding its `deposit_seed`
+    #[arg(long, value_name = "SEED")]
+    pub deposit_seed: Option<u64>,
+
+    /// Whether the buildings files patch the game's buildings or replace its files,
+    /// overriding the `buildings_mode` of the ratios config
+    #[arg(long, value_enum)]
+    pub buildings_mode: Option<BuildingsMode>,
+
+    /// Whether the states files replace the game's files or inject the changed keys
+    /// into its states, overriding the `states_mode` of the ratios config
+    #[arg(long, value_enum)]
+    pub states_mode: Option<StatesMode>,
+
+    /// Only write the states and region states with changed buildings to the buildings files,
+    /// overriding the `sparse` of the ratios config. `--sparse=false` writes all of them.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+    )]
+    pub sparse: Option<bool>,
+
+    /// Leave buildings entries that can't be split as they were and keep going,
+    /// instead of failing their file, overriding the `keep_going` of the ratios config.
+    /// `--keep-going=false` fails the file.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL",
+    )]
+    pub keep_going: Option<bool>,
+
+    /// Profile of the ratios config selecting which of its rules are used,
+    /// overriding the `profile` of the ratios config
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+}
+
+impl ConfigArgs {
+    /// Path of the ratios config file that is loaded, unless the built-in ratios are
+    pub fn path(&self) -> Option<PathBuf> {
+        match &self.ratios {
+            Some(path) => Some(path.clone()),
+            None => Path::new(RATIOS_FILE)
+                .is_file()
+                .then(|| PathBuf::from(RATIOS_FILE)),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Prints a JSON Schema of the ratios config file, for editors to validate and complete it
+    Schema,
+}
+
+impl ConfigCommand {
+    pub fn run(&self) -> anyhow::Result<()> {
+        match self {
+            ConfigCommand::Schema => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&schemars::schema_for!(RatiosFile))?
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How fractional levels moved to each target are rounded. The levels of a target are always
+/// taken from the owners by largest remainder, or randomly with an owner seed.
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    Floor,
+    /// Round to the nearest level, slightly favoring rounding down
+    #[default]
+    Round,
+    Ceil,
+    /// Round down, then give the remaining levels to the largest fractions
+    LargestRemainder,
+}
+
+/// How the generated buildings files change the game's buildings
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildingsMode {
+    /// Remove the split buildings and create them again, in files loaded after the game's
+    #[default]
+    Patch,
+    /// Replace the game's files with ones where split buildings are edited in place
+    Replace,
+}
+
+impl BuildingsMode {
+    /// Prefix of generated file names when no other prefix is given,
+    /// which replacements of the game's files can't have
+    pub fn default_prefix(self) -> &'static str {
+        match self {
+            BuildingsMode::Patch => "ir_",
+            BuildingsMode::Replace => "",
+        }
+    }
+}
+
+/// How the generated states files change the game's states
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatesMode {
+    /// Replace the game's files with ones where modified states are edited in place
+    #[default]
+    Replace,
+    /// Inject only the changed keys of the modified states, in files loaded after the game's
+    Inject,
+}
+
+impl StatesMode {
+    /// Prefix of generated file names when no other prefix is given,
+    /// which replacements of the game's files can't have
+    pub fn default_prefix(self) -> &'static str {
+        match self {
+            StatesMode::Replace => "",
+            StatesMode::Inject => "ir_",
+        }
+    }
+}
+
+/// Which generated states file each modded state goes into
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatesGrouping {
+    /// The file generated from the input file the state is in
+    #[default]
+    Input,
+    /// A file for each strategic region, named after it
+    StrategicRegion,
+    /// A single file of every state
+    Single,
+}
+
+/// How several `create_building` entries of the same building in a `region_state` are split
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatesMode {
+    /// Combine the owners of every entry into one building, which is split once
+    #[default]
+    Merge,
+    /// Split every entry by itself, removing the building only once
+    Separate,
+}
+
+/// Layout of the ratios config file
+#[derive(Deserialize, JsonSchema)]
+struct RatiosFile {
+    /// How the split levels of each target are rounded to whole levels
+    #[serde(default)]
+    rounding: Option<Rounding>,
+    /// Game version whose ratios are used, from `version`
+    #[serde(default)]
+    game_version: Option<String>,
+    split: Vec<SplitRule>,
+    /// Split rules replacing those of the same source building for specific game versions
+    #[serde(default)]
+    version: HashMap<String, VersionRatios>,
+    /// Arable resource changes of the states files, applied in order
+    #[serde(default)]
+    resource: Vec<ResourceRule>,
+    /// Arable land changes of the states files, applied in order
+    #[serde(default)]
+    arable_land: Vec<ArableLandRule>,
+    /// Amounts of capped resources set in the states files, by state and resource
+    #[serde(default)]
+    capped_resources: BTreeMap<String, BTreeMap<String, u32>>,
+    /// Discoverable resources added to the states files
+    #[serde(default)]
+    discoverable_resource: Vec<DiscoverableResource>,
+    /// Discoverable resources added to states of the states files picked at random
+    #[serde(default)]
+    random_deposits: Vec<RandomDeposits>,
+    /// Seed for randomly picking the states of `random_deposits`
+    #[serde(default)]
+    deposit_seed: u64,
+    /// State traits added to the states files, by state
+    #[serde(default)]
+    state_traits: BTreeMap<String, Vec<String>>,
+    /// TOML file of the resources of single states, relative to the config file
+    #[serde(default)]
+    state_overrides: Option<PathBuf>,
+    /// Homeland, claim and ownership changes of the state history files, applied in order
+    #[serde(default)]
+    state_history: Vec<StateHistoryRule>,
+    /// Globs of the names of states files that are left alone
+    #[serde(default = "default_states_exclude")]
+    states_exclude: Vec<String>,
+    /// Leave alone states files whose states are all sea states
+    #[serde(default = "default_skip_sea_states")]
+    skip_sea_states: bool,
+    /// Profile selecting which rules are used, from `profiles`
+    #[serde(default)]
+    profile: Option<String>,
+    /// Named subsets of the rules, of which one can be selected
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+    /// Seed for randomly picking which owners give up the split levels
+    #[serde(default)]
+    owner_seed: Option<u64>,
+    /// How several entries of the same building in a `region_state` are split
+    #[serde(default)]
+    duplicates: DuplicatesMode,
+    /// How the `reserves` of split buildings are divided, for rules that don't say
+    #[serde(default)]
+    reserves: ReservesMode,
+    /// Keep the levels of buildings whose split doesn't add up to their total,
+    /// moving only the levels owners gave up, instead of failing
+    #[serde(default)]
+    exact_levels: bool,
+    /// Whether the buildings files patch the game's buildings or replace its files
+    #[serde(default)]
+    buildings_mode: Option<BuildingsMode>,
+    /// Whether the states files replace the game's files or inject into its states
+    #[serde(default)]
+    states_mode: Option<StatesMode>,
+    /// Which generated states file each state goes into
+    #[serde(default)]
+    states_grouping: StatesGrouping,
+    /// Only write the states and region states with changed buildings to patches
+    #[serde(default)]
+    sparse: bool,
+    /// Leave buildings entries that can't be split as they were instead of failing their file
+    #[serde(default)]
+    keep_going: bool,
+    /// Whether the values of the generated buildings files are quoted
+    #[serde(default)]
+    quotes: QuoteStyle,
+    /// How the `region_state` keys of the generated buildings files are written
+    #[serde(default)]
+    region_state_keys: RegionStateKeys,
+    /// Line endings of the generated states files
+    #[serde(default)]
+    line_endings: LineEndings,
+    /// Warn about owners of buildings whose country the game doesn't define
+    #[serde(default)]
+    warn_unknown_countries: bool,
+    /// Buildings that are never split, even if a rule matches them
+    #[serde(default)]
+    never_touch: Vec<String>,
+    /// Buildings owning other buildings, whose levels follow the levels they own
+    #[serde(default)]
+    ownership_buildings: Vec<String>,
+    #[serde(default)]
+    owner_remap: Vec<OwnerRemap>,
+    /// Output layouts by the generator they apply to
+    #[serde(default)]
+    output: HashMap<String, OutputLayout>,
+    /// Localization generated for the modded buildings, production methods and goods
+    #[serde(default)]
+    localization: LocalizationConfig,
+    /// Production methods of the modded buildings, cloned from the game's production methods
+    #[serde(default)]
+    production_method: Vec<ProductionMethodRule>,
+    /// The game's building that the definitions of the modded buildings are cloned from,
+    /// instead of the buildings they are split off from
+    #[serde(default)]
+    building_template: Option<String>,
+    /// Fields of the generated definitions of modded buildings, by building
+    #[serde(default)]
+    building_definition: BTreeMap<String, BuildingDefinition>,
+    /// Rhai script deciding how buildings are split, relative to the config file
+    #[serde(default)]
+    script: Option<PathBuf>,
+    /// Countries whose buildings are never split
+    #[serde(default)]
+    exclude_countries: Vec<String>,
+    /// The only countries whose buildings are split, if given
+    #[serde(default)]
+    include_countries: Option<Vec<String>>,
+}
+
+/// The rules enabled by a profile, where a missing list enables every rule of its kind
+#[derive(Deserialize, JsonSchema)]
+struct Profile {
+    /// Source buildings of the enabled split rules
+    #[serde(default)]
+    splits: Option<Vec<String>>,
+    /// Names of the enabled resource rules
+    #[serde(default)]
+    resources: Option<Vec<String>>,
+}
+
+/// The split rules of a game version that differ from the default ones
+#[derive(Deserialize, JsonSchema)]
+struct VersionRatios {
+    #[serde(default)]
+    split: Vec<SplitRule>,
+    /// How the `region_state` keys of the buildings files of the version are written
+    #[serde(default)]
+    region_state_keys: Option<RegionStateKeys>,
+}
+
+/// How the `region_state` keys of the generated buildings files are written,
+/// which changed between game versions
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegionStateKeys {
+    /// The same way as the input file writes them
+    #[default]
+    Detect,
+    /// Like `region_state:GBR`, as the current game files do
+    Unquoted,
+    /// Like `"region_state:GBR"`, as the files of older game versions do
+    Quoted,
+}
+
+/// How a vanilla building is split into itself and modded buildings, either by a `ratio`
+/// to a single `target` or by the weights of the levels that `keep` being the source building
+/// and of each of `targets`
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct SplitRule {
+    /// The vanilla building to split
+    pub source: String,
+    /// Levels of the source building for every level of `target`, filled in from `percent` when loading
+    #[serde(default)]
+    pub ratio: Option<f32>,
+    /// Percentage of the levels moved to `target`, instead of a `ratio`
+    #[serde(default)]
+    pub percent: Option<f32>,
+    /// Expression of the levels moved to `target` such as `1 per 4 levels, min 1, max 10`,
+    /// instead of a `ratio` and the fewest and most levels moved
+    #[serde(default)]
+    pub levels: Option<String>,
+    /// Factor that every level of the building is scaled by, instead of splitting it
+    /// into targets
+    #[serde(default)]
+    pub scale: Option<f32>,
+    /// The modded building that levels are moved to, for rules with a single target
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Weight of the levels that stay the source building
+    #[serde(default)]
+    pub keep: f32,
+    /// The modded buildings that levels are moved to, filled in from `target` when loading
+    #[serde(default)]
+    pub targets: Vec<SplitTarget>,
+    /// Buildings with fewer levels than this are left alone
+    #[serde(default)]
+    pub min_total_levels: u16,
+    /// Buildings with more levels than this are left alone
+    #[serde(default)]
+    pub max_total_levels: Option<u16>,
+    /// Strategic regions the rule applies in, or every region if empty
+    #[serde(default)]
+    pub strategic_regions: Vec<String>,
+    /// Leave buildings alone in `region_state`s of countries the game doesn't define
+    #[serde(default)]
+    pub skip_unknown_countries: bool,
+    /// Leave buildings alone in `region_state`s of decentralized countries, which lack the
+    /// technology for the targets at the start of the game
+    #[serde(default)]
+    pub skip_decentralized: bool,
+    /// Targets that would get fewer levels than this get none instead
+    #[serde(default = "default_min_split_levels")]
+    pub min_split_levels: u16,
+    /// Owners that would give fewer levels than this to a target give them to the owners
+    /// giving the most instead
+    #[serde(default)]
+    pub min_owner_levels: u16,
+    /// Fewest levels moved to the targets of a building in total, if it has that many
+    #[serde(default)]
+    pub min_moved_levels: Option<u16>,
+    /// Most levels moved to the targets of a building in total
+    #[serde(default)]
+    pub max_moved_levels: Option<u16>,
+    /// Buildings with at least this many levels that are too small to split any levels
+    /// move a single level to their largest target instead
+    #[serde(default)]
+    pub partial_min_levels: Option<u16>,
+    /// Levels moved to the targets from direct `country` ownership are owned by the country
+    /// of the `region_state`, so foreign countries don't own the targets
+    #[serde(default)]
+    pub local_ownership: bool,
+    /// How the `reserves` of the building are divided between it and its targets,
+    /// instead of the config's `reserves`
+    #[serde(default)]
+    pub reserves: Option<ReservesMode>,
+    /// Fixed `reserves` of `target`, for rules with a single target
+    #[serde(default)]
+    pub target_reserves: Option<f32>,
+    /// Production methods of `target` for each production method of the building, for rules
+    /// with a single target
+    #[serde(default)]
+    pub target_production_methods: HashMap<String, String>,
+    /// Different ratios for specific states or owners, of which the first matching one is used
+    #[serde(default, rename = "override")]
+    pub overrides: Vec<RatioOverride>,
+    /// Scaling of the levels moved to the targets by the population of the state
+    #[serde(default)]
+    pub population: Option<StateScaling>,
+    /// Scaling of the levels moved to the targets by the arable land of the state
+    #[serde(default)]
+    pub arable_land: Option<StateScaling>,
+}
+
+/// Scaling of the weights of a rule's targets by a value of a state relative to
+/// a `reference` value, within the factors `min_factor` and `max_factor`
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct StateScaling {
+    /// Value of a state where the rule's own ratio or weights are used
+    pub reference: f32,
+    #[serde(default = "default_min_scaling_factor")]
+    pub min_factor: f32,
+    #[serde(default = "default_max_scaling_factor")]
+    pub max_factor: f32,
+}
+
+/// A modded building that a share of a split building's levels is moved to
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct SplitTarget {
+    pub building: String,
+    /// Share of the levels moved to this building, relative to the rule's other weights,
+    /// filled in from `ratio` when loading
+    #[serde(default)]
+    pub weight: f32,
+    /// Levels of the source building for every level of this building, instead of a `weight`
+    #[serde(default)]
+    pub ratio: Option<f32>,
+    /// Fixed `reserves` of this building, used instead of the rule's `reserves` mode
+    #[serde(default)]
+    pub reserves: Option<f32>,
+    /// Production method of this building to activate for each one activated in the split building,
+    /// of which the ones without a match are dropped
+    #[serde(default)]
+    pub production_methods: HashMap<String, String>,
+}
+
+/// How the `reserves` of a split building are divided between it and its targets
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReservesMode {
+    /// Targets get the same reserves as the building, which keeps its own
+    #[default]
+    Copy,
+    /// The building and its targets get a share of the reserves by their levels
+    Proportional,
+}
+
+/// Whether the values of the generated buildings files are quoted, like `"building_x"`,
+/// which numbers and `yes` or `no` never are
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuoteStyle {
+    #[default]
+    Always,
+    /// Only quote values that need quotes
+    Never,
+    /// Quote values if most of the values of the input file are quoted
+    Input,
+}
+
+/// Line endings of the generated states files
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineEndings {
+    /// The same as the input file's
+    #[default]
+    Input,
+    Lf,
+    Crlf,
+}
+
+/// A division of levels used instead of the rule's own in some states, either as a `ratio`
+/// for rules with a single target or as new weights for `keep` and `targets`
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct RatioOverride {
+    /// `STATE_*` keys of the states the override applies to, or every state if empty
+    #[serde(default)]
+    pub states: Vec<String>,
+    /// Country tags of the `region_state` owners the override applies to, or every owner if empty
+    #[serde(default)]
+    pub region_states: Vec<String>,
+    #[serde(default)]
+    pub ratio: Option<f32>,
+    #[serde(default)]
+    pub percent: Option<f32>,
+    /// Filled in from `ratio` when loading
+    #[serde(default)]
+    pub keep: Option<f32>,
+    #[serde(default)]
+    pub targets: Vec<SplitTarget>,
+}
+
+impl SplitRule {
+    /// Returns the weight of the levels kept as the source building and the targets to split into
+    /// in the part of `state` owned by `region_state`, which may be given with or without
+    /// their `s:` and `region_state:` prefixes
+    pub fn division_for(&self, state: &str, region_state: &str) -> (f32, &[SplitTarget]) {
+        let state = state.strip_prefix("s:").unwrap_or(state);
+        let region_state = region_state_tag(region_state);
+        let ratio_override = self.overrides.iter().find(|ratio_override| {
+            (ratio_override.states.is_empty() || ratio_override.states.iter().any(|s| s == state))
+                && (ratio_override.region_states.is_empty()
+                    || ratio_override
+                        .region_states
+                        .iter()
+                        .any(|r| r == region_state))
+        });
+        match ratio_override {
+            Some(ratio_override) if !ratio_override.targets.is_empty() => (
+                ratio_override.keep.unwrap_or(self.keep),
+                &ratio_override.targets,
+            ),
+            Some(ratio_override) => (ratio_override.keep.unwrap_or(self.keep), &self.targets),
+            None => (self.keep, &self.targets),
+        }
+    }
+
+    /// Returns the factor that the weights of the targets are scaled by in `state`
+    /// for its population and arable land, which is 1 without scaling or values of the state
+    pub fn scaling_factor(&self, state: &str, game_data: &GameData) -> f32 {
+        let state = state.strip_prefix("s:").unwrap_or(state);
+        let factor = |scaling: &Option<StateScaling>, value: Option<f32>| match (scaling, value) {
+            (Some(scaling), Some(value)) => {
+                (value / scaling.reference).clamp(scaling.min_factor, scaling.max_factor)
+            }
+            _ => 1.0,
+        };
+        factor(
+            &self.population,
+            game_data
+                .populations
+                .get(state)
+                .map(|&population| population as f32),
+        ) * factor(
+            &self.arable_land,
+            game_data
+                .arable_land
+                .get(state)
+                .map(|&arable_land| arable_land as f32),
+        )
+    }
+
+    /// Returns why a building with `total_levels` levels in the part of `state` owned by
+    /// `region_state` is left alone, or `None` if the rule applies to it
+    pub fn skip_reason(
+        &self,
+        state: &str,
+        region_state: &str,
+        total_levels: u16,
+        game_data: &GameData,
+    ) -> Option<String> {
+        let state = state.strip_prefix("s:").unwrap_or(state);
+        let region_state = region_state_tag(region_state);
+        if total_levels < self.min_total_levels {
+            return Some(format!(
+                "fewer than {} levels ({})",
+                self.min_total_levels, total_levels
+            ));
+        }
+        if let Some(max_total_levels) = self.max_total_levels
+            && total_levels > max_total_levels
+        {
+            return Some(format!(
+                "more than {} levels ({})",
+                max_total_levels, total_levels
+            ));
+        }
+        if !self.strategic_regions.is_empty() {
+            let region = game_data.strategic_regions.get(state);
+            if !region.is_some_and(|region| self.strategic_regions.contains(region)) {
+                return Some(format!(
+                    "outside the rule's strategic regions ({})",
+                    region.map_or("none", String::as_str)
+                ));
+            }
+        }
+        if self.skip_unknown_countries && !game_data.countries.contains(region_state) {
+            return Some(format!("unknown country {}", region_state));
+        }
+        if self.skip_decentralized && game_data.decentralized.contains(region_state) {
+            return Some(format!("decentralized country {}", region_state));
+        }
+        None
+    }
+
+    /// Whether the rule splits every building of the building group `source`
+    fn is_group_rule(&self) -> bool {
+        self.source.starts_with("bg_")
+    }
+
+    /// Whether the rule depends on definitions from the game's files
+    fn needs_game_data(&self) -> bool {
+        self.is_group_rule()
+            || !self.strategic_regions.is_empty()
+            || self.skip_unknown_countries
+            || self.skip_decentralized
+            || self.population.is_some()
+            || self.arable_land.is_some()
+    }
+
+    /// Returns every problem with the buildings of the rule, which the game wouldn't recognize
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let targets = self
+            .target
+            .iter()
+            .chain(self.targets.iter().map(|target| &target.building))
+            .chain(
+                self.overrides
+                    .iter()
+                    .flat_map(|ratio_override| &ratio_override.targets)
+                    .map(|target| &target.building),
+            );
+        if !(self.source.starts_with("building_") || self.source.starts_with("bg_"))
+            || !is_identifier(&self.source)
+        {
+            problems.push(format!(
+                "Unknown building or building group identifier {:?}",
+                self.source
+            ));
+        }
+        for building in targets.clone() {
+            if !building.starts_with("building_") || !is_identifier(building) {
+                problems.push(format!("Unknown building identifier {:?}", building));
+            }
+        }
+        if let Some(max_total_levels) = self.max_total_levels
+            && max_total_levels < self.min_total_levels
+        {
+            problems.push(format!(
+                "Rule for {} has a `max_total_levels` below its `min_total_levels`",
+                self.source
+            ));
+        }
+        if let (Some(min_moved_levels), Some(max_moved_levels)) =
+            (self.min_moved_levels, self.max_moved_levels)
+            && max_moved_levels < min_moved_levels
+        {
+            problems.push(format!(
+                "Rule for {} has a `max_moved_levels` below its `min_moved_levels`",
+                self.source
+            ));
+        }
+        if self.partial_min_levels == Some(0) {
+            problems.push(format!(
+                "Rule for {} has a `partial_min_levels` of 0, it must be at least 1",
+                self.source
+            ));
+        }
+        if self.target_reserves.is_some() && self.target.is_none() {
+            problems.push(format!(
+                "Rule for {} can only have `target_reserves` if it has a single `target`",
+                self.source
+            ));
+        }
+        if !self.target_production_methods.is_empty() && self.target.is_none() {
+            problems.push(format!(
+                "Rule for {} can only have `target_production_methods` if it has a single `target`",
+                self.source
+            ));
+        }
+        let production_methods = self.target_production_methods.iter().chain(
+            self.targets
+                .iter()
+                .chain(
+                    self.overrides
+                        .iter()
+                        .flat_map(|ratio_override| &ratio_override.targets),
+                )
+                .flat_map(|target| &target.production_methods),
+        );
+        for (from, to) in production_methods {
+            for production_method in [from, to] {
+                if !production_method.starts_with("pm_") || !is_identifier(production_method) {
+                    problems.push(format!(
+                        "Unknown production method identifier {:?}",
+                        production_method
+                    ));
+                }
+            }
+        }
+        if self
+            .target_reserves
+            .iter()
+            .chain(
+                self.targets
+                    .iter()
+                    .filter_map(|target| target.reserves.as_ref()),
+            )
+            .any(|&reserves| reserves < 0.0)
+        {
+            problems.push(format!("Reserves of {} can't be negative", self.source));
+        }
+        for (name, scaling) in [
+            ("population", &self.population),
+            ("arable_land", &self.arable_land),
+        ] {
+            if let Some(scaling) = scaling
+                && (scaling.reference <= 0.0
+                    || scaling.min_factor <= 0.0
+                    || scaling.max_factor < scaling.min_factor)
+            {
+                problems.push(format!(
+                    "`{}` scaling of {} needs a positive `reference` and `min_factor`, \
+                     and a `max_factor` of at least `min_factor`",
+                    name, self.source
+                ));
+            }
+        }
+        if targets.clone().any(|target| *target == self.source) {
+            problems.push(format!("Building {} is split into itself", self.source));
+        }
+        problems
+    }
+
+    /// Checks that the rule is complete, and turns a `ratio` and `target` into weights
+    fn normalize(&mut self) -> anyhow::Result<()> {
+        if let Some(scale) = self.scale {
+            if self.ratio.is_some()
+                || self.percent.is_some()
+                || self.levels.is_some()
+                || self.target.is_some()
+                || !self.targets.is_empty()
+                || !self.overrides.is_empty()
+            {
+                anyhow::bail!(
+                    "Rule for {} has a `scale`, it can't also have a `ratio`, `percent`, `levels`, \
+                     `target`, `targets` or overrides",
+                    self.source
+                );
+            }
+            if scale <= 0.0 {
+                anyhow::bail!("Scale of {} must be above 0, got {}", self.source, scale);
+            }
+            return Ok(());
+        }
+        if let Some(expression) = &self.levels {
+            if self.ratio.is_some()
+                || self.percent.is_some()
+                || self.min_moved_levels.is_some()
+                || self.max_moved_levels.is_some()
+            {
+                anyhow::bail!(
+                    "Rule for {} has `levels`, it can't also have a `ratio`, `percent`, \
+                     `min_moved_levels` or `max_moved_levels`",
+                    self.source
+                );
+            }
+            let (ratio, min_moved_levels, max_moved_levels) =
+                parse_levels_expression(&self.source, expression)?;
+            self.ratio = Some(ratio);
+            self.min_moved_levels = min_moved_levels;
+            self.max_moved_levels = max_moved_levels;
+        }
+        self.ratio = ratio_or_percent(&self.source, self.ratio, self.percent)?;
+        match (self.ratio, &self.target, self.targets.is_empty()) {
+            (Some(ratio), Some(target), true) => {
+                check_ratio(&self.source, ratio)?;
+                self.keep = ratio - 1.0;
+                self.targets = vec![SplitTarget {
+                    building: target.clone(),
+                    weight: 1.0,
+                    ratio: None,
+                    reserves: self.target_reserves,
+                    production_methods: self.target_production_methods.clone(),
+                }];
+            }
+            (None, None, false) => {
+                if let Some(keep) =
+                    ratio_weights(&self.source, self.keep != 0.0, &mut self.targets)?
+                {
+                    self.keep = keep;
+                }
+                check_weights(&self.source, self.keep, &self.targets)?
+            }
+            _ => anyhow::bail!(
+                "Rule for {} needs either a `ratio` or `percent` and a `target`, or a list of `targets`",
+                self.source
+            ),
+        }
+
+        for ratio_override in &mut self.overrides {
+            if ratio_override.states.is_empty() && ratio_override.region_states.is_empty() {
+                anyhow::bail!(
+                    "Override of {} needs `states` or `region_states` to apply to",
+                    self.source
+                );
+            }
+            ratio_override.ratio =
+                ratio_or_percent(&self.source, ratio_override.ratio, ratio_override.percent)?;
+            match ratio_override.ratio {
+                Some(_) if self.ratio.is_none() || !ratio_override.targets.is_empty() => {
+                    anyhow::bail!(
+                        "Override of {} can only have a `ratio` or `percent` if the rule has a single `target`",
+                        self.source
+                    )
+                }
+                Some(ratio) => {
+                    check_ratio(&self.source, ratio)?;
+                    ratio_override.keep = Some(ratio - 1.0);
+                }
+                None => {
+                    if let Some(keep) = ratio_weights(
+                        &self.source,
+                        ratio_override.keep.is_some(),
+                        &mut ratio_override.targets,
+                    )? {
+                        ratio_override.keep = Some(keep);
+                    }
+                    check_weights(
+                        &self.source,
+                        ratio_override.keep.unwrap_or(self.keep),
+                        &ratio_override.targets,
+                    )?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The country tag of a `region_state` key, which may be given with or without its
+/// `region_state:` prefix and quotes, as different game versions write them
+pub fn region_state_tag(region_state: &str) -> &str {
+    let region_state = region_state.trim_matches('"');
+    region_state
+        .strip_prefix("region_state:")
+        .unwrap_or(region_state)
+        .trim()
+}
+
+fn default_min_split_levels() -> u16 {
+    1
+}
+
+fn default_min_scaling_factor() -> f32 {
+    0.5
+}
+
+fn default_max_scaling_factor() -> f32 {
+    2.0
+}
+
+/// Returns the ratio equivalent to moving `percent` percent of the levels, if that is given instead
+fn ratio_or_percent(
+    source: &str,
+    ratio: Option<f32>,
+    percent: Option<f32>,
+) -> anyhow::Result<Option<f32>> {
+    match (ratio, percent) {
+        (Some(_), Some(_)) => {
+            anyhow::bail!("Rule for {} has both a `ratio` and a `percent`", source)
+        }
+        (None, Some(percent)) if percent <= 0.0 || percent > 100.0 => anyhow::bail!(
+            "Percent of {} must be above 0 and at most 100, got {}",
+            source,
+            percent
+        ),
+        (None, Some(percent)) => Ok(Some(100.0 / percent)),
+        (ratio, None) => Ok(ratio),
+    }
+}
+
+/// Reads a levels expression such as `1 per 4 levels, min 1, max 10` into its ratio
+/// and the fewest and most levels it moves
+fn parse_levels_expression(
+    source: &str,
+    expression: &str,
+) -> anyhow::Result<(f32, Option<u16>, Option<u16>)> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid `levels` of {} {:?}, expected an expression like `1 per 4 levels, min 1, max 10`",
+            source,
+            expression
+        )
+    };
+    let mut parts = expression.split(',').map(str::trim);
+    let ratio = match parts
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [moved, "per", levels] | [moved, "per", levels, "level" | "levels"] => {
+            let moved = moved.parse::<f32>().map_err(|_| invalid())?;
+            let levels = levels.parse::<f32>().map_err(|_| invalid())?;
+            if moved <= 0.0 {
+                return Err(invalid());
+            }
+            levels / moved
+        }
+        _ => return Err(invalid()),
+    };
+
+    let (mut min_levels, mut max_levels) = (None, None);
+    for part in parts {
+        let (bound, levels) = match part.split_once(char::is_whitespace) {
+            Some(("min", levels)) => (&mut min_levels, levels),
+            Some(("max", levels)) => (&mut max_levels, levels),
+            _ => return Err(invalid()),
+        };
+        if bound.is_some() {
+            return Err(invalid());
+        }
+        *bound = Some(levels.trim().parse::<u16>().map_err(|_| invalid())?);
+    }
+    Ok((ratio, min_levels, max_levels))
+}
+
+fn check_ratio(source: &str, ratio: f32) -> anyhow::Result<()> {
+    if ratio < 1.0 {
+        anyhow::bail!("Ratio of {} must be at least 1, got {}", source, ratio);
+    }
+    Ok(())
+}
+
+/// Turns the `ratio` of each of `targets` into its weight, returning the weight of the levels
+/// that are kept, or `None` if the targets have weights instead. The levels moved to every
+/// target are taken from the building together, so the ratios must leave some levels.
+fn ratio_weights(
+    source: &str,
+    has_keep: bool,
+    targets: &mut [SplitTarget],
+) -> anyhow::Result<Option<f32>> {
+    if targets.iter().all(|target| target.ratio.is_none()) {
+        return Ok(None);
+    }
+    if has_keep || targets.iter().any(|target| target.ratio.is_none()) {
+        anyhow::bail!(
+            "Targets of {} need either a `ratio` each or weights with a `keep`",
+            source
+        );
+    }
+    for target in targets.iter_mut() {
+        let ratio = target.ratio.unwrap_or(1.0);
+        check_ratio(source, ratio)?;
+        target.weight = 1.0 / ratio;
+    }
+    let keep = 1.0 - targets.iter().map(|target| target.weight).sum::<f32>();
+    if keep < 0.0 {
+        anyhow::bail!(
+            "Ratios of the targets of {} move more than every level",
+            source
+        );
+    }
+    Ok(Some(keep))
+}
+
+fn check_weights(source: &str, keep: f32, targets: &[SplitTarget]) -> anyhow::Result<()> {
+    if keep < 0.0 || targets.iter().any(|target| target.weight < 0.0) {
+        anyhow::bail!("Weights of {} can't be negative", source);
+    }
+    if targets.iter().all(|target| target.weight == 0.0) {
+        anyhow::bail!("Weights of {} must move some levels to a target", source);
+    }
+    Ok(())
+}
+
+/// Arable resources changed in every state that has any of `when`,
+/// which adds the resources in `add` and removes those in `remove`.
+/// Rules without `when` only remove resources, from every state that has them.
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct ResourceRule {
+    /// Name of the rule for enabling it in profiles
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub when: Vec<String>,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Where the added resources go in the list
+    #[serde(default)]
+    pub mode: ResourceMode,
+    /// Resources are only added while the list is shorter than this
+    #[serde(default)]
+    pub max_resources: Option<usize>,
+    /// The rule only applies to states with any of these state traits
+    #[serde(default)]
+    pub traits: Vec<String>,
+    /// The rule doesn't apply to states with any of these state traits
+    #[serde(default)]
+    pub exclude_traits: Vec<String>,
+    /// The rule only applies to states where most provinces have one of these terrains
+    #[serde(default)]
+    pub terrains: Vec<String>,
+}
+
+/// Where a resource rule puts the resources it adds
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResourceMode {
+    /// At the end of the list
+    #[default]
+    Append,
+    /// In place of the first of the rule's `when` resources the state has, which is removed
+    Replace,
+}
+
+impl ResourceRule {
+    /// Whether the rule applies to a state with `traits` and `terrain`
+    pub fn applies_to(&self, traits: &[&str], terrain: Option<&str>) -> bool {
+        (self.traits.is_empty() || self.traits.iter().any(|t| traits.contains(&t.as_str())))
+            && !self
+                .exclude_traits
+                .iter()
+                .any(|t| traits.contains(&t.as_str()))
+            && (self.terrains.is_empty()
+                || terrain.is_some_and(|terrain| self.terrains.iter().any(|t| t == terrain)))
+    }
+
+    /// Returns every problem with the rule
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.when.is_empty() && !self.add.is_empty() {
+            problems.push(format!(
+                "Resource rule adding {} needs `when` resources to apply to",
+                self.add.join(", ")
+            ));
+        }
+        if self.mode == ResourceMode::Replace && self.add.is_empty() {
+            problems.push(format!(
+                "Resource rule for {} needs resources to `add` to replace them with",
+                self.when.join(", ")
+            ));
+        }
+        if self.add.is_empty() && self.remove.is_empty() {
+            problems.push(format!(
+                "Resource rule for {} needs resources to `add` or `remove`",
+                self.when.join(", ")
+            ));
+        }
+        for resource in self.when.iter().chain(&self.add).chain(&self.remove) {
+            if !is_identifier(resource) {
+                problems.push(format!("Unknown resource identifier {:?}", resource));
+            }
+        }
+        problems
+    }
+}
+
+/// Arable land of the states in `states` and `strategic_regions`, or of every state if both
+/// are empty, multiplied by `factor` and then changed by `add`
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct ArableLandRule {
+    /// `STATE_*` keys of the states the rule applies to
+    #[serde(default)]
+    pub states: Vec<String>,
+    /// Strategic regions of the states the rule applies to
+    #[serde(default)]
+    pub strategic_regions: Vec<String>,
+    #[serde(default = "default_arable_land_factor")]
+    pub factor: f32,
+    #[serde(default)]
+    pub add: i32,
+}
+
+impl ArableLandRule {
+    /// Whether the rule applies to `state`, which may be given with or without its `s:` prefix
+    pub fn applies_to(&self, state: &str, game_data: &GameData) -> bool {
+        (self.states.is_empty() && self.strategic_regions.is_empty())
+            || selects_state(&self.states, &self.strategic_regions, state, game_data)
+    }
+
+    /// Returns the arable land of a state with `arable_land` after the rule
+    pub fn apply(&self, arable_land: u32) -> u32 {
+        ((arable_land as f32 * self.factor).round() as i64 + i64::from(self.add)).max(0) as u32
+    }
+
+    /// Returns every problem with the rule
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.factor < 0.0 {
+            problems.push(format!(
+                "Arable land factor can't be negative, got {}",
+                self.factor
+            ));
+        }
+        if self.factor == 1.0 && self.add == 0 {
+            problems.push("Arable land rule needs a `factor` or an amount to `add`".to_string());
+        }
+        problems
+    }
+}
+
+fn default_arable_land_factor() -> f32 {
+    1.0
+}
+
+fn default_states_exclude() -> Vec<String> {
+    vec!["*99_seas*".to_string()]
+}
+
+fn default_skip_sea_states() -> bool {
+    true
+}
+
+/// Resources of a state in the state overrides file, which it has instead of
+/// the ones the rules would give it
+#[derive(Deserialize, JsonSchema, Clone, Default)]
+pub struct StateOverride {
+    /// The whole `arable_resources` of the state, instead of applying the resource rules
+    #[serde(default)]
+    pub arable_resources: Option<Vec<String>>,
+    /// The whole `capped_resources` of the state, instead of its own and `capped_resources`
+    #[serde(default)]
+    pub capped_resources: Option<BTreeMap<String, u32>>,
+}
+
+/// A `resource` block with undiscovered deposits of `type` added to the states in `states`
+/// and `strategic_regions`, or updated in states that already have a block of the type
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct DiscoverableResource {
+    /// `STATE_*` keys of the states the resource is added to
+    #[serde(default)]
+    pub states: Vec<String>,
+    /// Strategic regions of the states the resource is added to
+    #[serde(default)]
+    pub strategic_regions: Vec<String>,
+    /// Building group of the resource, such as `bg_rubber`
+    pub r#type: String,
+    pub undiscovered_amount: u32,
+    /// Amount that is already discovered at the start of the game
+    #[serde(default)]
+    pub discovered_amount: Option<u32>,
+    /// Building group the resource becomes once depleted
+    #[serde(default)]
+    pub depleted_type: Option<String>,
+}
+
+impl DiscoverableResource {
+    /// Whether the resource is added to `state`, which may be given with or without its
+    /// `s:` prefix
+    pub fn applies_to(&self, state: &str, game_data: &GameData) -> bool {
+        selects_state(&self.states, &self.strategic_regions, state, game_data)
+    }
+
+    /// Returns every problem with the resource
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.states.is_empty() && self.strategic_regions.is_empty() {
+            problems.push(format!(
+                "Discoverable resource {} needs `states` or `strategic_regions`",
+                self.r#type
+            ));
+        }
+        for resource in std::iter::once(&self.r#type).chain(&self.depleted_type) {
+            if !resource.starts_with("bg_") || !is_identifier(resource) {
+                problems.push(format!("Unknown building group identifier {:?}", resource));
+            }
+        }
+        problems
+    }
+}
+
+/// Changes of the `history/states` entries of the states in `states` and `strategic_regions`
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct StateHistoryRule {
+    /// `STATE_*` keys of the states the rule applies to
+    #[serde(default)]
+    pub states: Vec<String>,
+    /// Strategic regions of the states the rule applies to
+    #[serde(default)]
+    pub strategic_regions: Vec<String>,
+    /// Cultures the states become a homeland of, with or without their `cu:` prefix
+    #[serde(default)]
+    pub add_homelands: Vec<String>,
+    /// Cultures the states stop being a homeland of
+    #[serde(default)]
+    pub remove_homelands: Vec<String>,
+    /// Countries that get a claim on the states, with or without their `c:` prefix
+    #[serde(default)]
+    pub add_claims: Vec<String>,
+    /// Countries whose claims on the states are removed
+    #[serde(default)]
+    pub remove_claims: Vec<String>,
+    /// Country that owns every province of the states, in a single `create_state`
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+impl StateHistoryRule {
+    /// Whether the rule applies to `state`, which may be given with or without its `s:` prefix
+    pub fn applies_to(&self, state: &str, game_data: &GameData) -> bool {
+        selects_state(&self.states, &self.strategic_regions, state, game_data)
+    }
+
+    /// Returns every problem with the rule
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.states.is_empty() && self.strategic_regions.is_empty() {
+            problems.push("State history rule needs `states` or `strategic_regions`".to_string());
+        }
+        if self.add_homelands.is_empty()
+            && self.remove_homelands.is_empty()
+            && self.add_claims.is_empty()
+            && self.remove_claims.is_empty()
+            && self.owner.is_none()
+        {
+            problems.push("State history rule doesn't change anything".to_string());
+        }
+        for culture in self.add_homelands.iter().chain(&self.remove_homelands) {
+            if !is_identifier(culture.strip_prefix("cu:").unwrap_or(culture)) {
+                problems.push(format!("Unknown culture identifier {:?}", culture));
+            }
+        }
+        for country in self
+            .add_claims
+            .iter()
+            .chain(&self.remove_claims)
+            .chain(&self.owner)
+        {
+            let tag = country.strip_prefix("c:").unwrap_or(country);
+            if tag.is_empty()
+                || !tag
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+            {
+                problems.push(format!("Unknown country tag {:?}", country));
+            }
+        }
+        problems
+    }
+}
+
+/// Deposits of `type` added to `count` states picked at random from the states in `states`
+/// and `strategic_regions`, or from every land state if both are empty, with a chance of each
+/// state by the weights of its terrain and strategic region
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct RandomDeposits {
+    /// `STATE_*` keys of the states the deposits can be placed in
+    #[serde(default)]
+    pub states: Vec<String>,
+    /// Strategic regions of the states the deposits can be placed in
+    #[serde(default)]
+    pub strategic_regions: Vec<String>,
+    /// Building group of the deposits, such as `bg_gold_fields`
+    pub r#type: String,
+    /// Number of states that get a deposit
+    pub count: u32,
+    pub undiscovered_amount: u32,
+    /// Amount that is already discovered at the start of the game
+    #[serde(default)]
+    pub discovered_amount: Option<u32>,
+    /// Building group the deposits become once depleted
+    #[serde(default)]
+    pub depleted_type: Option<String>,
+    /// Weights of the chance of states by the terrain of most of their provinces,
+    /// which is 1 for terrains not listed and 0 to leave them out
+    #[serde(default)]
+    pub terrain_weights: BTreeMap<String, u32>,
+    /// Weights of the chance of states by their strategic region,
+    /// which is 1 for regions not listed and 0 to leave them out
+    #[serde(default)]
+    pub region_weights: BTreeMap<String, u32>,
+}
+
+impl RandomDeposits {
+    /// Returns the weight of the chance of `state` with the `terrain` of most of its provinces,
+    /// which may be given with or without its `s:` prefix, of getting a deposit
+    pub fn weight(&self, state: &str, terrain: Option<&str>, game_data: &GameData) -> u32 {
+        let selected = (self.states.is_empty() && self.strategic_regions.is_empty())
+            || selects_state(&self.states, &self.strategic_regions, state, game_data);
+        if !selected {
+            return 0;
+        }
+        let terrain_weight = terrain
+            .and_then(|terrain| self.terrain_weights.get(terrain))
+            .copied()
+            .unwrap_or(1);
+        let region_weight = game_data
+            .strategic_regions
+            .get(state.strip_prefix("s:").unwrap_or(state))
+            .and_then(|region| self.region_weights.get(region))
+            .copied()
+            .unwrap_or(1);
+        terrain_weight.saturating_mul(region_weight)
+    }
+
+    /// Returns the discoverable resource of the deposits placed in `states`
+    pub fn placed(&self, states: Vec<String>) -> DiscoverableResource {
+        DiscoverableResource {
+            states,
+            strategic_regions: Vec::new(),
+            r#type: self.r#type.clone(),
+            undiscovered_amount: self.undiscovered_amount,
+            discovered_amount: self.discovered_amount,
+            depleted_type: self.depleted_type.clone(),
+        }
+    }
+
+    /// Returns every problem with the deposits
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.count == 0 {
+            problems.push(format!(
+                "Random deposits of {} need a `count` of at least 1",
+                self.r#type
+            ));
+        }
+        for resource in std::iter::once(&self.r#type).chain(&self.depleted_type) {
+            if !resource.starts_with("bg_") || !is_identifier(resource) {
+                problems.push(format!("Unknown building group identifier {:?}", resource));
+            }
+        }
+        problems
+    }
+
+    /// Whether placing the deposits reads the strategic regions or terrains from the game's files
+    fn needs_game_data(&self) -> bool {
+        !self.strategic_regions.is_empty()
+            || !self.region_weights.is_empty()
+            || !self.terrain_weights.is_empty()
+    }
+}
+
+/// Whether `state`, which may be given with or without its `s:` prefix, is one of `states`
+/// or in one of `strategic_regions`
+fn selects_state(
+    states: &[String],
+    strategic_regions: &[String],
+    state: &str,
+    game_data: &GameData,
+) -> bool {
+    let state = state.strip_prefix("s:").unwrap_or(state);
+    states.iter().any(|s| s == state)
+        || game_data
+            .strategic_regions
+            .get(state)
+            .is_some_and(|region| strategic_regions.contains(region))
+}
+
+/// An owning building type replaced by another in the ownership of modded buildings
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct OwnerRemap {
+    pub from: String,
+    pub to: String,
+    /// Modded buildings whose owners are remapped, or every modded building if empty
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+/// A production method of the modded buildings cloned from the game's `source` production
+/// method, with the amounts of the goods it uses scaled and goods replaced by others
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct ProductionMethodRule {
+    /// Key of the generated production method
+    pub name: String,
+    /// The game's production method it is cloned from, if no split target's production
+    /// methods map one to it
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Factor of the amounts of every input good
+    #[serde(default = "default_goods_factor")]
+    pub input_factor: f32,
+    /// Factor of the amounts of every output good
+    #[serde(default = "default_goods_factor")]
+    pub output_factor: f32,
+    /// Factors of the amounts of specific goods, by their name in the source production method,
+    /// applied after the input or output factor
+    #[serde(default)]
+    pub goods_factors: BTreeMap<String, f32>,
+    /// Goods of the source production method replaced by others, such as `grain = "fruit"`
+    #[serde(default)]
+    pub rename_goods: BTreeMap<String, String>,
+}
+
+impl ProductionMethodRule {
+    /// Returns every problem with the rule
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for production_method in std::iter::once(&self.name).chain(&self.source) {
+            if !production_method.starts_with("pm_") || !is_identifier(production_method) {
+                problems.push(format!(
+                    "Unknown production method identifier {:?}",
+                    production_method
+                ));
+            }
+        }
+        for factor in [self.input_factor, self.output_factor]
+            .into_iter()
+            .chain(self.goods_factors.values().copied())
+        {
+            if factor < 0.0 {
+                problems.push(format!(
+                    "Goods factor of {} can't be negative, got {}",
+                    self.name, factor
+                ));
+            }
+        }
+        for good in self
+            .goods_factors
+            .keys()
+            .chain(self.rename_goods.keys())
+            .chain(self.rename_goods.values())
+        {
+            if !is_identifier(good) {
+                problems.push(format!("Unknown good identifier {:?}", good));
+            }
+        }
+        problems
+    }
+}
+
+fn default_goods_factor() -> f32 {
+    1.0
+}
+
+/// Fields of the generated definition of a modded building, replacing those of the game's
+/// building it is cloned from
+#[derive(Deserialize, JsonSchema, Clone, Default)]
+pub struct BuildingDefinition {
+    /// The game's building the definition is cloned from, instead of the config's
+    /// `building_template` or the building it is split off from
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub building_group: Option<String>,
+    #[serde(default)]
+    pub production_method_groups: Option<Vec<String>>,
+    #[serde(default)]
+    pub required_construction: Option<String>,
+    #[serde(default)]
+    pub texture: Option<String>,
+}
+
+impl BuildingDefinition {
+    /// Returns every problem with the definition of `building`
+    fn check(&self, building: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        let identifiers = [
+            (Some(building), "building_", "building"),
+            (self.template.as_deref(), "building_", "building"),
+            (self.building_group.as_deref(), "bg_", "building group"),
+        ]
+        .into_iter()
+        .chain(
+            self.production_method_groups
+                .iter()
+                .flatten()
+                .map(|group| (Some(group.as_str()), "pmg_", "production method group")),
+        );
+        for (identifier, prefix, kind) in identifiers {
+            if let Some(identifier) = identifier
+                && (!identifier.starts_with(prefix) || !is_identifier(identifier))
+            {
+                problems.push(format!("Unknown {} identifier {:?}", kind, identifier));
+            }
+        }
+        problems
+    }
+}
+
+/// Languages the game has localization for
+const LANGUAGES: &[&str] = &[
+    "braz_por",
+    "english",
+    "french",
+    "german",
+    "japanese",
+    "korean",
+    "polish",
+    "russian",
+    "simp_chinese",
+    "spanish",
+    "turkish",
+];
+
+/// How the localization of the modded buildings, production methods and goods is generated.
+/// Names are made from templates, where `{name}` is the key without its `building_` or `pm_`
+/// prefix in title case and `{key}` is the whole key.
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct LocalizationConfig {
+    /// Languages to generate a localization file for
+    #[serde(default = "default_languages")]
+    pub languages: Vec<String>,
+    /// Name of the generated files before their `_l_{language}.yml`
+    #[serde(default = "default_localization_file")]
+    pub file: String,
+    #[serde(default = "default_name_template")]
+    pub building_name: String,
+    #[serde(default = "default_name_template")]
+    pub production_method_name: String,
+    #[serde(default = "default_name_template")]
+    pub good_name: String,
+    /// Goods the mod adds, which the rules don't refer to
+    #[serde(default)]
+    pub goods: Vec<String>,
+    /// Names of specific keys, used instead of their template
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+}
+
+impl Default for LocalizationConfig {
+    fn default() -> Self {
+        Self {
+            languages: default_languages(),
+            file: default_localization_file(),
+            building_name: default_name_template(),
+            production_method_name: default_name_template(),
+            good_name: default_name_template(),
+            goods: Vec::new(),
+            names: BTreeMap::new(),
+        }
+    }
+}
+
+impl LocalizationConfig {
+    /// Returns every problem with the localization config
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for language in &self.languages {
+            if !LANGUAGES.contains(&language.as_str()) {
+                problems.push(format!(
+                    "Unknown localization language {:?}, expected one of: {}",
+                    language,
+                    LANGUAGES.join(", ")
+                ));
+            }
+        }
+        if self.file.is_empty() || self.file.contains(['/', '\\']) {
+            problems.push(format!("Invalid localization file name {:?}", self.file));
+        }
+        for good in &self.goods {
+            if !is_identifier(good) {
+                problems.push(format!("Unknown good identifier {:?}", good));
+            }
+        }
+        problems
+    }
+}
+
+fn default_languages() -> Vec<String> {
+    vec!["english".to_string()]
+}
+
+fn default_localization_file() -> String {
+    "ir_buildings".to_string()
+}
+
+fn default_name_template() -> String {
+    "{name}".to_string()
+}
+
+/// Where a generator's files go in the mod and how they are named
+#[derive(Deserialize, JsonSchema, Clone, Default)]
+pub struct OutputLayout {
+    /// Directory of the generated files, relative to the mod root
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// Output prefix used as `{prefix}` in naming templates
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Naming template of the generated files
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Naming templates of specific input file names, used instead of `name`
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+}
+
+/// Settings for the generators that can be changed without recompiling
+pub struct Config {
+    /// Split rules by their source building
+    pub splits: HashMap<String, SplitRule>,
+    pub rounding: Rounding,
+    /// Seed for randomly assigning split levels to owners, if they are assigned randomly
+    pub owner_seed: Option<u64>,
+    pub duplicates: DuplicatesMode,
+    /// How the `reserves` of split buildings are divided, for rules that don't say
+    pub reserves: ReservesMode,
+    /// Keep the levels of buildings whose split doesn't add up to their total, instead of failing
+    pub exact_levels: bool,
+    pub buildings_mode: BuildingsMode,
+    pub states_mode: StatesMode,
+    /// Which generated states file each state goes into
+    pub states_grouping: StatesGrouping,
+    /// Only write the states and region states with changed buildings to patches
+    pub sparse: bool,
+    /// Leave buildings entries that can't be split as they were instead of failing their file
+    pub keep_going: bool,
+    pub quotes: QuoteStyle,
+    pub region_state_keys: RegionStateKeys,
+    pub line_endings: LineEndings,
+    /// Arable resource changes of the states files, applied in order
+    pub resources: Vec<ResourceRule>,
+    /// Arable land changes of the states files, applied in order
+    pub arable_land: Vec<ArableLandRule>,
+    /// Amounts of capped resources set in the states files, by state and resource
+    pub capped_resources: BTreeMap<String, BTreeMap<String, u32>>,
+    /// Discoverable resources added to the states files
+    pub discoverable_resources: Vec<DiscoverableResource>,
+    /// Discoverable resources added to states picked at random, which are placed into
+    /// `discoverable_resources` before generating the states files
+    pub random_deposits: Vec<RandomDeposits>,
+    /// Seed for randomly picking the states of `random_deposits`
+    pub deposit_seed: u64,
+    /// State traits added to the states files, by state
+    pub state_traits: BTreeMap<String, Vec<String>>,
+    /// Resources of single states set instead of by the rules, by state
+    pub state_overrides: BTreeMap<String, StateOverride>,
+    /// Homeland, claim and ownership changes of the state history files, applied in order
+    pub state_history: Vec<StateHistoryRule>,
+    /// Names of states files that are left alone
+    pub states_exclude: GlobSet,
+    /// Leave alone states files whose states are all sea states
+    pub skip_sea_states: bool,
+    /// Countries whose buildings are never split
+    pub exclude_countries: Vec<String>,
+    /// The only countries whose buildings are split, if given
+    pub include_countries: Option<Vec<String>>,
+    /// Warn about owners of buildings whose country the game doesn't define
+    pub warn_unknown_countries: bool,
+    /// Buildings that are never split, even if a rule matches them
+    pub never_touch: Vec<String>,
+    /// Buildings owning other buildings, which are rescaled when the levels they own change
+    pub ownership_buildings: Vec<String>,
+    /// Owning building types replaced in the ownership of modded buildings,
+    /// of which the first matching one is used
+    pub owner_remaps: Vec<OwnerRemap>,
+    /// Output layouts by the generator they apply to
+    pub outputs: HashMap<String, OutputLayout>,
+    pub localization: LocalizationConfig,
+    /// Production methods of the modded buildings, by their key
+    pub production_methods: BTreeMap<String, ProductionMethodRule>,
+    /// The game's building that the definitions of the modded buildings are cloned from,
+    /// instead of the buildings they are split off from
+    pub building_template: Option<String>,
+    /// Fields of the generated definitions of modded buildings, by building
+    pub building_definitions: BTreeMap<String, BuildingDefinition>,
+    pub script: Option<SplitScript>,
+    /// Definitions from the game's files, only loaded if a rule needs them
+    pub game_data: GameData,
+}
+
+impl Config {
+    pub fn load(args: &ConfigArgs) -> anyhow::Result<Self> {
+        let (path, contents) = match &args.ratios {
+            Some(path) => (path.as_path(), std::fs::read_to_string(path)?),
+            None if Path::new(RATIOS_FILE).is_file() => (
+                Path::new(RATIOS_FILE),
+                std::fs::read_to_string(RATIOS_FILE)?,
+            ),
+            None => (Path::new(RATIOS_FILE), DEFAULT_RATIOS.to_string()),
+        };
+        let mut ratios = parse_ratios(path, &contents)?;
+        let mut problems = Problems::new(path, &contents);
+
+        let mut splits = load_splits(ratios.split, &mut problems);
+        let mut region_state_keys = ratios.region_state_keys;
+        if let Some(game_version) = args.game_version.as_ref().or(ratios.game_version.as_ref()) {
+            let Some(version) = ratios.version.remove(game_version) else {
+                let mut known: Vec<&String> = ratios.version.keys().collect();
+                known.sort();
+                anyhow::bail!(
+                    "No ratios for game version {}, known versions are: {}",
+                    game_version,
+                    known
+                        .iter()
+                        .map(|version| version.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            };
+            splits.extend(load_splits(version.split, &mut problems));
+            region_state_keys = version.region_state_keys.unwrap_or(region_state_keys);
+        }
+        for rule in &ratios.resource {
+            let location = match &rule.name {
+                Some(name) => problems.locate("name", name),
+                None => problems.locate("when", rule.when.first().map_or("", String::as_str)),
+            };
+            for problem in rule.check() {
+                problems.push(location, problem);
+            }
+        }
+        for rule in &ratios.arable_land {
+            let location = match rule.states.first().or(rule.strategic_regions.first()) {
+                Some(key) => problems.locate("", key),
+                None => None,
+            };
+            for problem in rule.check() {
+                problems.push(location, problem);
+            }
+        }
+        for (state, state_resources) in &ratios.capped_resources {
+            if !state.starts_with("STATE_") {
+                let location = problems.locate("", state);
+                problems.push(
+                    location,
+                    format!("Capped resources for unknown state {:?}", state),
+                );
+            }
+            for resource in state_resources.keys() {
+                if !resource.starts_with("bg_") || !is_identifier(resource) {
+                    let location = problems.locate("", resource);
+                    problems.push(
+                        location,
+                        format!("Unknown building group identifier {:?}", resource),
+                    );
+                }
+            }
+        }
+        for (state, traits) in &ratios.state_traits {
+            if !state.starts_with("STATE_") {
+                let location = problems.locate("", state);
+                problems.push(
+                    location,
+                    format!("State traits for unknown state {:?}", state),
+                );
+            }
+            for state_trait in traits {
+                if !is_identifier(state_trait) {
+                    let location = problems.locate("", state_trait);
+                    problems.push(
+                        location,
+                        format!("Unknown state trait identifier {:?}", state_trait),
+                    );
+                }
+            }
+        }
+        for resource in &ratios.discoverable_resource {
+            let location = problems.locate("type", &resource.r#type);
+            for problem in resource.check() {
+                problems.push(location, problem);
+            }
+        }
+        for deposits in &ratios.random_deposits {
+            let location = problems.locate("type", &deposits.r#type);
+            for problem in deposits.check() {
+                problems.push(location, problem);
+            }
+        }
+        for rule in &ratios.state_history {
+            let location = match rule.states.first().or(rule.strategic_regions.first()) {
+                Some(key) => problems.locate("", key),
+                None => None,
+            };
+            for problem in rule.check() {
+                problems.push(location, problem);
+            }
+        }
+        let mut resources = ratios.resource;
+
+        if let Some(name) = args.profile.as_ref().or(ratios.profile.as_ref()) {
+            let Some(profile) = ratios.profiles.get(name) else {
+                anyhow::bail!("No profile named {}", name);
+            };
+            if let Some(enabled) = &profile.splits {
+                for unknown in enabled
+                    .iter()
+                    .filter(|source| !splits.contains_key(*source))
+                {
+                    let location = problems.locate("", unknown);
+                    problems.push(
+                        location,
+                        format!(
+                            "Profile {} enables a split of {}, which has no rule",
+                            name, unknown
+                        ),
+                    );
+                }
+                splits.retain(|source, _| enabled.contains(source));
+            }
+            if let Some(enabled) = &profile.resources {
+                for unknown in enabled.iter().filter(|enabled| {
+                    !resources
+                        .iter()
+                        .any(|rule| rule.name.as_ref() == Some(*enabled))
+                }) {
+                    let location = problems.locate("", unknown);
+                    problems.push(
+                        location,
+                        format!(
+                            "Profile {} enables resource rule {}, which doesn't exist",
+                            name, unknown
+                        ),
+                    );
+                }
+                resources.retain(|rule| {
+                    rule.name
+                        .as_ref()
+                        .is_some_and(|rule_name| enabled.contains(rule_name))
+                });
+            }
+        }
+        if !ratios.exclude_countries.is_empty() && ratios.include_countries.is_some() {
+            let location = problems.locate("", "include_countries");
+            problems.push(
+                location,
+                "Pass either `exclude_countries` or `include_countries`, not both",
+            );
+        }
+        for building in ratios.never_touch.iter().chain(&ratios.ownership_buildings) {
+            if !building.starts_with("building_") || !is_identifier(building) {
+                let location = problems.locate("", building);
+                problems.push(
+                    location,
+                    format!("Unknown building identifier {:?}", building),
+                );
+            }
+        }
+        for remap in &ratios.owner_remap {
+            let location = problems.locate("from", &remap.from);
+            for building in [&remap.from, &remap.to].into_iter().chain(&remap.targets) {
+                if !building.starts_with("building_") || !is_identifier(building) {
+                    problems.push(
+                        location,
+                        format!("Unknown building identifier {:?}", building),
+                    );
+                }
+            }
+        }
+        for generator in ratios.output.keys() {
+            if !GENERATORS.contains(&generator.as_str()) {
+                let location = problems.locate("", generator);
+                problems.push(
+                    location,
+                    format!(
+                        "Output layout for unknown generator {}, expected one of: {}",
+                        generator,
+                        GENERATORS.join(", ")
+                    ),
+                );
+            }
+        }
+        let mut production_methods = BTreeMap::new();
+        for rule in ratios.production_method {
+            let location = problems.locate("name", &rule.name);
+            for problem in rule.check() {
+                problems.push(location, problem);
+            }
+            if production_methods.contains_key(&rule.name) {
+                problems.push(
+                    location,
+                    format!(
+                        "Production method {} is generated more than once",
+                        rule.name
+                    ),
+                );
+            }
+            production_methods.insert(rule.name.clone(), rule);
+        }
+        if let Some(building) = &ratios.building_template
+            && (!building.starts_with("building_") || !is_identifier(building))
+        {
+            let location = problems.locate("building_template", building);
+            problems.push(
+                location,
+                format!("Unknown building identifier {:?}", building),
+            );
+        }
+        for (building, definition) in &ratios.building_definition {
+            for problem in definition.check(building) {
+                let location = problems.locate("", building);
+                problems.push(location, problem);
+            }
+        }
+        for problem in ratios.localization.check() {
+            let location = problems.locate("", "localization");
+            problems.push(location, problem);
+        }
+        let states_exclude = match build_glob_set(&ratios.states_exclude) {
+            Ok(states_exclude) => states_exclude,
+            Err(err) => {
+                let location =
+                    problems.locate("", ratios.states_exclude.first().map_or("", String::as_str));
+                problems.push(
+                    location,
+                    format!("Invalid `states_exclude` glob: {:#}", err),
+                );
+                GlobSet::empty()
+            }
+        };
+        problems.finish()?;
+
+        let script = match &ratios.script {
+            Some(script) => Some(SplitScript::load(
+                &path.parent().unwrap_or(Path::new("")).join(script),
+            )?),
+            None => None,
+        };
+
+        let state_overrides = match &ratios.state_overrides {
+            Some(state_overrides) => {
+                load_state_overrides(&path.parent().unwrap_or(Path::new("")).join(state_overrides))?
+            }
+            None => BTreeMap::new(),
+        };
+
+        let buildings_mode = args
+            .buildings_mode
+            .or(ratios.buildings_mode)
+            .unwrap_or_default();
+        let sparse = args.sparse.unwrap_or(ratios.sparse);
+        if sparse && buildings_mode == BuildingsMode::Replace {
+            anyhow::bail!(
+                "Sparse buildings files can only patch the game's buildings, not replace its files"
+            );
+        }
+
+        Ok(Self {
+            splits,
+            rounding: args.rounding.or(ratios.rounding).unwrap_or_default(),
+            owner_seed: args.owner_seed.or(ratios.owner_seed),
+            duplicates: ratios.duplicates,
+            reserves: ratios.reserves,
+            exact_levels: ratios.exact_levels,
+            buildings_mode,
+            states_mode: args.states_mode.or(ratios.states_mode).unwrap_or_default(),
+            states_grouping: ratios.states_grouping,
+            sparse,
+            keep_going: args.keep_going.unwrap_or(ratios.keep_going),
+            quotes: ratios.quotes,
+            region_state_keys,
+            line_endings: ratios.line_endings,
+            resources,
+            arable_land: ratios.arable_land,
+            capped_resources: ratios.capped_resources,
+            discoverable_resources: ratios.discoverable_resource,
+            random_deposits: ratios.random_deposits,
+            deposit_seed: args.deposit_seed.unwrap_or(ratios.deposit_seed),
+            state_traits: ratios.state_traits,
+            state_overrides,
+            state_history: ratios.state_history,
+            states_exclude,
+            skip_sea_states: ratios.skip_sea_states,
+            exclude_countries: ratios.exclude_countries,
+            include_countries: ratios.include_countries,
+            warn_unknown_countries: ratios.warn_unknown_countries,
+            never_touch: ratios.never_touch,
+            ownership_buildings: ratios.ownership_buildings,
+            owner_remaps: ratios.owner_remap,
+            outputs: ratios.output,
+            localization: ratios.localization,
+            production_methods,
+            building_template: ratios.building_template,
+            building_definitions: ratios.building_definition,
+            script,
+            game_data: GameData::default(),
+        })
+    }
+
+    /// Whether buildings in the part of a state owned by `region_state` are split,
+    /// which may be given with or without its `region_state:` prefix
+    pub fn splits_country(&self, region_state: &str) -> bool {
+        let region_state = region_state_tag(region_state);
+        !self.exclude_countries.iter().any(|tag| tag == region_state)
+            && self
+                .include_countries
+                .as_ref()
+                .is_none_or(|tags| tags.iter().any(|tag| tag == region_state))
+    }
+
+    /// Returns the production methods generated for the modded buildings by their key, with
+    /// the production method each is cloned from: those of the `production_method` rules, and
+    /// every production method a split target's production methods map one to that isn't
+    /// `defined` by the game. The first of several production methods mapped to the same one
+    /// by name is the one it is cloned from.
+    pub fn generated_production_methods(
+        &self,
+        defined: impl Fn(&str) -> bool,
+    ) -> BTreeMap<&str, (&str, Option<&ProductionMethodRule>)> {
+        let mut mapped: BTreeMap<&str, &str> = BTreeMap::new();
+        for rule in self.splits.values() {
+            let targets = rule.targets.iter().chain(
+                rule.overrides
+                    .iter()
+                    .flat_map(|ratio_override| &ratio_override.targets),
+            );
+            for (from, to) in targets.flat_map(|target| &target.production_methods) {
+                let source = mapped.entry(to).or_insert(from);
+                *source = (*source).min(from.as_str());
+            }
+        }
+
+        let mut generated = BTreeMap::new();
+        for (to, from) in mapped {
+            if !defined(to) {
+                generated.insert(to, (from, self.production_methods.get(to)));
+            }
+        }
+        for (name, rule) in &self.production_methods {
+            if let Some(source) = &rule.source {
+                generated.insert(name.as_str(), (source.as_str(), Some(rule)));
+            }
+        }
+        generated
+    }
+
+    /// Returns the modded buildings whose definitions are generated by their key, with the
+    /// game's building each is cloned from: every target of the split rules and every building
+    /// of `building_definitions`, cloned from the `template` of its definition, or otherwise
+    /// the config's `building_template`, or the first building by name it is split off from.
+    /// Buildings without a template have none.
+    pub fn building_templates(&self) -> BTreeMap<&str, Option<&str>> {
+        let mut sources: BTreeMap<&str, &str> = BTreeMap::new();
+        for rule in self.splits.values() {
+            let targets = rule.targets.iter().chain(
+                rule.overrides
+                    .iter()
+                    .flat_map(|ratio_override| &ratio_override.targets),
+            );
+            for target in targets {
+                let source = sources
+                    .entry(target.building.as_str())
+                    .or_insert(&rule.source);
+                *source = (*source).min(rule.source.as_str());
+            }
+        }
+
+        let buildings = sources
+            .keys()
+            .copied()
+            .chain(self.building_definitions.keys().map(String::as_str))
+            .collect::<BTreeSet<_>>();
+        buildings
+            .into_iter()
+            .map(|building| {
+                let template = self
+                    .building_definitions
+                    .get(building)
+                    .and_then(|definition| definition.template.as_deref())
+                    .or(self.building_template.as_deref())
+                    .or(sources.get(building).copied());
+                (building, template)
+            })
+            .collect()
+    }
+
+    /// Returns the output layout of `generator`, which is empty if the config has none
+    pub fn output_layout(&self, generator: &str) -> OutputLayout {
+        self.outputs.get(generator).cloned().unwrap_or_default()
+    }
+
+    /// Returns the building type that owns levels of `target` split off from `source`
+    /// where they were owned by `owner_type`, in a region that has a `target` if
+    /// `target_in_region`
+    pub fn owner_type_for<'a>(
+        &'a self,
+        owner_type: &'a str,
+        source: &str,
+        target: &'a str,
+        target_in_region: bool,
+    ) -> &'a str {
+        let remap = self.owner_remaps.iter().find(|remap| {
+            remap.from == owner_type
+                && (remap.targets.is_empty() || remap.targets.iter().any(|t| t == target))
+        });
+        match remap {
+            Some(remap) => &remap.to,
+            // Buildings owning themselves own the modded building instead, if there is one
+            None if owner_type == source && target_in_region => target,
+            None => owner_type,
+        }
+    }
+
+    /// Checks that the buildings of the split rules are defined by `mod_dir`, or the game's
+    /// `game` directory for the buildings that are split, if the mod defines buildings at all.
+    /// The `generated` buildings are defined by the generated files, which may not be written.
+    pub fn check_buildings(
+        &self,
+        game_dir: Option<&Path>,
+        mod_dir: &Path,
+        generated: &[String],
+    ) -> anyhow::Result<()> {
+        let Some(mut defined) = building_definitions(mod_dir)? else {
+            return Ok(());
+        };
+        defined.extend(generated.iter().cloned());
+        let game_buildings = match game_dir {
+            Some(game_dir) => building_definitions(game_dir)?,
+            None => None,
+        };
+        let check_sources = game_buildings.is_some();
+        defined.extend(game_buildings.into_iter().flatten());
+
+        let mut rules = self.splits.values().collect::<Vec<_>>();
+        rules.sort_by(|a, b| a.source.cmp(&b.source));
+        let mut missing = Vec::new();
+        for rule in rules {
+            if check_sources && !defined.contains(&rule.source) {
+                missing.push(format!("{} (split by a rule)", rule.source));
+            }
+            let targets = rule.targets.iter().chain(
+                rule.overrides
+                    .iter()
+                    .flat_map(|ratio_override| &ratio_override.targets),
+            );
+            for target in targets {
+                let problem = format!("{} (split off from {})", target.building, rule.source);
+                if !defined.contains(&target.building) && !missing.contains(&problem) {
+                    missing.push(problem);
+                }
+            }
+        }
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Buildings of the split rules aren't defined in common/buildings of {}, \
+                 which the buildings-def command can generate: {}",
+                mod_dir.display(),
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads the definitions the rules depend on from the game's `game` directory,
+    /// if any of them or the checks of owner countries need them
+    pub fn load_game_data(&mut self, game_dir: Option<&Path>) -> anyhow::Result<()> {
+        if !self.warn_unknown_countries
+            && !self.splits.values().any(SplitRule::needs_game_data)
+            && self
+                .arable_land
+                .iter()
+                .all(|rule| rule.strategic_regions.is_empty())
+            && self
+                .discoverable_resources
+                .iter()
+                .all(|resource| resource.strategic_regions.is_empty())
+            && self.resources.iter().all(|rule| rule.terrains.is_empty())
+            && !self
+                .random_deposits
+                .iter()
+                .any(RandomDeposits::needs_game_data)
+            && self
+                .state_history
+                .iter()
+                .all(|rule| rule.strategic_regions.is_empty())
+            && self.states_grouping != StatesGrouping::StrategicRegion
+        {
+            return Ok(());
+        }
+        let Some(game_dir) = game_dir else {
+            anyhow::bail!(
+                "The config depends on definitions from the game's files, pass --game-dir \
+                 or an input path inside the game directory"
+            );
+        };
+        self.game_data = GameData::load(game_dir)?;
+
+        // Rules for building groups apply to each building of the group without its own rule
+        let mut group_rules = self
+            .splits
+            .extract_if(|_, rule| rule.is_group_rule())
+            .map(|(_, rule)| rule)
+            .collect::<Vec<_>>();
+        group_rules.sort_by(|a, b| a.source.cmp(&b.source));
+        for rule in group_rules {
+            let buildings = self.game_data.buildings_in_group(&rule.source);
+            if buildings.is_empty() {
+                anyhow::bail!("Building group {} has no buildings", rule.source);
+            }
+            for building in buildings {
+                let is_target = rule
+                    .targets
+                    .iter()
+                    .any(|target| target.building == building);
+                if !is_target && !self.splits.contains_key(&building) {
+                    let building_rule = SplitRule {
+                        source: building.clone(),
+                        ..rule.clone()
+                    };
+                    self.splits.insert(building, building_rule);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks and normalizes `rules`, keyed by their source building.
+/// Rules with problems are recorded in `problems` and left out.
+fn load_splits(rules: Vec<SplitRule>, problems: &mut Problems) -> HashMap<String, SplitRule> {
+    let mut splits = HashMap::new();
+    for mut rule in rules {
+        let location = problems.locate("source", &rule.source);
+        let mut rule_problems = rule.check();
+        if let Err(err) = rule.normalize() {
+            rule_problems.push(err.to_string());
+        }
+        if splits.contains_key(&rule.source) {
+            rule_problems.push(format!("Building {} is split more than once", rule.source));
+        }
+
+        if rule_problems.is_empty() {
+            splits.insert(rule.source.clone(), rule);
+        }
+        for problem in rule_problems {
+            problems.push(location, problem);
+        }
+    }
+    splits
+}
+
+/// Whether `name` looks like a game identifier, such as `building_textile_mill`
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Problems found in a config file, which are all reported together
+/// with the line and column they were found at
+struct Problems<'a> {
+    path: &'a Path,
+    contents: &'a str,
+    /// How often each key and value have been located, to find repeated values in order
+    located: HashMap<(String, String), usize>,
+    messages: Vec<String>,
+}
+
+impl<'a> Problems<'a> {
+    fn new(path: &'a Path, contents: &'a str) -> Self {
+        Self {
+            path,
+            contents,
+            located: HashMap::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    /// Returns the line and column of the next quoted `value` not located yet
+    /// that is on a line mentioning `key`, if there is one
+    fn locate(&mut self, key: &str, value: &str) -> Option<(usize, usize)> {
+        let quoted = format!("\"{}\"", value);
+        let skip = self
+            .located
+            .entry((key.to_string(), quoted.clone()))
+            .or_default();
+        let location = self
+            .contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.contains(key))
+            .filter_map(|(index, line)| Some((index + 1, line.find(&quoted)? + 1)))
+            .nth(*skip);
+        *skip += 1;
+        location
+    }
+
+    fn push(&mut self, location: Option<(usize, usize)>, message: impl std::fmt::Display) {
+        self.messages.push(match location {
+            Some((line, column)) => {
+                format!("{}:{}:{}: {}", self.path.display(), line, column, message)
+            }
+            None => format!("{}: {}", self.path.display(), message),
+        });
+    }
+
+    /// Fails with every problem found, if there were any
+    fn finish(self) -> anyhow::Result<()> {
+        if !self.messages.is_empty() {
+            anyhow::bail!(
+                "{} problems in {}:\n{}",
+                self.messages.len(),
+                self.path.display(),
+                self.messages.join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Reads the state overrides file at `path`
+fn load_state_overrides(path: &Path) -> anyhow::Result<BTreeMap<String, StateOverride>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Can't read {}: {}", path.display(), err))?;
+    let state_overrides: BTreeMap<String, StateOverride> = toml::from_str(&contents)
+        .map_err(|err| anyhow::anyhow!("Invalid {}: {}", path.display(), err))?;
+    for (state, state_override) in &state_overrides {
+        if !state.starts_with("STATE_") {
+            anyhow::bail!("Invalid {}: unknown state {:?}", path.display(), state);
+        }
+        let resources = state_override.arable_resources.iter().flatten();
+        let capped = state_override
+            .capped_resources
+            .iter()
+            .flat_map(BTreeMap::keys);
+        if let Some(resource) = resources.chain(capped).find(|r| !is_identifier(r)) {
+            anyhow::bail!(
+                "Invalid {}: unknown resource identifier {:?} of {}",
+                path.display(),
+                resource,
+                state
+            );
+        }
+    }
+    Ok(state_overrides)
+}
+
+/// Parses the ratios config `contents`, as JSON if `path` has a `.json` extension
+/// and as TOML otherwise
+fn parse_ratios(path: &Path, contents: &str) -> anyhow::Result<RatiosFile> {
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(contents)
+            .map_err(|err| anyhow::anyhow!("Invalid {}: {}", path.display(), err))
+    } else {
+        toml::from_str(contents)
+            .map_err(|err| anyhow::anyhow!("Invalid {}: {}", path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_expression_ratio() {
+        assert_eq!(
+            parse_levels_expression("x", "1 per 4 levels").unwrap(),
+            (4.0, None, None)
+        );
+        assert_eq!(
+            parse_levels_expression("x", "2 per 5").unwrap(),
+            (2.5, None, None)
+        );
+        assert_eq!(
+            parse_levels_expression("x", "1 per 1 level").unwrap(),
+            (1.0, None, None)
+        );
+    }
+
+    #[test]
+    fn levels_expression_bounds() {
+        assert_eq!(
+            parse_levels_expression("x", "1 per 4 levels, min 1, max 10").unwrap(),
+            (4.0, Some(1), Some(10))
+        );
+        assert_eq!(
+            parse_levels_expression("x", " 1 per 4 levels ,max 3").unwrap(),
+            (4.0, None, Some(3))
+        );
+    }
+
+    #[test]
+    fn levels_expression_invalid() {
+        for expression in [
+            "",
+            "1 of 4 levels",
+            "0 per 4 levels",
+            "a per 4 levels",
+            "1 per 4 levels, min 1, min 2",
+            "1 per 4 levels, least 1",
+            "1 per 4 levels, max -1",
+        ] {
+            assert!(
+                parse_levels_expression("x", expression).is_err(),
+                "{:?}",
+                expression
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use clap::Command;
+use std::path::Path;
+
+/// Config file in the working directory providing default command line arguments
+pub const DEFAULTS_FILE: &str = "automate.toml";
+
+/// Sets the arguments in `automate.toml`, if it exists, as defaults of `command`,
+/// so that arguments given on the command line still take precedence.
+///
+/// Top level keys apply to every subcommand with an argument of that name,
+/// while keys in a `[buildings]`, `[states]` or `[all]` table only apply to that subcommand.
+/// Keys are argument names with underscores, e.g. `output_path` or `dry_run`.
+pub fn apply_defaults(command: Command) -> anyhow::Result<Command> {
+    let path = Path::new(DEFAULTS_FILE);
+    if !path.is_file() {
+        return Ok(command);
+    }
+    let table = std::fs::read_to_string(path)?
+        .parse::<toml::Table>()
+        .map_err(|err| anyhow::anyhow!("Invalid {}: {}", DEFAULTS_FILE, err))?;
+
+    let subcommands = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect::<Vec<_>>();
+    let mut command = command;
+    for (key, value) in &table {
+        if let toml::Value::Table(subcommand_table) = value {
+            if !subcommands.contains(key) {
+                anyhow::bail!("Unknown subcommand `{}` in {}", key, DEFAULTS_FILE);
+            }
+            for (arg, value) in subcommand_table {
+                command = set_default(command, key, arg, value)?
+                    .ok_or_else(|| unknown_argument(arg, Some(key)))?;
+            }
+        } else {
+            let mut applied = false;
+            for subcommand in &subcommands {
+                if let Some(updated) = set_default(command.clone(), subcommand, key, value)? {
+                    command = updated;
+                    applied = true;
+                }
+            }
+            if !applied {
+                return Err(unknown_argument(key, None));
+            }
+        }
+    }
+
+    Ok(command)
+}
+
+/// Sets `value` as the default of the argument `arg` of `subcommand`,
+/// returning `None` if the subcommand has no such argument
+fn set_default(
+    command: Command,
+    subcommand: &str,
+    arg: &str,
+    value: &toml::Value,
+) -> anyhow::Result<Option<Command>> {
+    let has_arg = command
+        .find_subcommand(subcommand)
+        .is_some_and(|subcommand| {
+            subcommand
+                .get_arguments()
+                .any(|argument| argument.get_id().as_str() == arg)
+        });
+    if !has_arg {
+        return Ok(None);
+    }
+
+    // clap requires default values to live for the rest of the program
+    let values = match value {
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| default_value_string(arg, value).map(leak))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        value => vec![leak(default_value_string(arg, value)?)],
+    };
+    Ok(Some(command.mut_subcommand(subcommand, |subcommand| {
+        subcommand.mut_arg(arg, |argument| {
+            argument.required(false).default_values(values)
+        })
+    })))
+}
+
+fn leak(value: String) -> &'static str {
+    value.leak()
+}
+
+fn default_value_string(arg: &str, value: &toml::Value) -> anyhow::Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => anyhow::bail!(
+            "Unsupported value for `{}` in {}, expected a string, number, boolean or array",
+            arg,
+            DEFAULTS_FILE
+        ),
+    }
+}
+
+fn unknown_argument(arg: &str, subcommand: Option<&str>) -> anyhow::Error {
+    match subcommand {
+        Some(subcommand) => anyhow::anyhow!(
+            "Unknown argument `{}` for `{}` in {}",
+            arg,
+            subcommand,
+            DEFAULTS_FILE
+        ),
+        None => anyhow::anyhow!("Unknown argument `{}` in {}", arg, DEFAULTS_FILE),
+    }
+}
@@ -0,0 +1,200 @@
+use crate::pops::state_populations;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tiger_lib::FileKind;
+use tiger_lib::block::Block;
+use tiger_lib::fileset::{FileEntry, FileStage};
+use tiger_lib::parse::ParserMemory;
+use tiger_lib::pdxfile::PdxFile;
+
+/// Directory of the strategic region files, relative to the game's `game` directory
+const STRATEGIC_REGIONS_GAME_DIR: &str = "common/strategic_regions";
+/// Directory of the country definition files, relative to the game's `game` directory
+const COUNTRY_DEFINITIONS_GAME_DIR: &str = "common/country_definitions";
+/// Directory of the building group definition files, relative to the game's `game` directory
+const BUILDING_GROUPS_GAME_DIR: &str = "common/building_groups";
+/// Directory of the state region files, relative to the game's `game` directory
+const STATE_REGIONS_GAME_DIR: &str = "map_data/state_regions";
+/// Terrain of each province, relative to the game's `game` directory
+const PROVINCE_TERRAINS_FILE: &str = "map_data/province_terrains.txt";
+/// Directory of the building definition files, relative to the game's `game` directory
+/// or the root of a mod
+const BUILDINGS_DEFINITIONS_DIR: &str = "common/buildings";
+
+/// Definitions from the game's files that rules can depend on
+#[derive(Default)]
+pub struct GameData {
+    /// Strategic region of each `STATE_*` key
+    pub strategic_regions: HashMap<String, String>,
+    /// Tags of every defined country
+    pub countries: HashSet<String>,
+    /// Tags of the countries defined as decentralized
+    pub decentralized: HashSet<String>,
+    /// Starting population of each `STATE_*` key
+    pub populations: HashMap<String, u64>,
+    /// Arable land of each `STATE_*` key
+    pub arable_land: HashMap<String, u32>,
+    /// Building group of each building
+    pub building_groups: HashMap<String, String>,
+    /// Parent group of each building group that is part of another
+    pub group_parents: HashMap<String, String>,
+    /// Terrain of each province, by its uppercase `x*` key
+    pub province_terrains: HashMap<String, String>,
+}
+
+impl GameData {
+    /// Reads the definitions from the game's `game` directory
+    pub fn load(game_dir: &Path) -> anyhow::Result<Self> {
+        let mut game_data = Self::default();
+        for path in definition_files(&game_dir.join(STRATEGIC_REGIONS_GAME_DIR))? {
+            for (region, region_block) in read_pdx_file(&path)?.iter_definitions() {
+                let Some(states) = region_block.get_field_block("states") else {
+                    continue;
+                };
+                for state in states.iter_values() {
+                    game_data
+                        .strategic_regions
+                        .insert(state.to_string(), region.to_string());
+                }
+            }
+        }
+        for path in definition_files(&game_dir.join(COUNTRY_DEFINITIONS_GAME_DIR))? {
+            for (tag, country) in read_pdx_file(&path)?.iter_definitions() {
+                game_data.countries.insert(tag.to_string());
+                if country
+                    .get_field_value("country_type")
+                    .is_some_and(|country_type| country_type.as_str() == "decentralized")
+                {
+                    game_data.decentralized.insert(tag.to_string());
+                }
+            }
+        }
+        for path in definition_files(&game_dir.join(STATE_REGIONS_GAME_DIR))? {
+            for (state, state_block) in read_pdx_file(&path)?.iter_definitions() {
+                let Some(arable_land) = state_block.get_field_value("arable_land") else {
+                    continue;
+                };
+                let arable_land = arable_land.as_str().parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid arable_land {} of {}", arable_land.as_str(), state)
+                })?;
+                game_data.arable_land.insert(state.to_string(), arable_land);
+            }
+        }
+        for path in definition_files(&game_dir.join(BUILDINGS_DEFINITIONS_DIR))? {
+            for (building, building_block) in read_pdx_file(&path)?.iter_definitions() {
+                if let Some(group) = building_block.get_field_value("building_group") {
+                    game_data
+                        .building_groups
+                        .insert(building.to_string(), group.to_string());
+                }
+            }
+        }
+        for path in definition_files(&game_dir.join(BUILDING_GROUPS_GAME_DIR))? {
+            for (group, group_block) in read_pdx_file(&path)?.iter_definitions() {
+                if let Some(parent) = group_block.get_field_value("parent_group") {
+                    game_data
+                        .group_parents
+                        .insert(group.to_string(), parent.to_string());
+                }
+            }
+        }
+        let province_terrains = game_dir.join(PROVINCE_TERRAINS_FILE);
+        if province_terrains.is_file() {
+            for (province, terrain) in read_pdx_file(&province_terrains)?.iter_assignments() {
+                game_data
+                    .province_terrains
+                    .insert(province.as_str().to_uppercase(), terrain.to_string());
+            }
+        }
+        game_data.populations = state_populations(game_dir)?;
+        Ok(game_data)
+    }
+
+    /// The buildings of `group` or any of the groups that are part of it, sorted
+    pub fn buildings_in_group(&self, group: &str) -> Vec<String> {
+        let mut buildings = self
+            .building_groups
+            .iter()
+            .filter(|(_, building_group)| self.is_part_of(building_group, group))
+            .map(|(building, _)| building.clone())
+            .collect::<Vec<_>>();
+        buildings.sort();
+        buildings
+    }
+
+    /// The terrain most of the `provinces` of a state have, if any of them have a known terrain
+    pub fn state_terrain<'a>(&self, provinces: impl Iterator<Item = &'a str>) -> Option<&str> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for province in provinces {
+            if let Some(terrain) = self.province_terrains.get(&province.to_uppercase()) {
+                *counts.entry(terrain).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+            .map(|(terrain, _)| terrain)
+    }
+
+    /// Whether `building_group` is `group` or part of it
+    fn is_part_of<'a>(&'a self, mut building_group: &'a str, group: &str) -> bool {
+        loop {
+            if building_group == group {
+                return true;
+            }
+            match self.group_parents.get(building_group) {
+                Some(parent) => building_group = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Keys of the buildings defined by `dir`, the game's `game` directory or the root of a mod,
+/// or `None` if it doesn't define buildings
+pub fn building_definitions(dir: &Path) -> anyhow::Result<Option<HashSet<String>>> {
+    let dir = dir.join(BUILDINGS_DEFINITIONS_DIR);
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let mut buildings = HashSet::new();
+    for path in definition_files(&dir)? {
+        for (building, _) in read_pdx_file(&path)?.iter_definitions() {
+            buildings.insert(building.to_string());
+        }
+    }
+    Ok(Some(buildings))
+}
+
+/// The game's `game` directory that `input_path` was read from, if it is inside one
+pub fn game_dir_of(input_path: &Path) -> Option<PathBuf> {
+    input_path
+        .ancestors()
+        .find(|dir| dir.join("common").is_dir() && dir.join("map_data").is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// Parses the script file at `path`
+pub fn read_pdx_file(path: &Path) -> anyhow::Result<Block> {
+    let parser = ParserMemory::default();
+    let file_entry = FileEntry::new(
+        path.to_path_buf(),
+        FileStage::NoStage,
+        FileKind::Vanilla,
+        path.to_path_buf(),
+    );
+    PdxFile::read(&file_entry, &parser).ok_or_else(|| anyhow::anyhow!("No file contents parsed"))
+}
+
+/// The `.txt` files of the definition directory `dir`, in the order the game loads them
+pub fn definition_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        anyhow::bail!("Game directory is missing {}", dir.display());
+    }
+    let mut files = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    files.retain(|path| path.extension().is_some_and(|ext| ext == "txt"));
+    files.sort();
+    Ok(files)
+}
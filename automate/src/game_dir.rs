@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+/// Name of the game's directory in a Steam library's `steamapps/common`
+const STEAM_GAME_DIR: &str = "Victoria 3";
+
+/// Returns the game's `game` content directory, from `game_dir` if given or otherwise from
+/// the detected Steam install. `game_dir` may be the install directory or its `game` subdirectory.
+pub fn resolve_game_dir(game_dir: Option<&Path>) -> anyhow::Result<PathBuf> {
+    let install_dir = match game_dir {
+        Some(game_dir) => game_dir.to_path_buf(),
+        None => detect_install_dir().ok_or_else(|| {
+            anyhow::anyhow!("Could not find the Victoria 3 install, pass --game-dir")
+        })?,
+    };
+
+    if install_dir.join("game").is_dir() {
+        Ok(install_dir.join("game"))
+    } else if install_dir.is_dir() {
+        Ok(install_dir)
+    } else {
+        anyhow::bail!("Game directory {} does not exist", install_dir.display());
+    }
+}
+
+/// Looks for Victoria 3 in every Steam library on this machine
+fn detect_install_dir() -> Option<PathBuf> {
+    steam_dirs()
+        .into_iter()
+        .flat_map(|steam_dir| steam_libraries(&steam_dir))
+        .map(|library| {
+            library
+                .join("steamapps")
+                .join("common")
+                .join(STEAM_GAME_DIR)
+        })
+        .find(|install_dir| install_dir.join("game").is_dir())
+}
+
+/// Default install locations of Steam itself, including the Flatpak install on Linux.
+/// When the game is run through Proton its files are still installed in these libraries.
+pub fn steam_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        for var in ["ProgramFiles(x86)", "ProgramFiles"] {
+            if let Some(program_files) = std::env::var_os(var) {
+                dirs.push(PathBuf::from(program_files).join("Steam"));
+            }
+        }
+    }
+
+    if let Some(home) = dirs::home_dir() {
+        if cfg!(target_os = "macos") {
+            dirs.push(home.join("Library/Application Support/Steam"));
+        } else if !cfg!(windows) {
+            dirs.push(home.join(".steam/steam"));
+            dirs.push(home.join(".local/share/Steam"));
+            dirs.push(home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"));
+        }
+    }
+    dirs.retain(|dir| dir.is_dir());
+    dirs
+}
+
+/// Steam library folders known to the Steam install at `steam_dir`, which always includes itself
+pub fn steam_libraries(steam_dir: &Path) -> Vec<PathBuf> {
+    let mut libraries = vec![steam_dir.to_path_buf()];
+    let Ok(library_folders) =
+        std::fs::read_to_string(steam_dir.join("steamapps").join("libraryfolders.vdf"))
+    else {
+        return libraries;
+    };
+
+    // Entries look like `"path"		"D:\\SteamLibrary"`
+    for line in library_folders.lines() {
+        let mut parts = line.split('"').filter(|part| !part.trim().is_empty());
+        if parts.next() == Some("path")
+            && let Some(path) = parts.next()
+        {
+            libraries.push(PathBuf::from(path.replace("\\\\", "\\")));
+        }
+    }
+    libraries
+}
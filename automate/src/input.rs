@@ -0,0 +1,102 @@
+use crate::game_data::game_dir_of;
+use clap::Args;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory of the DLCs, relative to the game's `game` directory
+const DLC_GAME_DIR: &str = "dlc";
+
+#[derive(Args)]
+pub struct InputArgs {
+    /// Only process input files whose names match one of these globs,
+    /// e.g. `*_europe*`
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip input files whose names match one of these globs,
+    /// e.g. `99_seas.txt`
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Also read the input files of the matching directory of every DLC in the game
+    /// directory, layered over the input path in the order of their names
+    #[arg(long)]
+    pub dlc: bool,
+
+    /// Another directory of input files layered over the input path and any DLCs,
+    /// whose files replace the input files of the same name like the game loads them
+    #[arg(long, value_name = "DIR")]
+    pub layer: Vec<PathBuf>,
+}
+
+/// Lists the `.txt` files in `input_path` and the directories layered over it that pass
+/// the include/exclude filters, in a stable order. Files of a later layer replace those
+/// of the same name. A single file given as `input_path` is always processed.
+pub fn input_files(input_path: &Path, args: &InputArgs) -> anyhow::Result<Vec<PathBuf>> {
+    if input_path.is_file() {
+        return Ok(vec![input_path.to_path_buf()]);
+    }
+
+    let include = build_glob_set(&args.include)?;
+    let exclude = build_glob_set(&args.exclude)?;
+
+    let mut paths = BTreeMap::new();
+    for dir in input_dirs(input_path, args)? {
+        for path in std::fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+        {
+            // Subdirectories, such as the staging directory of a run writing to the input
+            // directory, and backups aren't script files
+            if !path.is_file() || path.extension().is_none_or(|ext| ext != "txt") {
+                continue;
+            }
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            if (include.is_empty() || include.is_match(file_name)) && !exclude.is_match(file_name) {
+                paths.insert(file_name.to_owned(), path);
+            }
+        }
+    }
+    Ok(paths.into_values().collect())
+}
+
+/// The directories of input files, `input_path` followed by the layers over it in order
+pub fn input_dirs(input_path: &Path, args: &InputArgs) -> anyhow::Result<Vec<PathBuf>> {
+    let mut dirs = vec![input_path.to_path_buf()];
+    if args.dlc {
+        let Some(game_dir) = game_dir_of(input_path) else {
+            anyhow::bail!(
+                "--dlc needs an input path inside the game directory, got {}",
+                input_path.display()
+            );
+        };
+        let relative = input_path.strip_prefix(&game_dir)?;
+        let dlc_dir = game_dir.join(DLC_GAME_DIR);
+        if dlc_dir.is_dir() {
+            let mut dlcs = std::fs::read_dir(dlc_dir)?
+                .map(|entry| entry.map(|entry| entry.path().join(relative)))
+                .collect::<Result<Vec<_>, _>>()?;
+            dlcs.retain(|dir| dir.is_dir());
+            dlcs.sort();
+            dirs.extend(dlcs);
+        }
+    }
+    for layer in &args.layer {
+        if !layer.is_dir() {
+            anyhow::bail!("Input layer {} is not a directory", layer.display());
+        }
+        dirs.push(layer.clone());
+    }
+    Ok(dirs)
+}
+
+pub fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
@@ -0,0 +1,203 @@
+use crate::BOM_CHAR;
+use crate::config::Config;
+use crate::output::Output;
+use crate::report::{FileReport, Report};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Directory of the localization files, relative to the mod root
+pub const LOCALIZATION_MOD_DIR: &str = "localization";
+
+/// Generates a localization file for each language of the config with the names of the modded
+/// buildings and production methods of the split rules and the goods the config lists.
+/// The files are written into a directory of their language, which must exist, and merged
+/// with the keys of the existing files when merging. Output files are only written if none
+/// of them failed.
+pub fn run_localization(config: &Config, output: &mut Output) -> Report {
+    let languages = &config.localization.languages;
+    let progress = output.begin_run(languages.len());
+    let mut report = Report::default();
+    let entries = localization_entries(config);
+    for language in languages {
+        let out_name = localization_file_name(config, language);
+        let mut file_report = FileReport::new(PathBuf::from(&out_name));
+        if let Err(err) = write_localization_file(language, &entries, &out_name, output) {
+            file_report.error = Some(format!("{:#}", err));
+        } else {
+            file_report.output = Some(out_name);
+        }
+
+        report.files.push(file_report);
+        progress.set_message(format!("{} keys", entries.len()));
+        progress.inc(1);
+    }
+    report.commit(output);
+    progress.finish();
+
+    report
+}
+
+/// Returns the path of the localization file of `language`, relative to the `localization`
+/// directory
+pub fn localization_file_name(config: &Config, language: &str) -> String {
+    format!(
+        "{}/{}_l_{}.yml",
+        language, config.localization.file, language
+    )
+}
+
+/// Returns the localization keys of the modded buildings, production methods and goods
+/// by their names, in the order they are written. Production methods that split buildings
+/// activate are the game's, and aren't localized even if a target activates them too.
+fn localization_entries(config: &Config) -> Vec<(String, String)> {
+    let localization = &config.localization;
+    let mut buildings = BTreeSet::new();
+    let mut production_methods = BTreeSet::new();
+    let mut game_production_methods = BTreeSet::new();
+    for rule in config.splits.values() {
+        let targets = rule.targets.iter().chain(
+            rule.overrides
+                .iter()
+                .flat_map(|ratio_override| &ratio_override.targets),
+        );
+        for target in targets {
+            buildings.insert(target.building.as_str());
+            production_methods.extend(target.production_methods.values().map(String::as_str));
+            game_production_methods.extend(target.production_methods.keys().map(String::as_str));
+        }
+    }
+    // Production methods of the split buildings are the game's, which it already localizes
+    production_methods
+        .retain(|production_method| !game_production_methods.contains(production_method));
+    production_methods.extend(config.production_methods.keys().map(String::as_str));
+
+    let mut entries = Vec::new();
+    let kinds = [
+        (buildings, "building_", &localization.building_name),
+        (
+            production_methods,
+            "pm_",
+            &localization.production_method_name,
+        ),
+        (
+            localization.goods.iter().map(String::as_str).collect(),
+            "",
+            &localization.good_name,
+        ),
+    ];
+    for (keys, prefix, template) in kinds {
+        for key in keys {
+            let name = match localization.names.get(key) {
+                Some(name) => name.clone(),
+                None => template
+                    .replace(
+                        "{name}",
+                        &title_case(key.strip_prefix(prefix).unwrap_or(key)),
+                    )
+                    .replace("{key}", key),
+            };
+            entries.push((key.to_string(), name));
+        }
+    }
+    entries
+}
+
+/// Returns the words of the identifier `name` with their first letters capitalized
+fn title_case(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes the localization file `out_name` of `language` with `entries` to `output`. When
+/// merging into an existing file, its names are kept for the keys it has, and its other keys
+/// are kept after the generated ones.
+fn write_localization_file(
+    language: &str,
+    entries: &[(String, String)],
+    out_name: &str,
+    output: &mut Output,
+) -> anyhow::Result<()> {
+    let mut existing = match output.merge_path(out_name) {
+        Some(path) => read_localization_file(&path)?,
+        None => BTreeMap::new(),
+    };
+    let mut buffer = Vec::new();
+    write!(buffer, "{}", BOM_CHAR)?;
+    writeln!(buffer, "l_{}:", language)?;
+    for (key, name) in entries {
+        match existing.remove(key) {
+            Some(line) => writeln!(buffer, "{}", line)?,
+            None => writeln!(buffer, " {}:0 \"{}\"", key, name.replace('"', "\\\""))?,
+        }
+    }
+    for line in existing.values() {
+        writeln!(buffer, "{}", line)?;
+    }
+    output.write(out_name, &buffer, &format!("{} keys", entries.len()))
+}
+
+/// Reads the lines of the keys of the localization file at `path`, by key
+fn read_localization_file(path: &Path) -> anyhow::Result<BTreeMap<String, String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut lines = BTreeMap::new();
+    for line in contents.trim_start_matches(BOM_CHAR).lines() {
+        let code = line.trim_start();
+        if code.is_empty() || code.starts_with('#') || code.starts_with("l_") {
+            continue;
+        }
+        let Some((key, _)) = code.split_once(':') else {
+            anyhow::bail!("Invalid localization line in {}: {}", path.display(), line);
+        };
+        lines.insert(key.to_string(), line.to_string());
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_case_keys() {
+        assert_eq!(title_case("fruit_orchard"), "Fruit Orchard");
+        assert_eq!(title_case("distillery"), "Distillery");
+        assert_eq!(title_case("_double__underscores_"), "Double Underscores");
+        assert_eq!(title_case(""), "");
+    }
+
+    #[test]
+    fn read_localization_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ir_buildings_l_english.yml");
+        std::fs::write(
+            &path,
+            format!(
+                "{}l_english:\n # Buildings\n building_orchard:0 \"Orchard\"\n\n pm_cider: \"Cider\"\n",
+                BOM_CHAR
+            ),
+        )
+        .unwrap();
+        let lines = read_localization_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines["building_orchard"], " building_orchard:0 \"Orchard\"");
+        assert_eq!(lines["pm_cider"], " pm_cider: \"Cider\"");
+    }
+
+    #[test]
+    fn read_localization_invalid_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ir_buildings_l_english.yml");
+        std::fs::write(&path, "l_english:\n building_orchard \"Orchard\"\n").unwrap();
+        assert!(read_localization_file(&path).is_err());
+    }
+}
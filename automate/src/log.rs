@@ -0,0 +1,279 @@
+use clap::{Args, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[derive(Args, Default)]
+pub struct LogArgs {
+    /// Don't show a progress bar, and only print errors and the run summary
+    #[arg(long)]
+    pub quiet: bool,
+
+    /// Format of the messages printed during the run. `json` prints every event as
+    /// one JSON object per line to stdout, in the order the files were processed,
+    /// and never shows a progress bar.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Something that happened during a run
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Started watching the input path for changes
+    Watching {
+        path: PathBuf,
+    },
+    /// A file would have been written in dry-run mode
+    WouldWrite {
+        path: PathBuf,
+        bytes: usize,
+        lines: usize,
+        summary: String,
+    },
+    /// The changes a generated file would make to the current output
+    Diff {
+        path: PathBuf,
+        diff: String,
+    },
+    /// A generated file is identical to the current output
+    Unchanged {
+        path: PathBuf,
+    },
+    /// A generated file was not written because the output file already exists
+    SkippedExisting {
+        path: PathBuf,
+    },
+    /// Something wrong with an input file that didn't stop it from being processed
+    Warning {
+        input: PathBuf,
+        message: String,
+    },
+    /// An input file was processed, successfully or not
+    File {
+        input: PathBuf,
+        output: Option<String>,
+        splits: usize,
+        states_modified: usize,
+        companies_modified: usize,
+        skipped: usize,
+        warnings: usize,
+        error: Option<String>,
+    },
+    /// Levels of a split building converted into one of its targets, in one input file,
+    /// or in every file if there is no `input`
+    Conversion {
+        input: Option<PathBuf>,
+        building: String,
+        target: String,
+        levels: u32,
+        /// Levels the split buildings had before splitting
+        total_levels: u32,
+    },
+    /// Keys of a state in an input file that were changed
+    StateModified {
+        input: PathBuf,
+        state: String,
+        fields: Vec<String>,
+    },
+    /// Arable resources removed from a state in an input file
+    ResourcesRemoved {
+        input: PathBuf,
+        state: String,
+        resources: Vec<String>,
+    },
+    /// An entry of a split building in an input file that couldn't be split and was left
+    /// as it was
+    InvalidEntry {
+        input: PathBuf,
+        state: String,
+        region_state: String,
+        building: String,
+        reason: String,
+    },
+    /// Totals of a finished run
+    Summary {
+        files_ok: usize,
+        files_skipped: usize,
+        files_failed: usize,
+        entries_skipped: usize,
+        entries_invalid: usize,
+        states_modified: usize,
+        states_unchanged: usize,
+        warnings: usize,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Prints the events of a run in the requested format
+#[derive(Clone)]
+pub struct Log {
+    format: LogFormat,
+    quiet: bool,
+    progress: ProgressBar,
+}
+
+impl Log {
+    pub fn new(args: &LogArgs) -> Self {
+        Self {
+            format: args.log_format,
+            quiet: args.quiet,
+            progress: ProgressBar::hidden(),
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == LogFormat::Json
+    }
+
+    /// Starts a progress bar reporting files processed and elapsed time, which later
+    /// messages are printed around. It is hidden for quiet runs and JSON logs.
+    pub fn start_progress(&mut self, files: usize) -> ProgressBar {
+        self.progress = if self.quiet || self.is_json() {
+            ProgressBar::hidden()
+        } else {
+            let progress = ProgressBar::new(files as u64);
+            progress.set_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] {bar:40} {pos}/{len} files, {msg}",
+                )
+                .expect("Invalid progress bar template"),
+            );
+            progress
+        };
+        self.progress.clone()
+    }
+
+    /// Runs `f` with the progress bar hidden, so it can print to the terminal
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.progress.suspend(f)
+    }
+
+    /// Prints `event`. Text messages go to stderr, so that only the generated files and diffs
+    /// are printed to stdout, where they can be streamed.
+    pub fn event(&self, event: &Event) {
+        if self.is_json() {
+            let line = serde_json::to_string(event).expect("Events always serialize");
+            self.progress.suspend(|| println!("{}", line));
+            return;
+        }
+
+        self.progress.suspend(|| match event {
+            Event::Watching { path } if !self.quiet => {
+                eprintln!("watching {} for changes", path.display())
+            }
+            Event::WouldWrite {
+                path,
+                bytes,
+                lines,
+                summary,
+            } if !self.quiet => eprintln!(
+                "would write {} ({} bytes, {} lines): {}",
+                path.display(),
+                bytes,
+                lines,
+                summary
+            ),
+            // The diff is the requested output, so it is printed even for quiet runs
+            Event::Diff { diff, .. } => print!("{}", diff),
+            Event::Unchanged { path } if !self.quiet => {
+                eprintln!("no changes to {}", path.display())
+            }
+            Event::SkippedExisting { path } if !self.quiet => {
+                eprintln!("skipping existing file {}", path.display())
+            }
+            Event::Warning { input, message } => {
+                eprintln!("warning: {}: {}", input.display(), message)
+            }
+            Event::File {
+                input,
+                error: Some(error),
+                ..
+            } => eprintln!("error: {}: {}", input.display(), error),
+            Event::Conversion {
+                input,
+                building,
+                target,
+                levels,
+                total_levels,
+            } if !self.quiet => eprintln!(
+                "{}: {} -> {}: {} of {} levels ({:.1}%)",
+                input
+                    .as_ref()
+                    .map_or("total".into(), |input| input.display().to_string()),
+                building,
+                target,
+                levels,
+                total_levels,
+                f64::from(*levels) * 100.0 / f64::from((*total_levels).max(1))
+            ),
+            Event::StateModified {
+                input,
+                state,
+                fields,
+            } if !self.quiet => eprintln!(
+                "{}: modified {} of {}",
+                input.display(),
+                fields.join(", "),
+                state
+            ),
+            Event::ResourcesRemoved {
+                input,
+                state,
+                resources,
+            } if !self.quiet => eprintln!(
+                "{}: removed {} from the arable resources of {}",
+                input.display(),
+                resources.join(", "),
+                state
+            ),
+            Event::InvalidEntry {
+                input,
+                state,
+                region_state,
+                building,
+                reason,
+            } => eprintln!(
+                "warning: {}: left {} in {} {} as it was: {}",
+                input.display(),
+                building,
+                state,
+                region_state,
+                reason
+            ),
+            Event::Summary {
+                files_ok,
+                files_skipped,
+                files_failed,
+                entries_skipped,
+                entries_invalid,
+                states_modified,
+                states_unchanged,
+                warnings,
+            } => {
+                eprintln!("{:>8} files ok", files_ok);
+                eprintln!("{:>8} files skipped", files_skipped);
+                eprintln!("{:>8} files failed", files_failed);
+                eprintln!("{:>8} entries skipped", entries_skipped);
+                eprintln!("{:>8} entries invalid", entries_invalid);
+                // Only runs of the States command have states
+                if states_modified + states_unchanged > 0 {
+                    eprintln!("{:>8} states modified", states_modified);
+                    eprintln!("{:>8} states unchanged", states_unchanged);
+                }
+                eprintln!("{:>8} warnings", warnings);
+            }
+            Event::Error { message } => eprintln!("error: {}", message),
+            _ => (),
+        });
+    }
+}
@@ -1,13 +1,43 @@
-use clap::{Parser, Subcommand};
-use maplit::hashmap;
-use std::fs::File;
-use std::io::{BufWriter, Write, read_to_string};
+mod buildings;
+mod buildings_def;
+mod cloned;
+mod companies;
+mod config;
+mod defaults;
+mod game_data;
+mod game_dir;
+mod input;
+mod localization;
+mod log;
+mod mod_dir;
+mod output;
+mod pipeline;
+mod pops;
+mod production_methods;
+mod quotes;
+mod report;
+mod script;
+mod state_history;
+mod states;
+mod watch;
+
+use buildings::run_buildings;
+use buildings_def::run_buildings_def;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use companies::run_companies;
+use config::{Config, ConfigArgs, ConfigCommand, OutputLayout, StatesMode};
+use game_data::game_dir_of;
+use game_dir::resolve_game_dir;
+use input::{InputArgs, input_files};
+use localization::{LOCALIZATION_MOD_DIR, run_localization};
+use log::{Event, Log, LogArgs};
+use mod_dir::resolve_mod_dir;
+use output::{Output, OutputArgs, STDOUT_PATH};
+use production_methods::run_production_methods;
+use report::{Report, ReportArgs};
+use state_history::run_state_history;
+use states::{place_random_deposits, read_vanilla_states, run_states, run_states_diff};
 use std::path::{Path, PathBuf};
-use tiger_lib::FileKind;
-use tiger_lib::block::Block;
-use tiger_lib::fileset::{FileEntry, FileStage};
-use tiger_lib::parse::ParserMemory;
-use tiger_lib::pdxfile::PdxFile;
 
 #[derive(Parser)]
 struct Cli {
@@ -15,428 +45,768 @@ struct Cli {
     command: Commands,
 }
 
+/// Where a generator reads its input files from and writes the generated files to.
+/// Given only one path, it is the input path if `--mod-dir` is given and the output path otherwise.
+#[derive(Args)]
+struct PathArgs {
+    /// The game directory of the input files, or a single file from it.
+    /// If omitted, it is found in the game directory.
+    input_path: Option<PathBuf>,
+    /// Directory to write the generated files to, a file path for a single input file,
+    /// or `-` for stdout
+    output_path: Option<PathBuf>,
+    /// The Victoria 3 install directory to read the input files from when
+    /// no input path is given, detected from the Steam install if omitted
+    #[arg(long)]
+    game_dir: Option<PathBuf>,
+    /// Write the generated files into the matching directory of this mod instead of
+    /// an output path. A bare name is a mod in Documents/Paradox Interactive/Victoria 3/mod,
+    /// which is created if it doesn't exist yet.
+    /// If it defines buildings, the buildings of the split rules must be defined.
+    #[arg(long, value_name = "NAME|PATH")]
+    mod_dir: Option<PathBuf>,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Parses the game's buildings files and produces ones
     /// that add the correct number of modded buildings
     Buildings {
-        input_path: PathBuf,
-        output_path: PathBuf,
+        #[command(flatten)]
+        paths: PathArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+        /// Keep running and regenerate output files whenever their input files or the ratios
+        /// config change
+        #[arg(long)]
+        watch: bool,
     },
 
     /// Parses the game's states files and updates them with
     /// the new sets of resources
     States {
-        input_path: PathBuf,
-        output_path: PathBuf,
+        #[command(flatten)]
+        paths: PathArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+        /// Keep running and regenerate output files whenever their input files or the ratios
+        /// config change
+        #[arg(long)]
+        watch: bool,
     },
-}
 
-const BOM_CHAR: char = '\u{feff}';
+    /// Compares a modified state_regions directory with the game's and produces states files
+    /// that override only the states that differ, injecting their changed keys
+    StatesDiff {
+        /// The modified state_regions directory, or a single states file from it
+        modified_path: PathBuf,
+        /// Directory to write the override files to, a file path for a single modified file,
+        /// or `-` for stdout
+        output_path: Option<PathBuf>,
+        /// The game's state_regions directory to compare with.
+        /// If omitted, it is found in the game directory.
+        #[arg(long, value_name = "DIR")]
+        vanilla_dir: Option<PathBuf>,
+        /// The Victoria 3 install directory to read the game's states from when
+        /// no vanilla directory is given, detected from the Steam install if omitted
+        #[arg(long)]
+        game_dir: Option<PathBuf>,
+        /// Write the override files into the matching directory of this mod instead of
+        /// an output path. A bare name is a mod in Documents/Paradox Interactive/Victoria 3/mod,
+        /// which is created if it doesn't exist yet.
+        #[arg(long, value_name = "NAME|PATH")]
+        mod_dir: Option<PathBuf>,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+    },
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    /// Parses the game's company types files and adds the modded buildings that
+    /// companies own levels of after splitting the game's buildings
+    Companies {
+        #[command(flatten)]
+        paths: PathArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+        /// Keep running and regenerate output files whenever their input files or the ratios
+        /// config change
+        #[arg(long)]
+        watch: bool,
+    },
 
-    match &cli.command {
-        Commands::Buildings {
-            input_path,
-            output_path,
-        } => {
-            if !input_path.is_dir() {
-                anyhow::bail!("Input path must be a directory");
-            }
-            if !output_path.is_dir() {
-                anyhow::bail!("Output path must be a directory");
-            }
+    /// Parses the game's state history files and changes the homelands, claims
+    /// and owners of their states
+    StateHistory {
+        #[command(flatten)]
+        paths: PathArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+        /// Keep running and regenerate output files whenever their input files or the ratios
+        /// config change
+        #[arg(long)]
+        watch: bool,
+    },
 
-            for entry in std::fs::read_dir(input_path)?.filter_map(Result::ok) {
-                let in_path = entry.path();
-                let parser = ParserMemory::default();
-                let file_entry = FileEntry::new(
-                    in_path.clone(),
-                    FileStage::NoStage,
-                    FileKind::Vanilla,
-                    in_path.clone(),
-                );
-                let contents =
-                    PdxFile::read(&file_entry, &parser).expect("No file contents parsed");
+    /// Parses the game's production methods files and generates the production methods of
+    /// the modded buildings cloned from them, with the goods they use scaled and renamed
+    ProductionMethods {
+        #[command(flatten)]
+        paths: PathArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+        /// Keep running and regenerate output files whenever their input files or the ratios
+        /// config change
+        #[arg(long)]
+        watch: bool,
+    },
 
-                let out_path = output_path.join(format!(
-                    "ir_{}",
-                    in_path.file_name().unwrap().to_str().unwrap()
-                ));
-                create_modded_buildings_file(&contents, &out_path)?;
-            }
-        }
-        Commands::States {
-            input_path,
-            output_path,
-        } => {
-            if !input_path.is_dir() {
-                anyhow::bail!("Input path must be a directory");
-            }
-            if !output_path.is_dir() {
-                anyhow::bail!("Output path must be a directory");
-            }
+    /// Parses the game's building definitions and generates the definitions of the modded
+    /// buildings they don't define, cloned from a template building with the fields
+    /// of the config's building definitions
+    BuildingsDef {
+        #[command(flatten)]
+        paths: PathArgs,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+        /// Keep running and regenerate output files whenever their input files or the ratios
+        /// config change
+        #[arg(long)]
+        watch: bool,
+    },
 
-            for entry in std::fs::read_dir(input_path)?.filter_map(Result::ok) {
-                let in_path = entry.path();
-                let out_path = output_path.join(in_path.file_name().unwrap().to_str().unwrap());
-                create_modded_states_file_replace(&in_path, &out_path)?;
-            }
-        }
-    }
+    /// Generates the localization of the modded buildings and production methods of the split
+    /// rules and of the goods of the ratios config, with a file for each of its languages
+    Localization {
+        /// The `localization` directory to write the files into, or `-` for stdout
+        output_path: Option<PathBuf>,
+        /// Write the files into the `localization` directory of this mod instead of an output
+        /// path. A bare name is a mod in Documents/Paradox Interactive/Victoria 3/mod,
+        /// which is created if it doesn't exist yet.
+        #[arg(long, value_name = "NAME|PATH")]
+        mod_dir: Option<PathBuf>,
+        /// The Victoria 3 install directory to read the buildings of building groups from,
+        /// detected from the Steam install if omitted
+        #[arg(long)]
+        game_dir: Option<PathBuf>,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+    },
 
-    Ok(())
-}
+    /// Runs every generator in order, reading the game's files from the game directory
+    /// and writing the generated files and the localization to the matching paths in the mod
+    /// directory
+    All {
+        /// The Victoria 3 install directory, or its `game` subdirectory,
+        /// detected from the Steam install if omitted
+        #[arg(long)]
+        game_dir: Option<PathBuf>,
+        /// The mod to generate files into, either its root directory or the name of
+        /// a mod in Documents/Paradox Interactive/Victoria 3/mod, which is created if needed.
+        /// If it defines buildings, the buildings of the split rules must be defined by it,
+        /// the game or the generated definitions.
+        #[arg(long, value_name = "NAME|PATH")]
+        mod_dir: PathBuf,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        log: LogArgs,
+    },
 
-fn create_modded_buildings_file(contents: &Block, out_path: &Path) -> anyhow::Result<()> {
-    let building_ratios = hashmap! {
-        "building_textile_mill" => (4, "building_tailoring_workshop"),
-        "building_furniture_manufactory" => (4, "building_luxury_furniture_manufactory"),
-        "building_glassworks" => (4, "building_pottery_mill"),
-        "building_rye_farm" => (6, "building_fruit_orchard"),
-        "building_wheat_farm" => (6, "building_fruit_orchard"),
-        "building_rice_farm" => (6, "building_fruit_orchard"),
-        "building_millet_farm" => (6, "building_fruit_orchard"),
-        "building_maize_farm" => (6, "building_fruit_orchard"),
-        "building_livestock_ranch" => (2, "building_wool_farm"),
-        "building_food_industry" => (4, "building_distillery"),
-    };
+    /// Tools for working with the ratios config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
 
-    let mut out_file = BufWriter::new(File::create(out_path)?);
-    writeln!(out_file, "{}BUILDINGS={{", BOM_CHAR)?;
+impl Commands {
+    fn log_args(&self) -> Option<&LogArgs> {
+        match self {
+            Commands::Buildings { log, .. }
+            | Commands::States { log, .. }
+            | Commands::StatesDiff { log, .. }
+            | Commands::Companies { log, .. }
+            | Commands::StateHistory { log, .. }
+            | Commands::ProductionMethods { log, .. }
+            | Commands::BuildingsDef { log, .. }
+            | Commands::Localization { log, .. }
+            | Commands::All { log, .. } => Some(log),
+            Commands::Config { .. } => None,
+        }
+    }
+}
 
-    let buildings = contents
-        .get_field_block("BUILDINGS")
-        .expect("Missing BUILDINGS field");
-    for (state_name, state_block) in buildings.iter_assignments_and_definitions() {
-        writeln!(out_file, "\t{} = {{", state_name.as_str())?;
-        for (region_state_name, region_state_block) in state_block
-            .expect_block()
-            .unwrap()
-            .iter_assignments_and_definitions()
-        {
-            writeln!(out_file, "\t\t{} = {{", region_state_name.as_str())?;
-            for (token, building) in region_state_block
-                .expect_block()
-                .unwrap()
-                .iter_assignments_and_definitions()
-            {
-                if token.as_str() != "create_building" {
-                    continue;
-                }
+const BOM_CHAR: char = '\u{feff}';
 
-                // Check if this building is of a split type
-                let building = building.expect_block().unwrap();
-                let building_type = building.get_field_value("building").unwrap();
-                if !building_ratios.contains_key(building_type.as_str()) {
-                    continue;
-                }
+fn main() -> anyhow::Result<()> {
+    let command = defaults::apply_defaults(Cli::command())?;
+    let cli = Cli::from_arg_matches(&command.get_matches()).unwrap_or_else(|err| err.exit());
+
+    let default_log_args = LogArgs::default();
+    let log = Log::new(cli.command.log_args().unwrap_or(&default_log_args));
+    if let Err(err) = run(&cli.command, &log) {
+        log.event(&Event::Error {
+            message: format!("{:#}", err),
+        });
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-                // Check if this building has the minimum number of levels for splitting
-                let add_ownership = building.get_field_block("add_ownership").unwrap();
-                let add_ownership_building = add_ownership.get_field_blocks("building");
-                let add_ownership_country = add_ownership.get_field_blocks("country");
-                let mut original_owners = add_ownership_building
-                    .iter()
-                    .map(|block| {
-                        hashmap! {
-                            "type" => block.get_field_value("type").unwrap().to_string(),
-                            "country" => block.get_field_value("country").unwrap().to_string(),
-                            "levels" => block.get_field_value("levels").unwrap().to_string(),
-                            "region" => block.get_field_value("region").unwrap().to_string(),
-                        }
-                    })
-                    .chain(add_ownership_country.iter().map(|block| {
-                        hashmap! {
-                            "country" => block.get_field_value("country").unwrap().to_string(),
-                            "levels" => block.get_field_value("levels").unwrap().to_string(),
-                        }
-                    }))
-                    .collect::<Vec<_>>();
-                let total_building_levels = original_owners
-                    .iter()
-                    .map(|owner| owner.get("levels").unwrap().parse::<u16>().unwrap())
-                    .sum::<u16>();
-                let &(ratio, modded_building) =
-                    building_ratios.get(building_type.as_str()).unwrap();
-                let modded_building_levels =
-                    (total_building_levels as f32 / ratio as f32 - 0.1).round() as u16;
-                if modded_building_levels == 0 {
-                    continue;
+fn run(command: &Commands, log: &Log) -> anyhow::Result<()> {
+    match command {
+        Commands::Buildings {
+            paths,
+            config,
+            input,
+            output,
+            report,
+            watch,
+            ..
+        } => {
+            let config_args = config;
+            let config = Config::load(config_args)?;
+            let (input_path, mut output) = paths.open(
+                pipeline::BUILDINGS_GAME_DIR,
+                &config.output_layout("buildings"),
+                output,
+                log,
+                config.buildings_mode.default_prefix(),
+            )?;
+            let inputs = Inputs {
+                path: &input_path,
+                args: input,
+                config: config_args,
+            };
+            let game_dir = game_dir_of(&input_path);
+            let prepare = |config: &mut Config, _: &[PathBuf]| {
+                config.load_game_data(game_dir.as_deref())?;
+                if let Some(mod_dir) = &paths.mod_dir {
+                    config.check_buildings(
+                        game_dir.as_deref(),
+                        &resolve_mod_dir(mod_dir, false)?,
+                        &[],
+                    )?;
                 }
-
-                // Split the building, using a weighted approach for assigning owners
-                writeln!(
-                    out_file,
-                    "\t\t\tremove_building = {}",
-                    building_type.as_str()
-                )?;
-                original_owners.sort_unstable_by_key(|owner| {
-                    owner.get("levels").unwrap().parse::<u16>().unwrap()
-                });
-                original_owners.reverse();
-                let level_percentages = original_owners
-                    .iter()
-                    .map(|owner| {
-                        owner.get("levels").unwrap().parse::<u16>().unwrap() as f32
-                            / total_building_levels as f32
-                    })
-                    .collect::<Vec<_>>();
-                let mut modded_per_owner = level_percentages
-                    .iter()
-                    .map(|&p| (modded_building_levels as f32 * p).round() as u16)
-                    .collect::<Vec<_>>();
-
-                let mut modded_sum = modded_per_owner.iter().sum::<u16>();
-                let mut i = 0;
-                while modded_sum > modded_building_levels {
-                    // Remove starting from the back
-                    modded_per_owner[original_owners.len() - 1 - i] -= 1;
-                    i = (i + 1) % original_owners.len();
-                    modded_sum -= 1;
+                Ok(())
+            };
+            run_or_watch(
+                *watch,
+                &inputs,
+                config,
+                report,
+                log,
+                prepare,
+                |in_paths, config| run_buildings(in_paths, config, &mut output),
+            )?;
+        }
+        Commands::States {
+            paths,
+            config,
+            input,
+            output,
+            report,
+            watch,
+            ..
+        } => {
+            let config_args = config;
+            let config = Config::load(config_args)?;
+            let (input_path, mut output) = paths.open(
+                pipeline::STATES_GAME_DIR,
+                &config.output_layout("states"),
+                output,
+                log,
+                config.states_mode.default_prefix(),
+            )?;
+            let inputs = Inputs {
+                path: &input_path,
+                args: input,
+                config: config_args,
+            };
+            let prepare = |config: &mut Config, in_paths: &[PathBuf]| {
+                config.load_game_data(game_dir_of(&input_path).as_deref())?;
+                place_random_deposits(in_paths, config)
+            };
+            run_or_watch(
+                *watch,
+                &inputs,
+                config,
+                report,
+                log,
+                prepare,
+                |in_paths, config| run_states(in_paths, config, &mut output),
+            )?;
+        }
+        Commands::StatesDiff {
+            modified_path,
+            output_path,
+            vanilla_dir,
+            game_dir,
+            mod_dir,
+            config,
+            input,
+            output,
+            report,
+            ..
+        } => {
+            let config = Config::load(config)?;
+            let vanilla_dir = match vanilla_dir {
+                Some(vanilla_dir) => {
+                    if game_dir.is_some() {
+                        anyhow::bail!("Pass either --vanilla-dir or --game-dir, not both");
+                    }
+                    vanilla_dir.clone()
                 }
-                while modded_sum < modded_building_levels {
-                    // Add starting from the front
-                    modded_per_owner[i] += 1;
-                    i = (i + 1) % original_owners.len();
-                    modded_sum += 1;
+                None => resolve_game_dir(game_dir.as_deref())?.join(pipeline::STATES_GAME_DIR),
+            };
+            let paths = PathArgs {
+                input_path: Some(modified_path.clone()),
+                output_path: output_path.clone(),
+                game_dir: None,
+                mod_dir: mod_dir.clone(),
+            };
+            let (modified_path, mut output) = paths.open(
+                pipeline::STATES_GAME_DIR,
+                &config.output_layout("states"),
+                output,
+                log,
+                StatesMode::Inject.default_prefix(),
+            )?;
+            let vanilla = read_vanilla_states(&input_files(&vanilla_dir, input)?)?;
+            // DLCs and layers are part of what the modified states are compared with
+            let filters = InputArgs {
+                include: input.include.clone(),
+                exclude: input.exclude.clone(),
+                dlc: false,
+                layer: Vec::new(),
+            };
+            let modified_paths = input_files(&modified_path, &filters)?;
+            finish_run(
+                &run_states_diff(&vanilla, &modified_paths, &config, &mut output),
+                report,
+                log,
+            )?;
+        }
+        Commands::Companies {
+            paths,
+            config,
+            input,
+            output,
+            report,
+            watch,
+            ..
+        } => {
+            let config_args = config;
+            let config = Config::load(config_args)?;
+            let (input_path, mut output) = paths.open(
+                pipeline::COMPANIES_GAME_DIR,
+                &config.output_layout("companies"),
+                output,
+                log,
+                "",
+            )?;
+            let inputs = Inputs {
+                path: &input_path,
+                args: input,
+                config: config_args,
+            };
+            let prepare = |config: &mut Config, _: &[PathBuf]| {
+                config.load_game_data(game_dir_of(&input_path).as_deref())
+            };
+            run_or_watch(
+                *watch,
+                &inputs,
+                config,
+                report,
+                log,
+                prepare,
+                |in_paths, config| run_companies(in_paths, config, &mut output),
+            )?;
+        }
+        Commands::StateHistory {
+            paths,
+            config,
+            input,
+            output,
+            report,
+            watch,
+            ..
+        } => {
+            let config_args = config;
+            let config = Config::load(config_args)?;
+            let (input_path, mut output) = paths.open(
+                pipeline::STATE_HISTORY_GAME_DIR,
+                &config.output_layout("state-history"),
+                output,
+                log,
+                "",
+            )?;
+            let inputs = Inputs {
+                path: &input_path,
+                args: input,
+                config: config_args,
+            };
+            let prepare = |config: &mut Config, _: &[PathBuf]| {
+                config.load_game_data(game_dir_of(&input_path).as_deref())
+            };
+            run_or_watch(
+                *watch,
+                &inputs,
+                config,
+                report,
+                log,
+                prepare,
+                |in_paths, config| run_state_history(in_paths, config, &mut output),
+            )?;
+        }
+        Commands::ProductionMethods {
+            paths,
+            config,
+            input,
+            output,
+            report,
+            watch,
+            ..
+        } => {
+            let config_args = config;
+            let config = Config::load(config_args)?;
+            let (input_path, mut output) = paths.open(
+                pipeline::PRODUCTION_METHODS_GAME_DIR,
+                &config.output_layout("production-methods"),
+                output,
+                log,
+                pipeline::PRODUCTION_METHODS_PREFIX,
+            )?;
+            let inputs = Inputs {
+                path: &input_path,
+                args: input,
+                config: config_args,
+            };
+            let prepare = |config: &mut Config, _: &[PathBuf]| {
+                config.load_game_data(game_dir_of(&input_path).as_deref())
+            };
+            run_or_watch(
+                *watch,
+                &inputs,
+                config,
+                report,
+                log,
+                prepare,
+                |in_paths, config| run_production_methods(in_paths, config, &mut output),
+            )?;
+        }
+        Commands::BuildingsDef {
+            paths,
+            config,
+            input,
+            output,
+            report,
+            watch,
+            ..
+        } => {
+            let config_args = config;
+            let config = Config::load(config_args)?;
+            let (input_path, mut output) = paths.open(
+                pipeline::BUILDINGS_DEF_GAME_DIR,
+                &config.output_layout("buildings-def"),
+                output,
+                log,
+                pipeline::BUILDINGS_DEF_PREFIX,
+            )?;
+            let inputs = Inputs {
+                path: &input_path,
+                args: input,
+                config: config_args,
+            };
+            let prepare = |config: &mut Config, _: &[PathBuf]| {
+                config.load_game_data(game_dir_of(&input_path).as_deref())
+            };
+            run_or_watch(
+                *watch,
+                &inputs,
+                config,
+                report,
+                log,
+                prepare,
+                |in_paths, config| run_buildings_def(in_paths, config, &mut output),
+            )?;
+        }
+        Commands::Localization {
+            output_path,
+            mod_dir,
+            game_dir,
+            config,
+            output,
+            report,
+            ..
+        } => {
+            let mut config = Config::load(config)?;
+            // Only configs depending on the game's files, such as rules for building groups,
+            // need the game directory
+            let game_dir = match game_dir {
+                Some(game_dir) => Some(resolve_game_dir(Some(game_dir))?),
+                None => resolve_game_dir(None).ok(),
+            };
+            config.load_game_data(game_dir.as_deref())?;
+            let writes = !output.dry_run && !output.diff;
+            let output_dir = match (output_path, mod_dir) {
+                (Some(_), Some(_)) => {
+                    anyhow::bail!("Pass either an output path or --mod-dir, not both")
                 }
-                if modded_sum != modded_building_levels {
-                    anyhow::bail!("Incorrect number of modded building levels, fix the code");
+                (Some(output_path), None) => output_path.clone(),
+                (None, Some(mod_dir)) => {
+                    resolve_mod_dir(mod_dir, writes)?.join(LOCALIZATION_MOD_DIR)
                 }
-
-                // Create the basic building
-                writeln!(out_file, "\t\t\tcreate_building = {{")?;
-                writeln!(
-                    out_file,
-                    "\t\t\t\tbuilding = \"{}\"",
-                    building_type.as_str()
-                )?;
-                writeln!(out_file, "\t\t\t\tadd_ownership = {{")?;
-                for (i, owner) in original_owners.iter().enumerate() {
-                    let owned_by_building = owner.contains_key("type");
-                    if owned_by_building {
-                        writeln!(out_file, "\t\t\t\t\tbuilding = {{")?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\ttype = \"{}\"",
-                            owner.get("type").unwrap()
-                        )?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tcountry = \"{}\"",
-                            owner.get("country").unwrap()
-                        )?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tlevels = {}",
-                            owner.get("levels").unwrap().parse::<u16>().unwrap()
-                                - modded_per_owner[i]
-                        )?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tregion = \"{}\"",
-                            owner.get("region").unwrap()
-                        )?;
-                        writeln!(out_file, "\t\t\t\t\t}}")?;
-                    } else {
-                        writeln!(out_file, "\t\t\t\t\tcountry = {{")?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tcountry = \"{}\"",
-                            owner.get("country").unwrap()
-                        )?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tlevels = {}",
-                            owner.get("levels").unwrap().parse::<u16>().unwrap()
-                                - modded_per_owner[i]
-                        )?;
-                        writeln!(out_file, "\t\t\t\t\t}}")?;
-                    }
+                (None, None) => anyhow::bail!("Missing output path"),
+            };
+            let mut output = if output_dir == Path::new(STDOUT_PATH) {
+                if log.is_json() {
+                    anyhow::bail!("Can't write to stdout with JSON logs, which are printed there");
                 }
-                writeln!(out_file, "\t\t\t\t}}")?;
-                writeln!(out_file, "\t\t\t}}")?;
-
-                // Create the modded building
-                writeln!(out_file, "\t\t\tcreate_building = {{")?;
-                writeln!(out_file, "\t\t\t\tbuilding = \"{}\"", modded_building)?;
-                writeln!(out_file, "\t\t\t\tadd_ownership = {{")?;
-                for (i, owner) in original_owners.iter().enumerate() {
-                    if modded_per_owner[i] == 0 {
-                        break;
-                    }
-
-                    let owned_by_building = owner.contains_key("type");
-                    if owned_by_building {
-                        let owner_type = owner.get("type").unwrap();
-                        writeln!(out_file, "\t\t\t\t\tbuilding = {{")?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\ttype = \"{}\"",
-                            if owner_type == building_type.as_str() {
-                                modded_building
-                            } else {
-                                owner_type
-                            }
-                        )?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tcountry = \"{}\"",
-                            owner.get("country").unwrap()
-                        )?;
-                        writeln!(out_file, "\t\t\t\t\t\tlevels = {}", modded_per_owner[i])?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tregion = \"{}\"",
-                            owner.get("region").unwrap()
-                        )?;
-                        writeln!(out_file, "\t\t\t\t\t}}")?;
-                    } else {
-                        writeln!(out_file, "\t\t\t\t\tcountry = {{")?;
-                        writeln!(
-                            out_file,
-                            "\t\t\t\t\t\tcountry = \"{}\"",
-                            owner.get("country").unwrap()
-                        )?;
-                        writeln!(out_file, "\t\t\t\t\t\tlevels = {}", modded_per_owner[i])?;
-                        writeln!(out_file, "\t\t\t\t\t}}")?;
+                Output::new(output, &output_dir, "", log.clone())
+            } else {
+                if writes {
+                    for language in &config.localization.languages {
+                        std::fs::create_dir_all(output_dir.join(language))?;
                     }
                 }
-                writeln!(out_file, "\t\t\t\t}}")?;
-                writeln!(
-                    out_file,
-                    "\t\t\t\treserves = {}",
-                    building.get_field_value("reserves").unwrap().as_str()
-                )?;
-                writeln!(out_file, "\t\t\t}}")?;
-            }
-            writeln!(out_file, "\t\t}}")?;
+                Output::for_directory(output, &output_dir, "", log.clone())
+            };
+            finish_run(&run_localization(&config, &mut output), report, log)?;
+        }
+        Commands::All {
+            game_dir,
+            mod_dir,
+            config,
+            input,
+            output,
+            report,
+            ..
+        } => {
+            let config = Config::load(config)?;
+            finish_run(
+                &pipeline::run_all(game_dir.as_deref(), mod_dir, config, input, output, log)?,
+                report,
+                log,
+            )?;
         }
-        writeln!(out_file, "\t}}")?;
+        Commands::Config { command } => command.run()?,
     }
 
-    writeln!(out_file, "}}")?;
-    out_file.flush()?;
-
     Ok(())
 }
 
-#[allow(dead_code)]
-fn create_modded_states_file_inject(in_path: &Path, out_path: &Path) -> anyhow::Result<()> {
-    const FARM_TYPES: &[&str] = &[
-        "building_rice_farm",
-        "building_wheat_farm",
-        "building_maize_farm",
-        "building_millet_farm",
-        "building_rye_farm",
-    ];
+/// The input files of a generator and the config it is loaded from, which are watched for
+/// changes with `--watch`
+struct Inputs<'a> {
+    path: &'a Path,
+    args: &'a InputArgs,
+    config: &'a ConfigArgs,
+}
 
-    if in_path
-        .file_stem()
-        .unwrap()
-        .to_string_lossy()
-        .contains("99_seas")
-    {
-        return Ok(());
+/// Prepares `config` for the input files with `prepare`, runs `run` over them and finishes
+/// the run, then with `watch` keeps running it again over every input file whenever they or
+/// the config change. A changed config is loaded and prepared again first.
+fn run_or_watch(
+    watch: bool,
+    inputs: &Inputs,
+    mut config: Config,
+    report_args: &ReportArgs,
+    log: &Log,
+    prepare: impl Fn(&mut Config, &[PathBuf]) -> anyhow::Result<()>,
+    mut run: impl FnMut(&[PathBuf], &Config) -> Report,
+) -> anyhow::Result<()> {
+    let in_paths = input_files(inputs.path, inputs.args)?;
+    prepare(&mut config, &in_paths)?;
+    let report = run(&in_paths, &config);
+    if !watch {
+        return finish_run(&report, report_args, log);
     }
 
-    let in_data = read_to_string(File::open(in_path)?)?;
-
-    let mut out_file = BufWriter::new(File::create(out_path)?);
-    write!(out_file, "{}", BOM_CHAR)?;
-
-    let mut depth = 0;
-    let mut in_state = false;
-    for mut line in in_data.lines() {
-        line = line.trim_start_matches(BOM_CHAR);
-
-        if line.contains('{') {
-            depth += 1;
-        }
-        if line.contains('}') {
-            depth -= 1;
-        }
+    // Report failures of the initial run, but keep watching to allow fixing them
+    if let Err(err) = finish_run(&report, report_args, log) {
+        log.event(&Event::Error {
+            message: format!("{:#}", err),
+        });
+    }
+    let config_path = inputs.config.path();
+    watch::watch(
+        inputs.path,
+        inputs.args,
+        config_path.as_deref(),
+        log,
+        |in_paths, config_changed| {
+            // An invalid config keeps the previous one until it is fixed
+            if config_changed {
+                let mut reloaded = Config::load(inputs.config)?;
+                prepare(&mut reloaded, in_paths)?;
+                config = reloaded;
+            }
+            finish_run(&run(in_paths, &config), report_args, log)
+        },
+    )
+}
 
-        // Start state
-        if line.starts_with("STATE_") {
-            writeln!(out_file, "INJECT:{}", line)?;
-            in_state = true;
-            continue;
-        }
+/// Writes the requested report for a finished run and prints its summary,
+/// failing if any of its files failed
+fn finish_run(report: &Report, report_args: &ReportArgs, log: &Log) -> anyhow::Result<()> {
+    report_args.write(report)?;
+    report.print_summary(log);
 
-        // End state
-        if in_state && depth == 0 {
-            writeln!(out_file, "}}")?;
-            in_state = false;
-            continue;
-        }
+    let failed = report.failed_files();
+    if failed > 0 {
+        anyhow::bail!("{} of {} files failed", failed, report.files.len());
+    }
+    Ok(())
+}
 
-        if line.contains("arable_resources") {
-            let mut modified_line = line.to_string();
-            if FARM_TYPES
-                .iter()
-                .any(|&farm_type| modified_line.contains(farm_type))
-            {
-                modified_line = modified_line.replace("}", "\"building_fruit_orchard\" }");
+impl PathArgs {
+    /// Resolves the input path, found in `game_subdir` of the game directory if not given,
+    /// and creates the output for the generated files, named by `layout` and written into
+    /// its directory of the mod directory if one was given, or otherwise into `game_subdir`
+    fn open(
+        &self,
+        game_subdir: &str,
+        layout: &OutputLayout,
+        output_args: &OutputArgs,
+        log: &Log,
+        default_prefix: &str,
+    ) -> anyhow::Result<(PathBuf, Output)> {
+        let (input_path, output_path) = match (&self.input_path, &self.output_path, &self.mod_dir) {
+            (_, Some(_), Some(_)) => {
+                anyhow::bail!("Pass either an output path or --mod-dir, not both")
             }
-            if modified_line.contains("building_livestock_ranch") {
-                modified_line = modified_line.replace("}", "\"building_wool_farm\" }");
+            (input_path, None, Some(_)) => (input_path.as_deref(), None),
+            (input_path, Some(output_path), None) => {
+                (input_path.as_deref(), Some(output_path.as_path()))
             }
-            writeln!(out_file, "{}", modified_line)?;
-        }
+            (Some(output_path), None, None) => (None, Some(output_path.as_path())),
+            (None, None, None) => anyhow::bail!("Missing output path"),
+        };
+        let input_path = match input_path {
+            Some(input_path) => {
+                if self.game_dir.is_some() {
+                    anyhow::bail!("Pass either an input path or --game-dir, not both");
+                }
+                input_path.to_path_buf()
+            }
+            None => resolve_game_dir(self.game_dir.as_deref())?.join(game_subdir),
+        };
+
+        let output = match (output_path, &self.mod_dir) {
+            (Some(output_path), _) => {
+                check_paths(&input_path, output_path)?;
+                if output_path == Path::new(STDOUT_PATH) && log.is_json() {
+                    anyhow::bail!("Can't write to stdout with JSON logs, which are printed there");
+                }
+                Output::new(output_args, output_path, default_prefix, log.clone())
+            }
+            (None, Some(mod_dir)) => {
+                if !input_path.exists() {
+                    anyhow::bail!("Input path must be a file or a directory");
+                }
+                let writes = !output_args.dry_run && !output_args.diff;
+                let output_dir = resolve_mod_dir(mod_dir, writes)?
+                    .join(layout.dir.as_deref().unwrap_or(Path::new(game_subdir)));
+                if writes {
+                    std::fs::create_dir_all(&output_dir)?;
+                }
+                Output::for_directory(output_args, &output_dir, default_prefix, log.clone())
+            }
+            (None, None) => unreachable!(),
+        };
+        Ok((input_path, output.with_layout(output_args, layout)))
     }
-
-    out_file.flush()?;
-
-    Ok(())
 }
 
-fn create_modded_states_file_replace(in_path: &Path, out_path: &Path) -> anyhow::Result<()> {
-    const FARM_TYPES: &[&str] = &[
-        "building_rice_farm",
-        "building_wheat_farm",
-        "building_maize_farm",
-        "building_millet_farm",
-        "building_rye_farm",
-    ];
-
-    if in_path
-        .file_stem()
-        .unwrap()
-        .to_string_lossy()
-        .contains("99_seas")
-    {
+/// Checks that the input path is a file or directory, and that the output path
+/// is a directory, or a file path in an existing directory when the input is a single file.
+/// An output path of `-` writes to stdout and is always valid.
+fn check_paths(input_path: &Path, output_path: &Path) -> anyhow::Result<()> {
+    if !input_path.exists() {
+        anyhow::bail!("Input path must be a file or a directory");
+    }
+    if output_path == Path::new(STDOUT_PATH) {
         return Ok(());
     }
 
-    let in_data = read_to_string(File::open(in_path)?)?;
-
-    let mut out_file = BufWriter::new(File::create(out_path)?);
-    write!(out_file, "{}", BOM_CHAR)?;
-
-    for mut line in in_data.lines() {
-        line = line.trim_start_matches(BOM_CHAR);
-        if line.trim().starts_with("arable_resources") {
-            let mut modified_line = line.to_string();
-            if FARM_TYPES
-                .iter()
-                .any(|&farm_type| modified_line.contains(farm_type))
-            {
-                modified_line = modified_line.replace("}", "\"building_fruit_orchard\" }");
-            }
-            if modified_line.contains("building_livestock_ranch") {
-                modified_line = modified_line.replace("}", "\"building_wool_farm\" }");
-            }
-            writeln!(out_file, "{}", modified_line)?;
-        } else {
-            writeln!(out_file, "{}", line)?;
+    if input_path.is_dir() {
+        if !output_path.is_dir() {
+            anyhow::bail!("Output path must be a directory when the input path is a directory");
+        }
+    } else {
+        let output_parent = output_path.parent().unwrap_or(Path::new(""));
+        if !output_path.is_dir() && !output_parent.as_os_str().is_empty() && !output_parent.is_dir()
+        {
+            anyhow::bail!("Output path must be a directory or a file in an existing directory");
         }
     }
-
-    out_file.flush()?;
-
     Ok(())
 }
@@ -0,0 +1,89 @@
+use crate::game_dir::{steam_dirs, steam_libraries};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Steam app id of Victoria 3, which names its Proton prefix in `steamapps/compatdata`
+const STEAM_APP_ID: &str = "529340";
+
+/// The game's user directory, relative to the Documents folder
+const USER_DIR: &str = "Paradox Interactive/Victoria 3";
+
+/// Contents of a new mod's `.metadata/metadata.json`, which the launcher needs to list the mod
+#[derive(Serialize)]
+struct ModMetadata {
+    name: String,
+    id: String,
+    version: String,
+    supported_game_version: String,
+    short_description: String,
+    tags: Vec<String>,
+    relationships: Vec<String>,
+}
+
+/// Returns the root directory of the mod `mod_dir`. A bare name refers to that mod
+/// in the game's `mod` folder in Documents, which is created along with the mod's metadata
+/// if `create` is set and it doesn't exist yet. Anything else is the path to an existing mod.
+pub fn resolve_mod_dir(mod_dir: &Path, create: bool) -> anyhow::Result<PathBuf> {
+    if mod_dir.components().count() > 1 || mod_dir.is_dir() {
+        if !mod_dir.is_dir() {
+            anyhow::bail!("Mod directory {} does not exist", mod_dir.display());
+        }
+        return Ok(mod_dir.to_path_buf());
+    }
+
+    let user_dir = user_dir().ok_or_else(|| {
+        anyhow::anyhow!(
+            "Could not find the Victoria 3 folder in Documents/{}, pass --mod-dir as a path",
+            USER_DIR
+        )
+    })?;
+    let mod_root = user_dir.join("mod").join(mod_dir);
+    if create && !mod_root.is_dir() {
+        create_mod(&mod_root, &mod_dir.to_string_lossy())?;
+    }
+    Ok(mod_root)
+}
+
+/// Finds the game's user directory in Documents, or in the Documents folder
+/// of the game's Proton prefix when it is run through Proton
+fn user_dir() -> Option<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(documents) = dirs::document_dir() {
+        candidates.push(documents.join(USER_DIR));
+    }
+    for library in steam_dirs()
+        .into_iter()
+        .flat_map(|steam_dir| steam_libraries(&steam_dir))
+    {
+        candidates.push(
+            library
+                .join("steamapps/compatdata")
+                .join(STEAM_APP_ID)
+                .join("pfx/drive_c/users/steamuser/Documents")
+                .join(USER_DIR),
+        );
+    }
+    candidates.into_iter().find(|dir| dir.is_dir())
+}
+
+/// Creates an empty mod named `name` at `mod_root`
+fn create_mod(mod_root: &Path, name: &str) -> anyhow::Result<()> {
+    let metadata_dir = mod_root.join(".metadata");
+    std::fs::create_dir_all(&metadata_dir)?;
+    let metadata = ModMetadata {
+        name: name.to_string(),
+        id: name.to_string(),
+        version: "1.0".to_string(),
+        supported_game_version: String::new(),
+        short_description: String::new(),
+        tags: Vec::new(),
+        relationships: Vec::new(),
+    };
+    serde_json::to_writer_pretty(
+        BufWriter::new(File::create(metadata_dir.join("metadata.json"))?),
+        &metadata,
+    )?;
+    Ok(())
+}
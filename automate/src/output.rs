@@ -0,0 +1,408 @@
+use crate::BOM_CHAR;
+use crate::config::OutputLayout;
+use crate::log::{Event, Log};
+use anyhow::Context;
+use clap::Args;
+use indicatif::ProgressBar;
+use similar::TextDiff;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+
+/// Output path that streams generated files to stdout
+pub const STDOUT_PATH: &str = "-";
+
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Run the full transform and print a summary of each file
+    /// that would be written, without touching the output directory
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print a unified diff of each generated file against the current
+    /// contents of the output directory, without writing anything
+    #[arg(long, conflicts_with = "dry_run")]
+    pub diff: bool,
+
+    /// Overwrite existing output files
+    #[arg(long, conflicts_with = "no_clobber")]
+    pub force: bool,
+
+    /// Leave existing output files untouched and skip writing them
+    #[arg(long)]
+    pub no_clobber: bool,
+
+    /// Merge generated buildings and states files into existing output files, keeping what
+    /// was added to them by hand and only replacing the buildings and states that are generated
+    #[arg(long, conflicts_with = "no_clobber")]
+    pub merge: bool,
+
+    /// Before overwriting an existing output file, move it to `<file>.bak`, or to
+    /// `<file>.bak.1`, `<file>.bak.2` and so on if older backups exist
+    #[arg(long, conflicts_with = "no_clobber")]
+    pub backup: bool,
+
+    /// Before overwriting an existing output file, move it into
+    /// a timestamped subdirectory of this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "no_clobber")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Prefix for generated file names, used as `{prefix}` in the naming template.
+    /// Defaults to `ir_` for buildings files that patch the game's buildings,
+    /// and no prefix for buildings files replacing the game's files and states files.
+    #[arg(long, value_name = "PREFIX")]
+    pub output_prefix: Option<String>,
+
+    /// Template for generated file names. `{prefix}` is the output prefix,
+    /// `{stem}` the input file name without its extension and `{ext}` its extension.
+    /// Defaults to the `name` of the ratios config's output layout, or `{prefix}{stem}.{ext}`.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub output_name: Option<String>,
+}
+
+const DEFAULT_NAME_TEMPLATE: &str = "{prefix}{stem}.{ext}";
+
+/// What to do with output files that already exist
+enum Overwrite {
+    /// Fail the run
+    Refuse,
+    /// Write over them
+    Force,
+    /// Keep them and skip writing the generated file
+    Skip,
+    /// Move them to `<file>.bak`, or the first numbered backup that doesn't exist, first
+    Backup,
+    /// Move them into this directory first
+    BackupDir(PathBuf),
+}
+
+/// Where generated files are written to
+enum Destination {
+    /// Print every file to stdout, each preceded by a header with its name
+    Stdout,
+    /// Write each file into this directory
+    Directory(PathBuf),
+    /// Write the single generated file to this path
+    File(PathBuf),
+}
+
+/// Destination for generated files
+pub struct Output {
+    destination: Destination,
+    dry_run: bool,
+    diff: bool,
+    overwrite: Overwrite,
+    /// Whether existing files are merged with the generated ones, so they may be overwritten
+    merge: bool,
+    prefix: String,
+    name_template: String,
+    /// Naming templates of specific input file names, used instead of `name_template`
+    renames: HashMap<String, String>,
+    /// Files written so far, which may always be overwritten by later writes
+    written: HashSet<PathBuf>,
+    /// Directory that the staging directory is created in,
+    /// on the same filesystem as the output so files can be renamed into place
+    staging_parent: PathBuf,
+    /// Files written during the current run, which are removed
+    /// if the run fails before they are committed
+    staging: Option<TempDir>,
+    /// Output paths of the files in `staging`
+    staged: Vec<PathBuf>,
+    log: Log,
+}
+
+impl Output {
+    /// Creates an output writing into `output_path`, naming files
+    /// with `default_prefix` unless another prefix was requested
+    pub fn new(args: &OutputArgs, output_path: &Path, default_prefix: &str, log: Log) -> Self {
+        let destination = if output_path == Path::new(STDOUT_PATH) {
+            Destination::Stdout
+        } else if output_path.is_dir() {
+            Destination::Directory(output_path.to_path_buf())
+        } else {
+            Destination::File(output_path.to_path_buf())
+        };
+        Self::with_destination(args, destination, default_prefix, log)
+    }
+
+    /// Creates an output writing into the directory `dir`, even if it doesn't exist yet
+    pub fn for_directory(args: &OutputArgs, dir: &Path, default_prefix: &str, log: Log) -> Self {
+        Self::with_destination(
+            args,
+            Destination::Directory(dir.to_path_buf()),
+            default_prefix,
+            log,
+        )
+    }
+
+    fn with_destination(
+        args: &OutputArgs,
+        destination: Destination,
+        default_prefix: &str,
+        log: Log,
+    ) -> Self {
+        let staging_parent = match &destination {
+            Destination::Stdout => PathBuf::new(),
+            Destination::Directory(dir) => dir.clone(),
+            Destination::File(path) => match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        };
+        let overwrite = if args.no_clobber {
+            Overwrite::Skip
+        } else if let Some(backup_dir) = &args.backup_dir {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            Overwrite::BackupDir(backup_dir.join(timestamp.to_string()))
+        } else if args.backup {
+            Overwrite::Backup
+        } else if args.force {
+            Overwrite::Force
+        } else {
+            Overwrite::Refuse
+        };
+        Self {
+            destination,
+            dry_run: args.dry_run,
+            diff: args.diff,
+            overwrite,
+            merge: args.merge,
+            prefix: args
+                .output_prefix
+                .clone()
+                .unwrap_or_else(|| default_prefix.to_string()),
+            name_template: args
+                .output_name
+                .clone()
+                .unwrap_or_else(|| DEFAULT_NAME_TEMPLATE.to_string()),
+            renames: HashMap::new(),
+            written: HashSet::new(),
+            staging_parent,
+            staging: None,
+            staged: Vec::new(),
+            log,
+        }
+    }
+
+    /// Names files by the prefix and templates of `layout`, unless others were requested
+    pub fn with_layout(mut self, args: &OutputArgs, layout: &OutputLayout) -> Self {
+        if args.output_prefix.is_none()
+            && let Some(prefix) = &layout.prefix
+        {
+            self.prefix = prefix.clone();
+        }
+        if args.output_name.is_none() {
+            if let Some(name) = &layout.name {
+                self.name_template = name.clone();
+            }
+            self.renames = layout.rename.clone();
+        }
+        self
+    }
+
+    /// Starts a new run over `files` input files, returning its progress bar.
+    /// Discards whatever was staged by a previous run that failed.
+    pub fn begin_run(&mut self, files: usize) -> ProgressBar {
+        self.staging = None;
+        self.staged.clear();
+        self.log.start_progress(files)
+    }
+
+    /// Returns the name of the file generated from `in_path`, following the naming template
+    pub fn file_name(&self, in_path: &Path) -> String {
+        let stem = in_path.file_stem().unwrap_or_default().to_string_lossy();
+        let name_template = in_path
+            .file_name()
+            .and_then(|file_name| self.renames.get(file_name.to_string_lossy().as_ref()))
+            .unwrap_or(&self.name_template);
+        let template = match in_path.extension() {
+            Some(_) => name_template.as_str(),
+            // Avoid a trailing dot for input files without an extension
+            None => &name_template.replace(".{ext}", ""),
+        };
+        template
+            .replace("{prefix}", &self.prefix)
+            .replace("{stem}", &stem)
+            .replace(
+                "{ext}",
+                &in_path.extension().unwrap_or_default().to_string_lossy(),
+            )
+    }
+
+    /// The input file of `in_paths` that `in_path` is named like the file generated from,
+    /// or a backup of it, if a previous run generated it from another input file
+    pub fn generated_from<'a>(&self, in_path: &Path, in_paths: &'a [PathBuf]) -> Option<&'a Path> {
+        let file_name = in_path.file_name()?.to_string_lossy();
+        let file_name = backed_up_name(&file_name);
+        in_paths
+            .iter()
+            .find(|other| *other != in_path && self.file_name(other) == file_name)
+            .map(PathBuf::as_path)
+    }
+
+    /// Path of the existing file that the generated file `file_name` should be merged into,
+    /// if merging and it exists
+    pub fn merge_path(&self, file_name: &str) -> Option<PathBuf> {
+        if !self.merge {
+            return None;
+        }
+        let path = match &self.destination {
+            Destination::Stdout => return None,
+            Destination::Directory(dir) => dir.join(file_name),
+            Destination::File(path) => path.clone(),
+        };
+        path.is_file().then_some(path)
+    }
+
+    /// Writes `contents` to the generated file `file_name`, or only prints what would be written
+    /// in dry-run mode. `summary` is a short description of the changes made in the file.
+    pub fn write(&mut self, file_name: &str, contents: &[u8], summary: &str) -> anyhow::Result<()> {
+        let path = match &self.destination {
+            Destination::Stdout => PathBuf::from(file_name),
+            Destination::Directory(dir) => dir.join(file_name),
+            Destination::File(path) => path.clone(),
+        };
+
+        if self.dry_run {
+            self.log.event(&Event::WouldWrite {
+                lines: contents.iter().filter(|&&b| b == b'\n').count(),
+                bytes: contents.len(),
+                summary: summary.to_string(),
+                path,
+            });
+            return Ok(());
+        }
+
+        if self.diff {
+            // Files that don't exist yet are diffed against an empty file
+            let current = match &self.destination {
+                Destination::Stdout => Vec::new(),
+                _ => std::fs::read(&path).unwrap_or_default(),
+            };
+            let current = String::from_utf8_lossy(&current);
+            let generated = String::from_utf8_lossy(contents);
+            if current == generated {
+                self.log.event(&Event::Unchanged { path });
+            } else {
+                let diff = TextDiff::from_lines(&*current, &*generated)
+                    .unified_diff()
+                    .context_radius(3)
+                    .header(
+                        &format!("a/{}", path.display()),
+                        &format!("b/{}", path.display()),
+                    )
+                    .to_string();
+                self.log.event(&Event::Diff { path, diff });
+            }
+            return Ok(());
+        }
+
+        if let Destination::Stdout = self.destination {
+            let contents = contents
+                .strip_prefix(BOM_CHAR.to_string().as_bytes())
+                .unwrap_or(contents);
+            return self.log.suspend(|| {
+                let mut stdout = std::io::stdout().lock();
+                writeln!(stdout, "# ==> {} <==", file_name)?;
+                stdout.write_all(contents)?;
+                stdout.flush()?;
+                Ok(())
+            });
+        }
+
+        if path.exists() && !self.written.contains(&path) && !self.merge {
+            match &self.overwrite {
+                Overwrite::Refuse => anyhow::bail!(
+                    "Refusing to overwrite existing file {}, pass --force, --backup, --merge or --no-clobber",
+                    path.display()
+                ),
+                Overwrite::Skip => {
+                    self.log.event(&Event::SkippedExisting { path });
+                    return Ok(());
+                }
+                Overwrite::Force | Overwrite::Backup | Overwrite::BackupDir(_) => (),
+            }
+        }
+
+        // Stage the file so that it only replaces the existing output once the whole run succeeds
+        let staging = match &mut self.staging {
+            Some(staging) => staging,
+            None => self.staging.insert(
+                tempfile::Builder::new()
+                    .prefix(".automate-staging-")
+                    .tempdir_in(&self.staging_parent)?,
+            ),
+        };
+        std::fs::write(staging.path().join(file_name_of(&path)?), contents)?;
+        if !self.staged.contains(&path) {
+            self.staged.push(path);
+        }
+
+        Ok(())
+    }
+
+    /// Moves every file staged during the run into place, backing up existing files first
+    pub fn commit(&mut self) -> anyhow::Result<()> {
+        let Some(staging) = self.staging.take() else {
+            return Ok(());
+        };
+
+        for path in self.staged.drain(..) {
+            if path.exists() && !self.written.contains(&path) {
+                match &self.overwrite {
+                    Overwrite::Backup => std::fs::rename(&path, backup_path(&path))?,
+                    Overwrite::BackupDir(backup_dir) => {
+                        std::fs::create_dir_all(backup_dir)?;
+                        std::fs::copy(&path, backup_dir.join(file_name_of(&path)?))?;
+                    }
+                    Overwrite::Refuse | Overwrite::Force | Overwrite::Skip => (),
+                }
+            }
+            std::fs::rename(staging.path().join(file_name_of(&path)?), &path)?;
+            self.written.insert(path);
+        }
+
+        Ok(())
+    }
+}
+
+fn file_name_of(path: &Path) -> anyhow::Result<&OsStr> {
+    path.file_name()
+        .with_context(|| format!("Invalid output path {}", path.display()))
+}
+
+/// Name of the file that `file_name` is a backup of, as `backup_path` names them,
+/// or `file_name` itself if it isn't a backup
+fn backed_up_name(file_name: &str) -> &str {
+    let Some((name, number)) = file_name.rsplit_once(".bak") else {
+        return file_name;
+    };
+    let is_number = |number: &str| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit());
+    if number.is_empty() || number.strip_prefix('.').is_some_and(is_number) {
+        name
+    } else {
+        file_name
+    }
+}
+
+/// Returns `<path>.bak`, or `<path>.bak.N` with the lowest N whose backup doesn't exist yet,
+/// so that earlier backups are never overwritten
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = OsString::from(path.as_os_str());
+    backup.push(".bak");
+    let mut backup_path = PathBuf::from(&backup);
+    let mut number = 1;
+    while backup_path.exists() {
+        let mut numbered = backup.clone();
+        numbered.push(format!(".{}", number));
+        backup_path = PathBuf::from(numbered);
+        number += 1;
+    }
+    backup_path
+}
@@ -0,0 +1,165 @@
+use crate::buildings::run_buildings;
+use crate::buildings_def::run_buildings_def;
+use crate::companies::run_companies;
+use crate::config::Config;
+use crate::game_dir::resolve_game_dir;
+use crate::input::{InputArgs, input_files};
+use crate::localization::{LOCALIZATION_MOD_DIR, run_localization};
+use crate::log::Log;
+use crate::mod_dir::resolve_mod_dir;
+use crate::output::{Output, OutputArgs};
+use crate::production_methods::run_production_methods;
+use crate::report::Report;
+use crate::state_history::run_state_history;
+use crate::states::{place_random_deposits, run_states};
+use std::path::{Path, PathBuf};
+
+/// Directory of the buildings history files, relative to the game's `game` directory
+pub const BUILDINGS_GAME_DIR: &str = "common/history/buildings";
+/// Directory of the state region files, relative to the game's `game` directory
+pub const STATES_GAME_DIR: &str = "map_data/state_regions";
+/// Directory of the company type files, relative to the game's `game` directory
+pub const COMPANIES_GAME_DIR: &str = "common/company_types";
+/// Directory of the production method files, relative to the game's `game` directory
+pub const PRODUCTION_METHODS_GAME_DIR: &str = "common/production_methods";
+/// Prefix of the generated production method files, which add to the game's files
+/// instead of replacing them
+pub const PRODUCTION_METHODS_PREFIX: &str = "ir_";
+/// Directory of the building definition files, relative to the game's `game` directory
+pub const BUILDINGS_DEF_GAME_DIR: &str = "common/buildings";
+/// Prefix of the generated building definition files, which add to the game's files
+/// instead of replacing them
+pub const BUILDINGS_DEF_PREFIX: &str = "ir_";
+/// Directory of the state history files, relative to the game's `game` directory
+pub const STATE_HISTORY_GAME_DIR: &str = "common/history/states";
+
+/// A generator run by the `all` command
+struct Step {
+    /// Name of the generator, which its output layout in the config is given for
+    name: &'static str,
+    /// Directory of the input files, relative to the game's `game` directory
+    game_subdir: &'static str,
+    /// Directory of the generated files, relative to the mod root, unless the config gives another
+    mod_subdir: &'static str,
+    /// Prefix for generated file names with the config
+    default_prefix: fn(&Config) -> &'static str,
+    run: fn(&[PathBuf], &Config, &mut Output) -> Report,
+}
+
+/// Every generator of files from the game's files, in the order they need to run
+const STEPS: &[Step] = &[
+    // The definitions of the modded buildings come first, as the split buildings are checked
+    // to be defined before the other generators use them
+    Step {
+        name: "buildings-def",
+        game_subdir: BUILDINGS_DEF_GAME_DIR,
+        mod_subdir: BUILDINGS_DEF_GAME_DIR,
+        default_prefix: |_| BUILDINGS_DEF_PREFIX,
+        run: run_buildings_def,
+    },
+    Step {
+        name: "buildings",
+        game_subdir: BUILDINGS_GAME_DIR,
+        mod_subdir: BUILDINGS_GAME_DIR,
+        default_prefix: |config| config.buildings_mode.default_prefix(),
+        run: run_buildings,
+    },
+    Step {
+        name: "states",
+        game_subdir: STATES_GAME_DIR,
+        mod_subdir: STATES_GAME_DIR,
+        default_prefix: |config| config.states_mode.default_prefix(),
+        run: run_states,
+    },
+    Step {
+        name: "companies",
+        game_subdir: COMPANIES_GAME_DIR,
+        mod_subdir: COMPANIES_GAME_DIR,
+        default_prefix: |_| "",
+        run: run_companies,
+    },
+    Step {
+        name: "production-methods",
+        game_subdir: PRODUCTION_METHODS_GAME_DIR,
+        mod_subdir: PRODUCTION_METHODS_GAME_DIR,
+        default_prefix: |_| PRODUCTION_METHODS_PREFIX,
+        run: run_production_methods,
+    },
+    Step {
+        name: "state-history",
+        game_subdir: STATE_HISTORY_GAME_DIR,
+        mod_subdir: STATE_HISTORY_GAME_DIR,
+        default_prefix: |_| "",
+        run: run_state_history,
+    },
+];
+
+/// Runs every generator from `game_dir`, or the detected game install, into `mod_dir`,
+/// followed by the localization, returning the combined report of all of them. The other
+/// generators don't run if the definitions of the modded buildings fail.
+pub fn run_all(
+    game_dir: Option<&Path>,
+    mod_dir: &Path,
+    mut config: Config,
+    input: &InputArgs,
+    output: &OutputArgs,
+    log: &Log,
+) -> anyhow::Result<Report> {
+    let game_dir = resolve_game_dir(game_dir)?;
+    let mod_dir = resolve_mod_dir(mod_dir, !output.dry_run && !output.diff)?;
+    config.load_game_data(Some(&game_dir))?;
+    if !config.random_deposits.is_empty() {
+        place_random_deposits(
+            &input_files(&game_dir.join(STATES_GAME_DIR), input)?,
+            &mut config,
+        )?;
+    }
+
+    let writes = !output.dry_run && !output.diff;
+    let mut report = Report::default();
+    for step in STEPS {
+        let layout = config.output_layout(step.name);
+        let input_path = game_dir.join(step.game_subdir);
+        let output_path = mod_dir.join(layout.dir.as_deref().unwrap_or(Path::new(step.mod_subdir)));
+        if !input_path.is_dir() {
+            anyhow::bail!("Game directory is missing {}", input_path.display());
+        }
+        if writes {
+            std::fs::create_dir_all(&output_path)?;
+        }
+
+        let mut step_output = Output::for_directory(
+            output,
+            &output_path,
+            (step.default_prefix)(&config),
+            log.clone(),
+        )
+        .with_layout(output, &layout);
+        let step_report = (step.run)(&input_files(&input_path, input)?, &config, &mut step_output);
+        if step.name == "buildings-def" {
+            if step_report.failed_files() > 0 {
+                report.extend(step_report);
+                return Ok(report);
+            }
+            let generated = step_report
+                .files
+                .iter()
+                .flat_map(|file| file.buildings_defined.iter().cloned())
+                .collect::<Vec<_>>();
+            config.check_buildings(Some(&game_dir), &mod_dir, &generated)?;
+        }
+        report.extend(step_report);
+    }
+
+    // The localization isn't generated from the game's files, so it isn't one of the steps
+    let localization_dir = mod_dir.join(LOCALIZATION_MOD_DIR);
+    if writes {
+        for language in &config.localization.languages {
+            std::fs::create_dir_all(localization_dir.join(language))?;
+        }
+    }
+    let mut localization_output = Output::for_directory(output, &localization_dir, "", log.clone());
+    report.extend(run_localization(&config, &mut localization_output));
+
+    Ok(report)
+}
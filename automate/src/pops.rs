@@ -0,0 +1,35 @@
+use crate::game_data::{definition_files, read_pdx_file};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Directory of the pops history files, relative to the game's `game` directory
+const POPS_GAME_DIR: &str = "common/history/pops";
+
+/// Reads the total size of the pops each state starts with from the game's `game` directory,
+/// by the state's `STATE_*` key
+pub fn state_populations(game_dir: &Path) -> anyhow::Result<HashMap<String, u64>> {
+    let mut populations = HashMap::new();
+    for path in definition_files(&game_dir.join(POPS_GAME_DIR))? {
+        let contents = read_pdx_file(&path)?;
+        let Some(pops) = contents.get_field_block("POPS") else {
+            continue;
+        };
+        for (state_name, state_block) in pops.iter_definitions() {
+            let state = state_name.as_str();
+            let population = populations
+                .entry(state.strip_prefix("s:").unwrap_or(state).to_string())
+                .or_default();
+            for (_, region_state_block) in state_block.iter_definitions() {
+                for pop in region_state_block.get_field_blocks("create_pop") {
+                    let Some(size) = pop.get_field_value("size") else {
+                        continue;
+                    };
+                    *population += size.as_str().parse::<u64>().map_err(|_| {
+                        anyhow::anyhow!("Invalid pop size {} in {}", size.as_str(), path.display())
+                    })?;
+                }
+            }
+        }
+    }
+    Ok(populations)
+}
@@ -0,0 +1,106 @@
+use crate::cloned::{Cloning, Definitions, PlannedClone, run_cloned};
+use crate::config::{Config, ProductionMethodRule};
+use crate::output::Output;
+use crate::report::Report;
+use std::path::PathBuf;
+use tiger_lib::Token;
+use tiger_lib::block::{BV, Block, BlockItem, Field};
+
+/// Generates a file of the production methods of the modded buildings for each of `in_paths`
+/// whose production methods they are cloned from. Output files are only written if none of
+/// the input files failed.
+pub fn run_production_methods(
+    in_paths: &[PathBuf],
+    config: &Config,
+    output: &mut Output,
+) -> Report {
+    let cloning = Cloning {
+        kind: "Production method",
+        done: "production methods generated",
+        apply: modify_goods,
+        generated: |file_report| &mut file_report.production_methods_generated,
+    };
+    run_cloned(
+        in_paths,
+        config,
+        output,
+        |defined| plan_production_methods(defined, config),
+        &cloning,
+    )
+}
+
+/// Returns every production method of the modded buildings that the input files don't
+/// define, with the one of the `defined` production methods it is cloned from and its rule
+fn plan_production_methods<'a>(
+    defined: &Definitions,
+    config: &'a Config,
+) -> anyhow::Result<Vec<PlannedClone<'a, ProductionMethodRule>>> {
+    let generated = config.generated_production_methods(|key| defined.contains_key(key));
+    if let Some(name) = config
+        .production_methods
+        .keys()
+        .find(|name| !generated.contains_key(name.as_str()))
+    {
+        anyhow::bail!(
+            "Production method {} has no `source`, and no split target's production methods \
+             map one to it",
+            name
+        );
+    }
+    Ok(generated
+        .into_iter()
+        .map(|(name, (source, rule))| (name, source, rule))
+        .collect())
+}
+
+/// Prefixes of the modifiers of the goods a production method uses, with whether they are inputs
+const GOODS_MODIFIERS: &[(&str, bool)] = &[("goods_input_", true), ("goods_output_", false)];
+
+/// Scales and renames the goods of the modifiers of the production method `block`, and
+/// those of its nested blocks, by `rule`. Modifiers whose amount is scaled to 0 are removed.
+fn modify_goods(block: &mut Block, rule: &ProductionMethodRule) {
+    let items = block.drain().collect::<Vec<_>>();
+    for item in items {
+        let BlockItem::Field(Field(key, cmp, mut value)) = item else {
+            block.add_item(item);
+            continue;
+        };
+        if let BV::Block(nested) = &mut value {
+            modify_goods(nested, rule);
+            block.add_key_bv(key, cmp, value);
+            continue;
+        }
+        let Some((prefix, good, suffix, input)) = goods_modifier(key.as_str()) else {
+            block.add_key_bv(key, cmp, value);
+            continue;
+        };
+        let renamed = rule.rename_goods.get(good).map_or(good, String::as_str);
+        let key = Token::new(&format!("{}{}{}", prefix, renamed, suffix), key.loc);
+        let factor = if input {
+            rule.input_factor
+        } else {
+            rule.output_factor
+        } * rule.goods_factors.get(good).copied().unwrap_or(1.0);
+        let amount = value.get_value().and_then(Token::get_number);
+        match amount {
+            Some(amount) if suffix == "_add" => {
+                let scaled = ((amount as f32 * factor * 100.0).round() / 100.0).to_string();
+                if scaled != "0" && scaled != "-0" {
+                    block.add_key_bv(key, cmp, BV::Value(Token::new(&scaled, value.loc())));
+                }
+            }
+            _ => block.add_key_bv(key, cmp, value),
+        }
+    }
+}
+
+/// Splits the key of a goods modifier, such as `goods_input_coal_add`, into its prefix, good
+/// and suffix, and whether it is an input, if it is one
+fn goods_modifier(key: &str) -> Option<(&'static str, &str, &str, bool)> {
+    GOODS_MODIFIERS.iter().find_map(|&(prefix, input)| {
+        let rest = key.strip_prefix(prefix)?;
+        let (good, suffix) = rest.rsplit_once('_')?;
+        Some((prefix, good, &rest[good.len()..], input))
+            .filter(|_| suffix == "add" || suffix == "mult")
+    })
+}
@@ -0,0 +1,276 @@
+use crate::config::QuoteStyle;
+
+/// Characters that end an unquoted token
+const DELIMITERS: &[char] = &['=', '<', '>', '!', '?', '{', '}', '"', '#'];
+
+/// One token of a script line
+enum Piece<'a> {
+    /// Whitespace, operators and braces, written as they are
+    Other(&'a str),
+    /// A value or key, with whether it was quoted
+    Token(&'a str, bool),
+}
+
+/// Splits a script `line` into tokens and everything between them
+fn pieces(line: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while let Some(first) = rest.chars().next() {
+        let len = if first == '"' {
+            match closing_quote(rest) {
+                Some(end) => {
+                    pieces.push(Piece::Token(&rest[1..end], true));
+                    end + 1
+                }
+                // A string going on past the end of the line is kept as it is
+                None => {
+                    pieces.push(Piece::Other(rest));
+                    rest.len()
+                }
+            }
+        } else if rest.starts_with("@[") {
+            // Script math is kept as it is, spaces and all
+            let end = rest.find(']').map_or(rest.len(), |end| end + 1);
+            pieces.push(Piece::Other(&rest[..end]));
+            end
+        } else if first == '#' {
+            // Comments are kept as they are
+            pieces.push(Piece::Other(rest));
+            rest.len()
+        } else if first.is_whitespace() || DELIMITERS.contains(&first) {
+            pieces.push(Piece::Other(&rest[..first.len_utf8()]));
+            first.len_utf8()
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || DELIMITERS.contains(&c))
+                .unwrap_or(rest.len());
+            pieces.push(Piece::Token(&rest[..end], false));
+            end
+        };
+        rest = &rest[len..];
+    }
+    pieces
+}
+
+/// Position of the quote closing the quoted string that `text` starts with,
+/// skipping quotes escaped by a backslash
+fn closing_quote(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            '"' if !escaped => return Some(index),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Whether the token at `index` of `pieces` is a key, which is followed by an operator
+fn is_key(pieces: &[Piece], index: usize) -> bool {
+    pieces[index + 1..]
+        .iter()
+        .find(|piece| !matches!(piece, Piece::Other(other) if other.trim().is_empty()))
+        .is_some_and(|piece| {
+            matches!(piece, Piece::Other(other) if ["=", "<", ">", "!", "?"].contains(other))
+        })
+}
+
+/// Whether the token at `index` of `pieces` is the tag of a block, such as `rgb` in
+/// `rgb { 255 0 0 }`, which is never quoted
+fn is_tag(pieces: &[Piece], index: usize) -> bool {
+    pieces[index + 1..]
+        .iter()
+        .find(|piece| !matches!(piece, Piece::Other(other) if other.trim().is_empty()))
+        .is_some_and(|piece| matches!(piece, Piece::Other("{")))
+}
+
+/// Whether a value is written without quotes whatever the style: numbers, booleans and
+/// `@` script values and script math, which would be strings if they were quoted
+pub fn is_bare_value(value: &str) -> bool {
+    value.parse::<f64>().is_ok() || value == "yes" || value == "no" || value.starts_with('@')
+}
+
+/// Whether a value can be written without quotes
+fn can_be_unquoted(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_alphanumeric() || ['_', ':', '.', '@', '-', '\''].contains(&c))
+}
+
+/// Whether most of the values in the script `contents` that could be quoted are quoted,
+/// for matching the quotes of an input file
+pub fn quotes_values(contents: &str) -> bool {
+    let (mut quoted, mut unquoted) = (0, 0);
+    for line in contents.lines() {
+        let pieces = pieces(line);
+        for (index, piece) in pieces.iter().enumerate() {
+            if let Piece::Token(value, was_quoted) = piece
+                && !is_bare_value(value)
+                && can_be_unquoted(value)
+                && !is_key(&pieces, index)
+            {
+                if *was_quoted {
+                    quoted += 1;
+                } else {
+                    unquoted += 1;
+                }
+            }
+        }
+    }
+    quoted >= unquoted
+}
+
+/// Rewrites the values of the script `contents` to be quoted or not by `style`,
+/// where `input_quoted` is whether the input file mostly quotes them. Numbers and booleans
+/// are never quoted, and values that need quotes are always quoted.
+pub fn normalize_quotes(contents: &str, style: QuoteStyle, input_quoted: bool) -> String {
+    let quote = match style {
+        QuoteStyle::Always => true,
+        QuoteStyle::Never => false,
+        QuoteStyle::Input => input_quoted,
+    };
+    let mut normalized = String::with_capacity(contents.len());
+    for line in contents.split_inclusive('\n') {
+        let pieces = pieces(line);
+        for (index, piece) in pieces.iter().enumerate() {
+            match piece {
+                Piece::Other(other) => normalized.push_str(other),
+                Piece::Token(token, was_quoted)
+                    if is_key(&pieces, index) || is_tag(&pieces, index) =>
+                {
+                    if *was_quoted {
+                        normalized.push('"');
+                        normalized.push_str(token);
+                        normalized.push('"');
+                    } else {
+                        normalized.push_str(token);
+                    }
+                }
+                Piece::Token(value, _) => {
+                    if !is_bare_value(value) && (quote || !can_be_unquoted(value)) {
+                        normalized.push('"');
+                        normalized.push_str(value);
+                        normalized.push('"');
+                    } else {
+                        normalized.push_str(value);
+                    }
+                }
+            }
+        }
+    }
+    normalized
+}
+
+/// Rewrites the `region_state` keys of the buildings file `contents` to be `quoted` or not
+pub fn quote_region_state_keys(contents: &str, quoted: bool) -> String {
+    let mut rewritten = String::with_capacity(contents.len());
+    for line in contents.split_inclusive('\n') {
+        let key_start = line.len() - line.trim_start().len();
+        let (indent, rest) = line.split_at(key_start);
+        let key_end = rest.find(['=', ' ', '\t']).unwrap_or(rest.len());
+        let (key, rest) = rest.split_at(key_end);
+        let tag = key.trim_matches('"');
+        match tag.strip_prefix("region_state:") {
+            Some(tag) if quoted => {
+                rewritten.push_str(&format!("{}\"region_state:{}\"{}", indent, tag, rest))
+            }
+            Some(tag) => rewritten.push_str(&format!("{}region_state:{}{}", indent, tag, rest)),
+            None => rewritten.push_str(line),
+        }
+    }
+    rewritten
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_quotes_by_style() {
+        let contents = "building = \"building_textile_mill\"\nlevel = 4\nowned = yes\n";
+        assert_eq!(
+            normalize_quotes(
+                "building = building_textile_mill\n",
+                QuoteStyle::Always,
+                false
+            ),
+            "building = \"building_textile_mill\"\n"
+        );
+        assert_eq!(
+            normalize_quotes(contents, QuoteStyle::Never, true),
+            "building = building_textile_mill\nlevel = 4\nowned = yes\n"
+        );
+        assert_eq!(
+            normalize_quotes(contents, QuoteStyle::Input, true),
+            contents
+        );
+        assert_eq!(
+            normalize_quotes(contents, QuoteStyle::Input, false),
+            "building = building_textile_mill\nlevel = 4\nowned = yes\n"
+        );
+    }
+
+    #[test]
+    fn normalize_quotes_keeps_what_quotes_would_change() {
+        // Values with spaces always need quotes, while keys, block tags, script values and
+        // script math are never quoted
+        assert_eq!(
+            normalize_quotes("name = \"two words\"\n", QuoteStyle::Never, false),
+            "name = \"two words\"\n"
+        );
+        assert_eq!(
+            normalize_quotes(
+                "\"region_state:GBR\" = { color = hsv { 0.5 0.5 0.5 } }\n",
+                QuoteStyle::Always,
+                false
+            ),
+            "\"region_state:GBR\" = { color = hsv { 0.5 0.5 0.5 } }\n"
+        );
+        assert_eq!(
+            normalize_quotes(
+                "levels = @large\nreserves = @[ large * 2 ]\n",
+                QuoteStyle::Always,
+                false
+            ),
+            "levels = @large\nreserves = @[ large * 2 ]\n"
+        );
+        assert_eq!(
+            normalize_quotes("type = x # type = y\n", QuoteStyle::Always, false),
+            "type = \"x\" # type = y\n"
+        );
+    }
+
+    #[test]
+    fn normalize_quotes_keeps_strings_whole() {
+        assert_eq!(
+            normalize_quotes("name = \"say \\\"hi\\\"\"\n", QuoteStyle::Never, false),
+            "name = \"say \\\"hi\\\"\"\n"
+        );
+        assert_eq!(
+            normalize_quotes("name = \"unterminated\n", QuoteStyle::Never, false),
+            "name = \"unterminated\n"
+        );
+        assert_eq!(
+            normalize_quotes("name = \"a\"", QuoteStyle::Never, false),
+            "name = a"
+        );
+    }
+
+    #[test]
+    fn quotes_values_by_majority() {
+        assert!(quotes_values("a = \"x\"\nb = \"y\"\nc = z\nlevel = 5\n"));
+        assert!(!quotes_values("a = x\nb = y\nc = \"z\"\n"));
+    }
+
+    #[test]
+    fn region_state_keys() {
+        let contents = "\t\tregion_state:GBR = {\n\t\t\tlevel = 1\n\t\t}\n";
+        let quoted = "\t\t\"region_state:GBR\" = {\n\t\t\tlevel = 1\n\t\t}\n";
+        assert_eq!(quote_region_state_keys(contents, true), quoted);
+        assert_eq!(quote_region_state_keys(quoted, false), contents);
+        assert_eq!(quote_region_state_keys(quoted, true), quoted);
+        assert_eq!(quote_region_state_keys(contents, false), contents);
+    }
+}
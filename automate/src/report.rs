@@ -0,0 +1,346 @@
+use crate::log::{Event, Log};
+use crate::output::Output;
+use clap::Args;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Args)]
+pub struct ReportArgs {
+    /// Write a structured report of the run, as `json=<path>`
+    #[arg(long, value_name = "FORMAT=PATH")]
+    pub report: Option<ReportTarget>,
+}
+
+/// Where and in which format to write a run report
+#[derive(Clone)]
+pub struct ReportTarget {
+    path: PathBuf,
+}
+
+impl FromStr for ReportTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("json", path)) if !path.is_empty() => Ok(Self {
+                path: PathBuf::from(path),
+            }),
+            Some((format, _)) if format != "json" => Err(format!(
+                "unsupported report format `{}`, expected `json`",
+                format
+            )),
+            _ => Err("expected `json=<path>`".to_string()),
+        }
+    }
+}
+
+impl ReportArgs {
+    /// Writes `report` to the requested report file, if any
+    pub fn write(&self, report: &Report) -> anyhow::Result<()> {
+        if let Some(target) = &self.report {
+            serde_json::to_writer_pretty(BufWriter::new(File::create(&target.path)?), report)?;
+        }
+        Ok(())
+    }
+}
+
+/// Structured record of everything a run did
+#[derive(Serialize, Default)]
+pub struct Report {
+    pub files: Vec<FileReport>,
+    /// Why the generated files could not be moved into place, if they couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_error: Option<String>,
+}
+
+impl Report {
+    /// Number of input files that failed to process, counting every file
+    /// as failed when the generated files could not be written
+    pub fn failed_files(&self) -> usize {
+        if self.commit_error.is_some() {
+            return self.files.len();
+        }
+        self.files
+            .iter()
+            .filter(|file| file.error.is_some())
+            .count()
+    }
+
+    /// Adds the files and errors of a later run to this report
+    pub fn extend(&mut self, other: Report) {
+        self.files.extend(other.files);
+        if self.commit_error.is_none() {
+            self.commit_error = other.commit_error;
+        }
+    }
+
+    /// Writes the files staged in `output` if every file was processed successfully
+    pub fn commit(&mut self, output: &mut Output) {
+        if self.failed_files() > 0 {
+            return;
+        }
+        if let Err(err) = output.commit() {
+            self.commit_error = Some(format!("{:#}", err));
+        }
+    }
+
+    /// Prints the warnings and errors of every file, the levels split buildings converted into each
+    /// of their targets per file and in total, the keys changed in modified states, the arable
+    /// resources removed from states, and the entries that couldn't be split, followed by
+    /// a table of how many files were processed successfully, skipped or failed, how many
+    /// entries were skipped or invalid, how many states were modified or unchanged, and how
+    /// many warnings there were
+    pub fn print_summary(&self, log: &Log) {
+        for file in &self.files {
+            for message in &file.warnings {
+                log.event(&Event::Warning {
+                    input: file.input.clone(),
+                    message: message.clone(),
+                });
+            }
+            log.event(&Event::File {
+                input: file.input.clone(),
+                output: file.output.clone(),
+                splits: file.splits.len(),
+                states_modified: file.states_modified.len(),
+                companies_modified: file.companies_modified.len(),
+                skipped: file.skipped.len(),
+                warnings: file.warnings.len(),
+                error: file.error.clone(),
+            });
+        }
+        for file in &self.files {
+            log_conversions(log, Some(&file.input), &file.splits);
+        }
+        if self
+            .files
+            .iter()
+            .filter(|file| !file.splits.is_empty())
+            .count()
+            > 1
+        {
+            log_conversions(log, None, self.files.iter().flat_map(|file| &file.splits));
+        }
+        for file in &self.files {
+            for change in &file.state_changes {
+                log.event(&Event::StateModified {
+                    input: file.input.clone(),
+                    state: change.state.clone(),
+                    fields: change.fields.clone(),
+                });
+            }
+            for removed in &file.resources_removed {
+                log.event(&Event::ResourcesRemoved {
+                    input: file.input.clone(),
+                    state: removed.state.clone(),
+                    resources: removed.resources.clone(),
+                });
+            }
+        }
+        // Entries that couldn't be split are listed together, after every file was processed
+        for file in &self.files {
+            for entry in &file.invalid {
+                log.event(&Event::InvalidEntry {
+                    input: file.input.clone(),
+                    state: entry.state.clone(),
+                    region_state: entry.region_state.clone(),
+                    building: entry.building.clone(),
+                    reason: entry.reason.clone(),
+                });
+            }
+        }
+        if let Some(error) = &self.commit_error {
+            log.event(&Event::Error {
+                message: error.clone(),
+            });
+        }
+
+        let failed = self.failed_files();
+        let skipped = self
+            .files
+            .iter()
+            .filter(|file| file.error.is_none() && file.output.is_none())
+            .count();
+        log.event(&Event::Summary {
+            files_ok: self.files.len().saturating_sub(failed + skipped),
+            files_skipped: skipped,
+            files_failed: failed,
+            entries_skipped: self.files.iter().map(|file| file.skipped.len()).sum(),
+            entries_invalid: self.files.iter().map(|file| file.invalid.len()).sum(),
+            states_modified: self.files.iter().map(|file| file.state_changes.len()).sum(),
+            states_unchanged: self
+                .files
+                .iter()
+                .map(|file| file.states_unchanged.len())
+                .sum(),
+            warnings: self.files.iter().map(|file| file.warnings.len()).sum(),
+        });
+    }
+}
+
+/// Logs how many levels of each building were converted into each of its targets by `splits`,
+/// which were made in `input` or in every file
+fn log_conversions<'a>(
+    log: &Log,
+    input: Option<&PathBuf>,
+    splits: impl IntoIterator<Item = &'a SplitReport>,
+) {
+    // Levels of each split building, and the levels moved to each of its targets
+    let mut conversions: BTreeMap<&str, (u32, BTreeMap<&str, u32>)> = BTreeMap::new();
+    for split in splits {
+        let (total_levels, targets) = conversions.entry(&split.building).or_default();
+        *total_levels += u32::from(split.total_levels);
+        for target in &split.targets {
+            *targets.entry(&target.building).or_default() += u32::from(target.levels);
+        }
+    }
+    for (building, (total_levels, targets)) in conversions {
+        for (target, levels) in targets {
+            log.event(&Event::Conversion {
+                input: input.cloned(),
+                building: building.to_string(),
+                target: target.to_string(),
+                levels,
+                total_levels,
+            });
+        }
+    }
+}
+
+/// What was done with a single input file
+#[derive(Serialize)]
+pub struct FileReport {
+    pub input: PathBuf,
+    /// Name of the generated file, or `None` if the input file was skipped
+    pub output: Option<String>,
+    /// Buildings that were split, for the Buildings command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub splits: Vec<SplitReport>,
+    /// Buildings whose levels were scaled, for the Buildings command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scaled: Vec<ScaledReport>,
+    /// States whose resources were modified, for the States command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub states_modified: Vec<String>,
+    /// Keys that changed in each modified state, for the States command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub state_changes: Vec<StateChange>,
+    /// States that were passed through unchanged, for the States command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub states_unchanged: Vec<String>,
+    /// Arable resources removed from states, for the States command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resources_removed: Vec<RemovedResources>,
+    /// Company types whose buildings were extended, for the Companies command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub companies_modified: Vec<String>,
+    /// Production methods of modded buildings that were generated,
+    /// for the ProductionMethods command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub production_methods_generated: Vec<String>,
+    /// Modded buildings whose definitions were generated, for the BuildingsDef command
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buildings_defined: Vec<String>,
+    pub skipped: Vec<SkippedEntry>,
+    /// Entries of split buildings that couldn't be split and were left as they were,
+    /// with why as their reason
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub invalid: Vec<SkippedEntry>,
+    pub warnings: Vec<String>,
+    /// Why processing the file failed, if it did
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    pub fn new(input: PathBuf) -> Self {
+        Self {
+            input,
+            output: None,
+            splits: Vec::new(),
+            scaled: Vec::new(),
+            states_modified: Vec::new(),
+            state_changes: Vec::new(),
+            states_unchanged: Vec::new(),
+            resources_removed: Vec::new(),
+            companies_modified: Vec::new(),
+            production_methods_generated: Vec::new(),
+            buildings_defined: Vec::new(),
+            skipped: Vec::new(),
+            invalid: Vec::new(),
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+/// A vanilla building that was split into itself and modded buildings
+#[derive(Serialize)]
+pub struct SplitReport {
+    pub state: String,
+    pub region_state: String,
+    pub building: String,
+    pub total_levels: u16,
+    pub targets: Vec<TargetReport>,
+    pub owners: Vec<OwnerReport>,
+}
+
+/// A vanilla building whose levels were scaled by its rule's `scale`
+#[derive(Serialize)]
+pub struct ScaledReport {
+    pub state: String,
+    pub region_state: String,
+    pub building: String,
+    pub total_levels: u16,
+    pub levels: u16,
+}
+
+/// The keys of a state that were changed by the States command
+#[derive(Serialize)]
+pub struct StateChange {
+    pub state: String,
+    pub fields: Vec<String>,
+}
+
+/// Arable resources removed from a state by the resource rules
+#[derive(Serialize)]
+pub struct RemovedResources {
+    pub state: String,
+    pub resources: Vec<String>,
+}
+
+/// How many levels of a split building were moved to one modded building
+#[derive(Serialize)]
+pub struct TargetReport {
+    pub building: String,
+    pub levels: u16,
+}
+
+/// How many levels of a split building one owner had, and had moved to each modded building
+#[derive(Serialize)]
+pub struct OwnerReport {
+    /// The owning building type, or `None` for direct country and company ownership
+    pub building: Option<String>,
+    /// The owning company type, for company ownership
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub company: Option<String>,
+    pub country: String,
+    /// `STATE_*` key of the owning building's region
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub levels: u16,
+    /// Levels moved to each of the split's targets, in the same order
+    pub moved_levels: Vec<u16>,
+}
+
+/// A building of a split type that was left untouched
+#[derive(Serialize)]
+pub struct SkippedEntry {
+    pub state: String,
+    pub region_state: String,
+    pub building: String,
+    pub reason: String,
+}
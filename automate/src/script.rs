@@ -0,0 +1,205 @@
+use crate::buildings::Owner;
+use crate::config::SplitTarget;
+use rhai::{AST, Array, Dynamic, Engine, Map, Scope};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tiger_lib::block::Block;
+
+/// Name of the function a split script must define
+const SPLIT_FUNCTION: &str = "split";
+
+/// A Rhai script deciding how buildings are split, for cases the split rules can't express.
+///
+/// Its `split(entry)` function is called for every building that has a split rule, with a map of
+/// the `state`, `region_state`, `building` block, `owners`, `total_levels`, the rule's `targets`
+/// and the `levels` the rule would move to each of them. It returns `()` to use the rule's levels,
+/// `false` to leave the building alone, or the levels to move to each target, as a single number
+/// for rules with one target or as an array with one number for each target.
+pub struct SplitScript {
+    engine: Engine,
+    ast: AST,
+}
+
+/// What a split script decided for a building
+pub enum ScriptDecision {
+    /// Split by the rule's own levels
+    Rule,
+    Skip,
+    /// Levels to move to each of the rule's targets
+    Levels(Vec<u16>),
+}
+
+impl SplitScript {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|err| anyhow::anyhow!("Invalid split script {}: {}", path.display(), err))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script for `building`, split by its rule into `levels` of `targets`
+    #[allow(clippy::too_many_arguments)]
+    pub fn decide(
+        &self,
+        state: &str,
+        region_state: &str,
+        building: &Block,
+        owners: &[Owner],
+        total_levels: u16,
+        targets: &[SplitTarget],
+        levels: &[u16],
+    ) -> anyhow::Result<ScriptDecision> {
+        let mut entry = Map::new();
+        entry.insert("state".into(), Dynamic::from(state.to_string()));
+        entry.insert(
+            "region_state".into(),
+            Dynamic::from(region_state.to_string()),
+        );
+        entry.insert("building".into(), block_to_dynamic(building));
+        entry.insert(
+            "owners".into(),
+            Dynamic::from_array(owners.iter().map(owner_to_dynamic).collect()),
+        );
+        entry.insert(
+            "total_levels".into(),
+            Dynamic::from(i64::from(total_levels)),
+        );
+        entry.insert(
+            "targets".into(),
+            Dynamic::from_array(
+                targets
+                    .iter()
+                    .map(|target| Dynamic::from(target.building.clone()))
+                    .collect(),
+            ),
+        );
+        entry.insert(
+            "levels".into(),
+            Dynamic::from_array(
+                levels
+                    .iter()
+                    .map(|&levels| Dynamic::from(i64::from(levels)))
+                    .collect(),
+            ),
+        );
+
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, SPLIT_FUNCTION, (entry,))
+            .map_err(|err| anyhow::anyhow!("Split script failed: {}", err))?;
+
+        if result.is_unit() {
+            return Ok(ScriptDecision::Rule);
+        }
+        if let Ok(split) = result.as_bool() {
+            return Ok(if split {
+                ScriptDecision::Rule
+            } else {
+                ScriptDecision::Skip
+            });
+        }
+        let levels = if let Ok(levels) = result.as_int() {
+            vec![to_levels(levels)?]
+        } else if let Ok(levels) = result.clone().into_array() {
+            levels
+                .into_iter()
+                .map(|levels| {
+                    levels
+                        .as_int()
+                        .map_err(|_| anyhow::anyhow!("Split script returned non-integer levels"))
+                        .and_then(to_levels)
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        } else {
+            anyhow::bail!(
+                "Split script returned a {}, expected (), a bool, levels or an array of levels",
+                result.type_name()
+            );
+        };
+
+        if levels.len() != targets.len() {
+            anyhow::bail!(
+                "Split script returned levels for {} targets, but the rule has {}",
+                levels.len(),
+                targets.len()
+            );
+        }
+        if levels
+            .iter()
+            .map(|&target_levels| u32::from(target_levels))
+            .sum::<u32>()
+            > u32::from(total_levels)
+        {
+            anyhow::bail!(
+                "Split script moved more levels than the building has ({})",
+                total_levels
+            );
+        }
+        Ok(ScriptDecision::Levels(levels))
+    }
+}
+
+fn to_levels(levels: i64) -> anyhow::Result<u16> {
+    u16::try_from(levels).map_err(|_| anyhow::anyhow!("Split script returned {} levels", levels))
+}
+
+fn owner_to_dynamic(owner: &Owner) -> Dynamic {
+    let mut map = Map::new();
+    map.insert(
+        "building".into(),
+        owner.building.clone().map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    map.insert(
+        "company".into(),
+        owner.company.clone().map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    map.insert("country".into(), Dynamic::from(owner.country.clone()));
+    map.insert("levels".into(), Dynamic::from(i64::from(owner.levels)));
+    map.insert(
+        "region".into(),
+        owner.region.clone().map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    Dynamic::from_map(map)
+}
+
+/// Converts a script block into a map of its fields, or an array for blocks of plain values.
+/// Fields that appear more than once become an array of their values.
+fn block_to_dynamic(block: &Block) -> Dynamic {
+    let mut fields = block.iter_assignments_and_definitions().peekable();
+    if fields.peek().is_none() {
+        return Dynamic::from_array(
+            block
+                .iter_values()
+                .map(|value| Dynamic::from(value.to_string()))
+                .collect(),
+        );
+    }
+
+    let mut values: BTreeMap<&str, Array> = BTreeMap::new();
+    for (key, value) in fields {
+        let value = match value.get_block() {
+            Some(block) => block_to_dynamic(block),
+            None => Dynamic::from(
+                value
+                    .get_value()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+            ),
+        };
+        values.entry(key.as_str()).or_default().push(value);
+    }
+    Dynamic::from_map(
+        values
+            .into_iter()
+            .map(|(key, mut values)| {
+                let value = if values.len() == 1 {
+                    values.remove(0)
+                } else {
+                    Dynamic::from_array(values)
+                };
+                (key.into(), value)
+            })
+            .collect(),
+    )
+}
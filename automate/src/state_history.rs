@@ -0,0 +1,393 @@
+use crate::buildings::write_item;
+use crate::config::{Config, StateHistoryRule};
+use crate::game_data::read_pdx_file;
+use crate::output::Output;
+use crate::report::{FileReport, Report, StateChange};
+use crate::states::SourceFormat;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tiger_lib::Token;
+use tiger_lib::block::Eq::Single;
+use tiger_lib::block::{BV, Block, BlockItem, Comparator, Field};
+
+/// Key of the block of a state history file with the history of each of its states
+const STATES_KEY: &str = "STATES";
+
+/// Generates the modded state history files for each of `in_paths`, with the homeland, claim
+/// and ownership changes of the config's state history rules. Output files are only written
+/// if none of the input files failed.
+pub fn run_state_history(in_paths: &[PathBuf], config: &Config, output: &mut Output) -> Report {
+    let progress = output.begin_run(in_paths.len());
+    let mut report = Report::default();
+    let mut total_states_modified = 0;
+    for in_path in in_paths {
+        let mut file_report = FileReport::new(in_path.clone());
+        if let Err(err) = generate_state_history_file(in_path, config, output, &mut file_report) {
+            file_report.error = Some(format!("{:#}", err));
+        }
+
+        total_states_modified += file_report.states_modified.len();
+        report.files.push(file_report);
+        progress.set_message(format!("{} states modified", total_states_modified));
+        progress.inc(1);
+    }
+    report.commit(output);
+    progress.finish();
+
+    report
+}
+
+/// Generates the modded state history file for `in_path` and hands it to `output`,
+/// unless none of its states were modified
+fn generate_state_history_file(
+    in_path: &Path,
+    config: &Config,
+    output: &mut Output,
+    file_report: &mut FileReport,
+) -> anyhow::Result<()> {
+    let out_name = output.file_name(in_path);
+    let mut buffer = Vec::new();
+    if create_modded_state_history_file(in_path, config, &mut buffer, file_report)? {
+        output.write(
+            &out_name,
+            &buffer,
+            &format!("{} states modified", file_report.states_modified.len()),
+        )?;
+        file_report.output = Some(out_name);
+    }
+
+    Ok(())
+}
+
+/// Writes the state history file at `in_path` to `out_file` with the changes of the config's
+/// state history rules, recording the states that were modified in `report`.
+/// Returns `false` if none of its states were modified and it should be skipped.
+pub fn create_modded_state_history_file(
+    in_path: &Path,
+    config: &Config,
+    out_file: &mut impl Write,
+    report: &mut FileReport,
+) -> anyhow::Result<bool> {
+    let mut contents = read_pdx_file(in_path)?;
+    let mut items = contents.drain().collect::<Vec<_>>();
+    for item in &mut items {
+        let BlockItem::Field(Field(key, _, BV::Block(states))) = item else {
+            continue;
+        };
+        if !key.is(STATES_KEY) {
+            continue;
+        }
+        let state_items = states.drain().collect::<Vec<_>>();
+        for mut state_item in state_items {
+            if let BlockItem::Field(Field(state_name, _, BV::Block(state_block))) = &mut state_item
+            {
+                let rules = config
+                    .state_history
+                    .iter()
+                    .filter(|rule| rule.applies_to(state_name.as_str(), &config.game_data))
+                    .collect::<Vec<_>>();
+                let fields = modify_state_history(state_name.as_str(), state_block, &rules, report);
+                if !fields.is_empty() {
+                    report.states_modified.push(state_name.to_string());
+                    report.state_changes.push(StateChange {
+                        state: state_name.to_string(),
+                        fields: fields.into_iter().map(ToString::to_string).collect(),
+                    });
+                }
+            }
+            states.add_item(state_item);
+        }
+    }
+    if report.states_modified.is_empty() {
+        return Ok(false);
+    }
+
+    let format = SourceFormat::read(&std::fs::read(in_path)?, config.line_endings);
+    let mut buffer = Vec::new();
+    for item in &items {
+        write_item(&mut buffer, item, 0)?;
+        writeln!(buffer)?;
+    }
+    out_file.write_all(format.apply(&String::from_utf8_lossy(&buffer)).as_bytes())?;
+
+    Ok(true)
+}
+
+/// Applies the state history `rules` to the history `state_block` of `state_name` in order,
+/// recording warnings in `report`. Returns the keys of the state that changed.
+fn modify_state_history(
+    state_name: &str,
+    state_block: &mut Block,
+    rules: &[&StateHistoryRule],
+    report: &mut FileReport,
+) -> Vec<&'static str> {
+    let (mut homelands_modified, mut claims_modified, mut owner_modified) = (false, false, false);
+    for rule in rules {
+        homelands_modified |= change_values(
+            state_block,
+            "add_homeland",
+            "cu:",
+            &rule.add_homelands,
+            &rule.remove_homelands,
+        );
+        claims_modified |= change_values(
+            state_block,
+            "add_claim",
+            "c:",
+            &rule.add_claims,
+            &rule.remove_claims,
+        );
+        if let Some(owner) = &rule.owner {
+            owner_modified |= set_owner(state_name, state_block, &scoped("c:", owner), report);
+        }
+    }
+    [
+        ("create_state", owner_modified),
+        ("add_homeland", homelands_modified),
+        ("add_claim", claims_modified),
+    ]
+    .into_iter()
+    .filter(|(_, modified)| *modified)
+    .map(|(key, _)| key)
+    .collect()
+}
+
+/// Returns `name` with the scope `prefix`, which it may already have
+fn scoped(prefix: &str, name: &str) -> String {
+    format!("{}{}", prefix, name.strip_prefix(prefix).unwrap_or(name))
+}
+
+/// Removes the `key` fields of `block` whose value is one of `remove`, and adds a `key` field
+/// for each of `add` that it doesn't have yet, with their scope `prefix`.
+/// Returns whether any field changed.
+fn change_values(
+    block: &mut Block,
+    key: &str,
+    prefix: &str,
+    add: &[String],
+    remove: &[String],
+) -> bool {
+    let remove = remove
+        .iter()
+        .map(|name| scoped(prefix, name))
+        .collect::<Vec<_>>();
+    let mut changed = false;
+    let items = block.drain().collect::<Vec<_>>();
+    for item in items {
+        if let BlockItem::Field(Field(field_key, _, BV::Value(value))) = &item
+            && field_key.is(key)
+            && remove.iter().any(|name| value.is(name))
+        {
+            changed = true;
+            continue;
+        }
+        block.add_item(item);
+    }
+    for name in add.iter().map(|name| scoped(prefix, name)) {
+        if !block
+            .get_field_values(key)
+            .iter()
+            .any(|value| value.is(&name))
+        {
+            block.add_key_bv(
+                Token::new(key, block.loc),
+                Comparator::Equals(Single),
+                BV::Value(Token::new(&name, block.loc)),
+            );
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Gives every province of the state `state_name` to `owner`, in the first `create_state`
+/// of its history `state_block` with the `owned_provinces` of the others added to it,
+/// warning in `report` about the countries that lose their part of the state.
+/// Returns whether the ownership changed.
+fn set_owner(
+    state_name: &str,
+    state_block: &mut Block,
+    owner: &str,
+    report: &mut FileReport,
+) -> bool {
+    let creates = state_block.get_field_blocks("create_state");
+    let Some(first) = creates.first() else {
+        report.warnings.push(format!(
+            "{} has no `create_state` to give to {}",
+            state_name, owner
+        ));
+        return false;
+    };
+    let countries = creates
+        .iter()
+        .filter_map(|create| create.get_field_value("country"))
+        .collect::<Vec<_>>();
+    if creates.len() == 1 && countries.iter().any(|country| country.is(owner)) {
+        return false;
+    }
+    let previous = countries
+        .iter()
+        .filter(|country| !country.is(owner))
+        .map(|country| country.to_string())
+        .collect::<Vec<_>>();
+    let mut provinces = Block::new(first.loc);
+    for province in creates
+        .iter()
+        .flat_map(|create| create.get_field_list("owned_provinces").unwrap_or_default())
+    {
+        provinces.add_value(province);
+    }
+
+    let mut merged = Block::new(first.loc);
+    for item in first.iter_items() {
+        match item {
+            BlockItem::Field(Field(key, cmp, value)) if key.is("country") => {
+                merged.add_key_bv(key.clone(), *cmp, BV::Value(Token::new(owner, value.loc())))
+            }
+            BlockItem::Field(Field(key, cmp, _)) if key.is("owned_provinces") => {
+                merged.add_key_bv(key.clone(), *cmp, BV::Block(provinces.clone()));
+            }
+            item => merged.add_item(item.clone()),
+        }
+    }
+    // The provinces of the others still go to the owner if the first has none of its own
+    if merged.get_field_block("owned_provinces").is_none()
+        && provinces.iter_items().next().is_some()
+    {
+        merged.add_key_bv(
+            Token::new("owned_provinces", first.loc),
+            Comparator::Equals(Single),
+            BV::Block(provinces),
+        );
+    }
+    let mut merged = Some(merged);
+    let items = state_block.drain().collect::<Vec<_>>();
+    for item in items {
+        match item {
+            BlockItem::Field(Field(key, cmp, _)) if key.is("create_state") => {
+                if let Some(merged) = merged.take() {
+                    state_block.add_key_bv(key, cmp, BV::Block(merged));
+                }
+            }
+            item => state_block.add_item(item),
+        }
+    }
+    if !previous.is_empty() {
+        report.warnings.push(format!(
+            "{} now belongs to {}, but the pops and buildings history of {} in it is left as it was",
+            state_name,
+            owner,
+            previous.join(", ")
+        ));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Block {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("states.txt");
+        std::fs::write(&path, contents).unwrap();
+        read_pdx_file(&path).unwrap()
+    }
+
+    fn values(block: &Block, key: &str) -> Vec<String> {
+        block
+            .get_field_values(key)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn provinces(create: &Block) -> Vec<String> {
+        create
+            .get_field_list("owned_provinces")
+            .unwrap_or_default()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn change_homelands() {
+        let mut state = parse("add_homeland = cu:british add_homeland = cu:irish");
+        let changed = change_values(
+            &mut state,
+            "add_homeland",
+            "cu:",
+            &["scottish".to_string(), "cu:british".to_string()],
+            &["irish".to_string()],
+        );
+        assert!(changed);
+        assert_eq!(
+            values(&state, "add_homeland"),
+            vec!["cu:british", "cu:scottish"]
+        );
+
+        let changed = change_values(
+            &mut state,
+            "add_homeland",
+            "cu:",
+            &["british".to_string()],
+            &["welsh".to_string()],
+        );
+        assert!(!changed);
+        assert_eq!(
+            values(&state, "add_homeland"),
+            vec!["cu:british", "cu:scottish"]
+        );
+    }
+
+    #[test]
+    fn set_owner_merges_create_states() {
+        let mut state = parse(
+            "create_state = { country = c:GBR owned_provinces = { x000001 x000002 } state_type = incorporated }
+            add_homeland = cu:british
+            create_state = { country = c:FRA owned_provinces = { x000003 } }",
+        );
+        let mut report = FileReport::new(PathBuf::new());
+        assert!(set_owner("STATE_A", &mut state, "c:GBR", &mut report));
+
+        let creates = state.get_field_blocks("create_state");
+        let [create] = creates.as_slice() else {
+            panic!("{} create_state", creates.len());
+        };
+        assert_eq!(values(create, "country"), vec!["c:GBR"]);
+        assert_eq!(provinces(create), vec!["x000001", "x000002", "x000003"]);
+        assert_eq!(values(create, "state_type"), vec!["incorporated"]);
+        assert_eq!(values(&state, "add_homeland"), vec!["cu:british"]);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(
+            report.warnings[0].contains("c:FRA"),
+            "{}",
+            report.warnings[0]
+        );
+    }
+
+    #[test]
+    fn set_owner_changes_the_owner() {
+        let mut state = parse("create_state = { country = c:GBR owned_provinces = { x000001 } }");
+        let mut report = FileReport::new(PathBuf::new());
+        assert!(!set_owner("STATE_A", &mut state, "c:GBR", &mut report));
+        assert!(set_owner("STATE_A", &mut state, "c:FRA", &mut report));
+        let create = state.get_field_block("create_state").unwrap();
+        assert_eq!(values(create, "country"), vec!["c:FRA"]);
+        assert_eq!(provinces(create), vec!["x000001"]);
+    }
+
+    #[test]
+    fn set_owner_adds_missing_provinces() {
+        let mut state = parse(
+            "create_state = { country = c:GBR }
+            create_state = { country = c:FRA owned_provinces = { x000003 } }",
+        );
+        let mut report = FileReport::new(PathBuf::new());
+        assert!(set_owner("STATE_A", &mut state, "c:FRA", &mut report));
+        let create = state.get_field_block("create_state").unwrap();
+        assert_eq!(values(create, "country"), vec!["c:FRA"]);
+        assert_eq!(provinces(create), vec!["x000003"]);
+    }
+}
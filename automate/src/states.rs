@@ -0,0 +1,1499 @@
+use crate::BOM_CHAR;
+use crate::buildings::{SeededRng, write_item};
+use crate::config::{
+    ArableLandRule, Config, DiscoverableResource, LineEndings, ResourceMode, ResourceRule,
+    StatesGrouping, StatesMode,
+};
+use crate::game_data::GameData;
+use crate::game_data::read_pdx_file;
+use crate::output::Output;
+use crate::report::{FileReport, RemovedResources, Report, StateChange};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tiger_lib::Token;
+use tiger_lib::block::Eq::Single;
+use tiger_lib::block::{BV, Block, BlockItem, Comparator, Field};
+
+/// Generates the modded states files for each of `in_paths`, or for each group of their states
+/// when states are regrouped. Output files are only written if none of the input files failed.
+pub fn run_states(in_paths: &[PathBuf], config: &Config, output: &mut Output) -> Report {
+    let progress = output.begin_run(in_paths.len());
+    let mut report = Report::default();
+    let mut total_states_modified = 0;
+    let mut groups = BTreeMap::new();
+    for in_path in in_paths {
+        let mut file_report = FileReport::new(in_path.clone());
+        let generated = match config.states_grouping {
+            StatesGrouping::Input => {
+                generate_states_file(in_path, config, output, &mut file_report)
+            }
+            grouping => group_states(
+                in_path,
+                config,
+                grouping,
+                output,
+                &mut groups,
+                &mut file_report,
+            ),
+        };
+        if let Err(err) = generated {
+            file_report.error = Some(format!("{:#}", err));
+        }
+
+        total_states_modified += file_report.states_modified.len();
+        report.files.push(file_report);
+        progress.set_message(format!("{} states modified", total_states_modified));
+        progress.inc(1);
+    }
+    if report.failed_files() == 0
+        && let Err(err) = write_grouped_states(groups, output)
+    {
+        report.commit_error = Some(format!("{:#}", err));
+    }
+    report.commit(output);
+    progress.finish();
+
+    report
+}
+
+/// Generates the modded states file for `in_path` and hands it to `output`
+fn generate_states_file(
+    in_path: &Path,
+    config: &Config,
+    output: &mut Output,
+    file_report: &mut FileReport,
+) -> anyhow::Result<()> {
+    let out_name = output.file_name(in_path);
+    let existing = match output.merge_path(&out_name) {
+        Some(path) => Some(read_pdx_file(&path)?),
+        None => None,
+    };
+    let mut buffer = Vec::new();
+    if create_modded_states_file(in_path, config, existing.as_ref(), &mut buffer, file_report)? {
+        output.write(
+            &out_name,
+            &buffer,
+            &format!("{} states modified", file_report.states_modified.len()),
+        )?;
+        file_report.output = Some(out_name);
+    }
+
+    Ok(())
+}
+
+/// Places the config's random deposits in states of the states files `in_paths` picked with its
+/// `deposit_seed`, adding them to its discoverable resources. Deposits go into land states of
+/// the files that aren't excluded and don't have a `resource` block of their type yet, or into
+/// every such state if there are fewer than their count. States are picked in the order of
+/// their names, so the same seed places the same deposits whatever order the files are in.
+pub fn place_random_deposits(in_paths: &[PathBuf], config: &mut Config) -> anyhow::Result<()> {
+    if config.random_deposits.is_empty() {
+        return Ok(());
+    }
+    let mut states = BTreeMap::new();
+    for in_path in in_paths {
+        if in_path
+            .file_name()
+            .is_some_and(|file_name| config.states_exclude.is_match(file_name))
+        {
+            continue;
+        }
+        for (state_name, state_block) in read_pdx_file(in_path)?.iter_definitions() {
+            if !is_sea_state(state_block, &config.game_data) {
+                states.insert(state_name.to_string(), state_block.clone());
+            }
+        }
+    }
+
+    let mut placed = Vec::new();
+    for deposits in &config.random_deposits {
+        let mut candidates = states
+            .iter()
+            .filter(|(_, state_block)| {
+                !state_block
+                    .get_field_blocks("resource")
+                    .iter()
+                    .any(|resource| {
+                        resource
+                            .get_field_value("type")
+                            .is_some_and(|t| t.as_str() == deposits.r#type)
+                    })
+            })
+            .map(|(state_name, state_block)| {
+                let provinces = state_block.get_field_list("provinces").unwrap_or_default();
+                let terrain = config
+                    .game_data
+                    .state_terrain(provinces.iter().map(Token::as_str));
+                (
+                    state_name.clone(),
+                    u64::from(deposits.weight(state_name, terrain, &config.game_data)),
+                )
+            })
+            .filter(|(_, weight)| *weight > 0)
+            .collect::<Vec<_>>();
+        let mut rng = SeededRng::new(config.deposit_seed, &[&deposits.r#type]);
+        let mut picked = Vec::new();
+        while picked.len() < deposits.count as usize && !candidates.is_empty() {
+            let total = candidates.iter().map(|(_, weight)| weight).sum::<u64>();
+            let mut pick = rng.next_u64() % total;
+            let mut index = 0;
+            while pick >= candidates[index].1 {
+                pick -= candidates[index].1;
+                index += 1;
+            }
+            picked.push(candidates.remove(index).0);
+        }
+        placed.push(deposits.placed(picked));
+    }
+    config.discoverable_resources.extend(placed);
+
+    Ok(())
+}
+
+/// Modded states of the input files going into the same generated file, by its name,
+/// with the format of the first input file they came from and the game's states that the
+/// existing output file is merged with
+type StateGroups = BTreeMap<String, (SourceFormat, Vec<BlockItem>, HashMap<String, Block>)>;
+
+/// Adds the modded states of the states file at `in_path` to the generated files of `groups`
+/// that `grouping` puts them in
+fn group_states(
+    in_path: &Path,
+    config: &Config,
+    grouping: StatesGrouping,
+    output: &Output,
+    groups: &mut StateGroups,
+    file_report: &mut FileReport,
+) -> anyhow::Result<()> {
+    let merged = |state_name: &str| {
+        output
+            .merge_path(&group_file_name(
+                Some(state_name),
+                in_path,
+                config,
+                grouping,
+                output,
+            ))
+            .is_some()
+    };
+    let Some((format, states, mut originals)) =
+        modify_states(in_path, config, merged, file_report)?
+    else {
+        return Ok(());
+    };
+    let mut out_names = Vec::new();
+    for state in states {
+        let state_name = match &state {
+            BlockItem::Field(Field(state_name, _, _)) => Some(state_name.as_str()),
+            BlockItem::Value(_) => None,
+        };
+        let out_name = group_file_name(state_name, in_path, config, grouping, output);
+        if !out_names.contains(&out_name) {
+            out_names.push(out_name.clone());
+        }
+        let group = groups
+            .entry(out_name)
+            .or_insert_with(|| (format.clone(), Vec::new(), HashMap::new()));
+        if let Some(state_name) = state_name
+            && let Some(original) = originals.remove(state_name)
+        {
+            group.2.insert(state_name.to_string(), original);
+        }
+        group.1.push(state);
+    }
+    if !out_names.is_empty() {
+        file_report.output = Some(out_names.join(", "));
+    }
+    Ok(())
+}
+
+/// Returns the name of the generated file that `grouping` puts the state `state_name` of the
+/// states file at `in_path` in, or its other items without a `state_name`
+fn group_file_name(
+    state_name: Option<&str>,
+    in_path: &Path,
+    config: &Config,
+    grouping: StatesGrouping,
+    output: &Output,
+) -> String {
+    let region = match (state_name, grouping) {
+        (Some(state_name), StatesGrouping::StrategicRegion) => {
+            let state_name = state_name.strip_prefix("INJECT:").unwrap_or(state_name);
+            config.game_data.strategic_regions.get(state_name)
+        }
+        _ => None,
+    };
+    // States without a strategic region stay in a file of their input file
+    match (grouping, region) {
+        (StatesGrouping::Single, _) => output.file_name(Path::new(SINGLE_STATES_FILE)),
+        (_, Some(region)) => output.file_name(Path::new(&format!("{}.txt", region))),
+        (_, None) => output.file_name(in_path),
+    }
+}
+
+/// Name of the generated file of every state when they are grouped into a single file,
+/// before the naming template
+const SINGLE_STATES_FILE: &str = "states.txt";
+
+/// Writes the generated file of each of `groups` to `output`, merged into the existing
+/// output file when merging
+fn write_grouped_states(groups: StateGroups, output: &mut Output) -> anyhow::Result<()> {
+    for (out_name, (format, mut states, originals)) in groups {
+        if let Some(path) = output.merge_path(&out_name) {
+            states = merge_states(states, &originals, &read_pdx_file(&path)?)?;
+        }
+        let mut buffer = Vec::new();
+        write_states_file(&format, &states, &mut buffer)?;
+        output.write(&out_name, &buffer, &format!("{} states", states.len()))?;
+    }
+    Ok(())
+}
+
+/// Writes the modded version of the states file at `in_path` to `out_file`,
+/// recording the states that were modified in `report`. In replace mode this is every
+/// state of the file, and in inject mode only the changed keys of the modified states.
+/// The states are merged into the `existing` output file, if given.
+/// Returns `false` if the file is excluded, not a land states file, or has nothing to inject,
+/// and should be skipped.
+pub fn create_modded_states_file(
+    in_path: &Path,
+    config: &Config,
+    existing: Option<&Block>,
+    out_file: &mut impl Write,
+    report: &mut FileReport,
+) -> anyhow::Result<bool> {
+    let Some((format, states)) = modded_states(in_path, config, existing, report)? else {
+        return Ok(false);
+    };
+    write_states_file(&format, &states, out_file)?;
+
+    Ok(true)
+}
+
+/// Returns the format of the states file at `in_path` and its states modded by `config`,
+/// recording the states that were modified in `report`, or `None` if the file is skipped.
+/// In inject mode only the `INJECT:` blocks of the modified states are returned.
+/// With an `existing` output file, its states are kept and merged with the modded ones.
+fn modded_states(
+    in_path: &Path,
+    config: &Config,
+    existing: Option<&Block>,
+    report: &mut FileReport,
+) -> anyhow::Result<Option<(SourceFormat, Vec<BlockItem>)>> {
+    let merged = |state_name: &str| existing.is_some_and(|existing| existing.has_key(state_name));
+    let Some((format, mut states, originals)) = modify_states(in_path, config, merged, report)?
+    else {
+        return Ok(None);
+    };
+    if let Some(existing) = existing {
+        states = merge_states(states, &originals, existing)?;
+    }
+
+    Ok(Some((format, states)))
+}
+
+/// Format, modded states and game's states to merge with of a states file
+type ModifiedStates = (SourceFormat, Vec<BlockItem>, HashMap<String, Block>);
+
+/// Returns the format of the states file at `in_path`, its states modded by `config` and,
+/// in replace mode, the game's version of the states that are `merged` into an existing
+/// output file, recording the states that were modified in `report`, or `None` if the file
+/// is skipped. In inject mode only the `INJECT:` blocks of the modified states are returned.
+fn modify_states(
+    in_path: &Path,
+    config: &Config,
+    merged: impl Fn(&str) -> bool,
+    report: &mut FileReport,
+) -> anyhow::Result<Option<ModifiedStates>> {
+    if in_path
+        .file_name()
+        .is_some_and(|file_name| config.states_exclude.is_match(file_name))
+    {
+        return Ok(None);
+    }
+
+    let mut contents = read_pdx_file(in_path)?;
+    if config.skip_sea_states
+        && contents
+            .iter_definitions()
+            .all(|(_, state_block)| is_sea_state(state_block, &config.game_data))
+    {
+        return Ok(None);
+    }
+    let format = SourceFormat::read(&std::fs::read(in_path)?, config.line_endings);
+    let resources = contents
+        .iter_definitions()
+        .filter_map(|(_, state_block)| state_block.get_field_block("arable_resources"))
+        .flat_map(Block::iter_values)
+        .map(Token::as_str);
+    let rules = match ResourceNames::detect(resources) {
+        Some(names) => names.rename_rules(&config.resources, &config.game_data),
+        None => config.resources.clone(),
+    };
+
+    let mut states = Vec::new();
+    // The game's states that an existing output file has, to merge it with
+    let mut originals = HashMap::new();
+    for mut item in contents.drain() {
+        let BlockItem::Field(Field(state_name, _, BV::Block(state_block))) = &mut item else {
+            if config.states_mode == StatesMode::Replace {
+                states.push(item);
+            }
+            continue;
+        };
+        let original = (config.states_mode == StatesMode::Inject).then(|| state_block.clone());
+        if config.states_mode == StatesMode::Replace && merged(state_name.as_str()) {
+            originals.insert(state_name.to_string(), state_block.clone());
+        }
+        let fields = modify_state(state_name.as_str(), state_block, &rules, config, report);
+        let modified = !fields.is_empty();
+        if modified {
+            report.states_modified.push(state_name.to_string());
+            report.state_changes.push(StateChange {
+                state: state_name.to_string(),
+                fields: fields.into_iter().map(ToString::to_string).collect(),
+            });
+        } else {
+            report.states_unchanged.push(state_name.to_string());
+        }
+        match original {
+            None => states.push(item),
+            Some(original) if modified => {
+                states.push(injected_state(state_name, &original, state_block)?);
+            }
+            Some(_) => (),
+        }
+    }
+    if config.states_mode == StatesMode::Inject && report.states_modified.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some((format, states, originals)))
+}
+
+/// Merges the modded `states` into the `existing` output file. States of the existing file
+/// are merged with the modded state of the same key by `merge_state`, with the game's state
+/// from `originals` as their base, or no base for `INJECT:` blocks. States only the existing
+/// file has are kept after the modded states.
+fn merge_states(
+    states: Vec<BlockItem>,
+    originals: &HashMap<String, Block>,
+    existing: &Block,
+) -> anyhow::Result<Vec<BlockItem>> {
+    let mut merged = Vec::new();
+    let mut keys = HashSet::new();
+    for mut item in states {
+        if let BlockItem::Field(Field(key, _, BV::Block(modded))) = &mut item {
+            keys.insert(key.to_string());
+            if let Some(existing_state) = existing.get_field_block(key.as_str()) {
+                let no_base = Block::new(modded.loc);
+                let original = originals.get(key.as_str()).unwrap_or(&no_base);
+                *modded = merge_state(original, modded, existing_state)?;
+            }
+        }
+        merged.push(item);
+    }
+    for item in existing.iter_items() {
+        match item {
+            BlockItem::Field(Field(key, _, _)) if keys.contains(key.as_str()) => (),
+            item => merged.push(item.clone()),
+        }
+    }
+    Ok(merged)
+}
+
+/// Merges the `modded` version of the `original` state into the `existing` one, key by key.
+/// Keys the existing state has as in the original state take the fields of the modded state,
+/// and keys changed by hand in the existing state keep its fields. Keys only the modded state
+/// has are added.
+fn merge_state(original: &Block, modded: &Block, existing: &Block) -> anyhow::Result<Block> {
+    let original_fields = fields_by_key(original)?;
+    let existing_fields = fields_by_key(existing)?;
+    let mut merged = Block::new(existing.loc);
+    let mut replaced = HashSet::new();
+    for item in existing.iter_items() {
+        match item {
+            BlockItem::Field(Field(key, _, _))
+                if existing_fields.get(key.as_str()) == original_fields.get(key.as_str()) =>
+            {
+                if replaced.insert(key.as_str()) {
+                    for modded_item in modded.iter_items() {
+                        if let BlockItem::Field(Field(modded_key, _, _)) = modded_item
+                            && modded_key == key
+                        {
+                            merged.add_item(modded_item.clone());
+                        }
+                    }
+                }
+            }
+            item => merged.add_item(item.clone()),
+        }
+    }
+    for item in modded.iter_items() {
+        if let BlockItem::Field(Field(key, _, _)) = item
+            && !original_fields.contains_key(key.as_str())
+            && !existing_fields.contains_key(key.as_str())
+        {
+            merged.add_item(item.clone());
+        }
+    }
+    Ok(merged)
+}
+
+/// Writes `states` to `out_file` in `format`, after checking that they load as written
+fn write_states_file(
+    format: &SourceFormat,
+    states: &[BlockItem],
+    out_file: &mut impl Write,
+) -> anyhow::Result<()> {
+    let mut buffer = Vec::new();
+    for item in states {
+        write_item(&mut buffer, item, 0)?;
+        writeln!(buffer)?;
+    }
+    let formatted = format.apply(&String::from_utf8_lossy(&buffer));
+    validate_states_file(&formatted, &buffer)?;
+    out_file.write_all(formatted.as_bytes())?;
+    Ok(())
+}
+
+/// Checks that the generated states file `formatted` loads as the states written to `written`,
+/// by parsing it again like the game would and writing the parsed states the same way,
+/// and that it has no state more than once
+fn validate_states_file(formatted: &str, written: &[u8]) -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("states.txt");
+    std::fs::write(&path, formatted)?;
+    let mut parsed = read_pdx_file(&path)
+        .map_err(|err| anyhow::anyhow!("Generated states file can't be parsed: {:#}", err))?;
+
+    let mut states = HashSet::new();
+    let mut rewritten = Vec::new();
+    for item in parsed.drain() {
+        if let BlockItem::Field(Field(state_name, _, _)) = &item
+            && !states.insert(state_name.to_string())
+        {
+            anyhow::bail!("Generated states file has {} more than once", state_name);
+        }
+        write_item(&mut rewritten, &item, 0)?;
+        writeln!(rewritten)?;
+    }
+    if rewritten != written {
+        anyhow::bail!("Generated states file doesn't parse as the states that were written");
+    }
+    Ok(())
+}
+
+/// Applies every change of `config` for `state_name` to `state_block`, with the resource
+/// `rules` named the way the states file names its resources, recording the removed
+/// resources in `report`. Returns the keys of the state that changed.
+fn modify_state(
+    state_name: &str,
+    state_block: &mut Block,
+    rules: &[ResourceRule],
+    config: &Config,
+    report: &mut FileReport,
+) -> Vec<&'static str> {
+    let state_override = config.state_overrides.get(state_name);
+    let (removed, resources_added) =
+        match state_override.and_then(|state| state.arable_resources.as_ref()) {
+            Some(resources) => set_arable_resources(state_block, resources),
+            None => {
+                let traits = state_block.get_field_list("traits").unwrap_or_default();
+                let traits = traits.iter().map(Token::as_str).collect::<Vec<_>>();
+                let provinces = state_block.get_field_list("provinces").unwrap_or_default();
+                let terrain = config
+                    .game_data
+                    .state_terrain(provinces.iter().map(Token::as_str));
+                let state_rules = rules
+                    .iter()
+                    .filter(|rule| rule.applies_to(&traits, terrain))
+                    .collect::<Vec<_>>();
+                let removed = modify_arable_resources(state_block, |original| {
+                    let modded = modded_arable_resources(original, &state_rules)?;
+                    Some(modded.into_iter().map(ToString::to_string).collect())
+                });
+                (removed, false)
+            }
+        };
+    let resources_modified = removed.is_some() || resources_added;
+    if let Some(resources) = removed.filter(|removed| !removed.is_empty()) {
+        report.resources_removed.push(RemovedResources {
+            state: state_name.to_string(),
+            resources,
+        });
+    }
+    let land_modified = modify_arable_land(
+        state_name,
+        state_block,
+        &config.arable_land,
+        &config.game_data,
+    );
+    let capped_modified = match state_override.and_then(|state| state.capped_resources.as_ref()) {
+        Some(capped) => {
+            // Resources the override doesn't list are removed by setting them to 0
+            let mut replaced = state_block
+                .get_field_block("capped_resources")
+                .map(|resources| {
+                    resources
+                        .iter_assignments()
+                        .map(|(resource, _)| (resource.to_string(), 0))
+                        .collect::<BTreeMap<_, _>>()
+                })
+                .unwrap_or_default();
+            replaced.extend(capped.clone());
+            modify_capped_resources(state_block, &replaced)
+        }
+        None => config
+            .capped_resources
+            .get(state_name)
+            .is_some_and(|capped| modify_capped_resources(state_block, capped)),
+    };
+    let discoverable_modified = modify_discoverable_resources(
+        state_name,
+        state_block,
+        &config.discoverable_resources,
+        &config.game_data,
+    );
+    let traits_modified = config
+        .state_traits
+        .get(state_name)
+        .is_some_and(|traits| add_state_traits(state_block, traits));
+    [
+        ("arable_resources", resources_modified),
+        ("arable_land", land_modified),
+        ("capped_resources", capped_modified),
+        ("resource", discoverable_modified),
+        ("traits", traits_modified),
+    ]
+    .into_iter()
+    .filter(|(_, modified)| *modified)
+    .map(|(key, _)| key)
+    .collect()
+}
+
+/// Returns an `INJECT:` block for `state_name` with the fields of `modded` whose key
+/// has different fields in `original`
+fn injected_state(
+    state_name: &Token,
+    original: &Block,
+    modded: &Block,
+) -> anyhow::Result<BlockItem> {
+    let original_fields = fields_by_key(original)?;
+    let modded_fields = fields_by_key(modded)?;
+    let mut injected = Block::new(modded.loc);
+    for item in modded.iter_items() {
+        if let BlockItem::Field(Field(key, _, _)) = item
+            && original_fields.get(key.as_str()) != modded_fields.get(key.as_str())
+        {
+            injected.add_item(item.clone());
+        }
+    }
+    Ok(BlockItem::Field(Field(
+        Token::new(&format!("INJECT:{}", state_name), state_name.loc),
+        Comparator::Equals(Single),
+        BV::Block(injected),
+    )))
+}
+
+/// Returns the fields of `block` as written, grouped by key
+fn fields_by_key(block: &Block) -> anyhow::Result<BTreeMap<&str, Vec<u8>>> {
+    let mut fields: BTreeMap<&str, Vec<u8>> = BTreeMap::new();
+    for item in block.iter_items() {
+        if let BlockItem::Field(Field(key, _, _)) = item {
+            let written = fields.entry(key.as_str()).or_default();
+            write_item(written, item, 1)?;
+            writeln!(written)?;
+        }
+    }
+    Ok(fields)
+}
+
+/// Terrains of provinces that are sea or lake
+const SEA_TERRAINS: &[&str] = &["ocean", "lakes"];
+
+/// Whether `state_block` is a sea state. States whose provinces have known terrains are sea
+/// states if every province is sea or lake, and the others if they have no arable land
+/// or arable resources.
+fn is_sea_state(state_block: &Block, game_data: &GameData) -> bool {
+    let provinces = state_block.get_field_list("provinces").unwrap_or_default();
+    let terrains = provinces
+        .iter()
+        .filter_map(|province| {
+            game_data
+                .province_terrains
+                .get(&province.as_str().to_uppercase())
+        })
+        .collect::<Vec<_>>();
+    if terrains.is_empty() {
+        return !state_block.has_key("arable_land") && !state_block.has_key("arable_resources");
+    }
+    terrains
+        .iter()
+        .all(|terrain| SEA_TERRAINS.contains(&terrain.as_str()))
+}
+
+/// How the arable resources of a states file are named
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ResourceNames {
+    /// By building group, such as `bg_wheat_farms`
+    Groups,
+    /// By building, such as `building_wheat_farm`, as in older states files
+    Buildings,
+}
+
+impl ResourceNames {
+    /// Detects how the arable `resources` of a states file are named,
+    /// returning `None` if there are no resources to tell by
+    fn detect<'a>(resources: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let (mut groups, mut buildings) = (0, 0);
+        for resource in resources {
+            if resource.starts_with("bg_") {
+                groups += 1;
+            } else if resource.starts_with("building_") {
+                buildings += 1;
+            }
+        }
+        match groups.cmp(&buildings) {
+            std::cmp::Ordering::Greater => Some(Self::Groups),
+            std::cmp::Ordering::Less => Some(Self::Buildings),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    /// Returns `rules` with their resources named this way
+    fn rename_rules(self, rules: &[ResourceRule], game_data: &GameData) -> Vec<ResourceRule> {
+        let rename = |resources: &[String]| {
+            resources
+                .iter()
+                .map(|resource| self.rename(resource, game_data))
+                .collect()
+        };
+        rules
+            .iter()
+            .map(|rule| ResourceRule {
+                when: rename(&rule.when),
+                add: rename(&rule.add),
+                remove: rename(&rule.remove),
+                ..rule.clone()
+            })
+            .collect()
+    }
+
+    /// Returns `resource` named this way. Buildings and building groups the game defines are
+    /// renamed by the game's files, and the others by the game's naming of building groups
+    /// as the plural of their building.
+    fn rename(self, resource: &str, game_data: &GameData) -> String {
+        match self {
+            Self::Groups => {
+                let Some(name) = resource.strip_prefix("building_") else {
+                    return resource.to_string();
+                };
+                if let Some(group) = game_data.building_groups.get(resource) {
+                    return group.clone();
+                }
+                if ["ch", "sh", "s", "x"].iter().any(|end| name.ends_with(end)) {
+                    format!("bg_{}es", name)
+                } else {
+                    format!("bg_{}s", name)
+                }
+            }
+            Self::Buildings => {
+                let Some(name) = resource.strip_prefix("bg_") else {
+                    return resource.to_string();
+                };
+                let mut buildings = game_data
+                    .building_groups
+                    .iter()
+                    .filter(|(_, group)| *group == resource);
+                if let (Some((building, _)), None) = (buildings.next(), buildings.next()) {
+                    return building.clone();
+                }
+                let singular = if ["ches", "shes", "sses", "xes"]
+                    .iter()
+                    .any(|end| name.ends_with(end))
+                {
+                    &name[..name.len() - 2]
+                } else {
+                    name.strip_suffix('s').unwrap_or(name)
+                };
+                format!("building_{}", singular)
+            }
+        }
+    }
+}
+
+/// How a states file is written, which its modded file is written the same way as
+/// so that it only differs where states were modified
+#[derive(Clone)]
+pub struct SourceFormat {
+    bom: bool,
+    crlf: bool,
+    /// Indentation of a level of blocks, if it isn't a tab
+    indent: Option<String>,
+}
+
+impl SourceFormat {
+    /// Reads the format of the states file `source`, with line endings by `line_endings`
+    pub fn read(source: &[u8], line_endings: LineEndings) -> Self {
+        let source = String::from_utf8_lossy(source);
+        let indent = source
+            .lines()
+            .map(|line| &line[..line.len() - line.trim_start().len()])
+            .find(|indent| !indent.is_empty())
+            .filter(|indent| !indent.starts_with('\t'))
+            .map(ToString::to_string);
+        Self {
+            bom: source.starts_with(BOM_CHAR),
+            crlf: match line_endings {
+                LineEndings::Input => source.contains("\r\n"),
+                LineEndings::Lf => false,
+                LineEndings::Crlf => true,
+            },
+            indent,
+        }
+    }
+
+    /// Rewrites `contents`, written with tabs and `\n` line endings, in this format
+    pub fn apply(&self, contents: &str) -> String {
+        let mut formatted = String::with_capacity(contents.len());
+        if self.bom {
+            formatted.push(BOM_CHAR);
+        }
+        for line in contents.lines() {
+            let code = line.trim_start_matches('\t');
+            let depth = line.len() - code.len();
+            match &self.indent {
+                Some(indent) => formatted.push_str(&indent.repeat(depth)),
+                None => formatted.push_str(&line[..depth]),
+            }
+            formatted.push_str(code);
+            formatted.push_str(if self.crlf { "\r\n" } else { "\n" });
+        }
+        formatted
+    }
+}
+
+/// Replaces the `arable_resources` of `state_block` with those `modded` returns for them,
+/// returning the resources that were removed if any resources changed
+fn modify_arable_resources(
+    state_block: &mut Block,
+    modded: impl Fn(&[&str]) -> Option<Vec<String>>,
+) -> Option<Vec<String>> {
+    let mut modified = None;
+    let items = state_block.drain().collect::<Vec<_>>();
+    for item in items {
+        match item {
+            BlockItem::Field(Field(key, cmp, BV::Block(resources)))
+                if key.as_str() == "arable_resources" =>
+            {
+                let original = resources
+                    .iter_values()
+                    .map(Token::as_str)
+                    .collect::<Vec<_>>();
+                let value = match modded(&original) {
+                    Some(modded) => {
+                        let mut modded_resources = Block::new(resources.loc);
+                        for resource in &modded {
+                            modded_resources.add_value(Token::new(resource, resources.loc));
+                        }
+                        let removed = original
+                            .iter()
+                            .filter(|resource| !modded.iter().any(|m| m == *resource))
+                            .map(ToString::to_string);
+                        modified.get_or_insert_with(Vec::new).extend(removed);
+                        modded_resources
+                    }
+                    None => resources,
+                };
+                state_block.add_key_bv(key, cmp, BV::Block(value));
+            }
+            item => state_block.add_item(item),
+        }
+    }
+    modified
+}
+
+/// Sets the `arable_resources` of `state_block` to `resources` without the ones listed more
+/// than once, adding the list if the state has none. Returns the resources that were removed
+/// if the existing list changed, and whether a list was added.
+fn set_arable_resources(
+    state_block: &mut Block,
+    resources: &[String],
+) -> (Option<Vec<String>>, bool) {
+    let mut listed = HashSet::new();
+    let resources = resources
+        .iter()
+        .filter(|resource| listed.insert(*resource))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !state_block.has_key("arable_resources") {
+        if resources.is_empty() {
+            return (None, false);
+        }
+        let loc = state_block.loc;
+        let mut list = Block::new(loc);
+        for resource in &resources {
+            list.add_value(Token::new(resource, loc));
+        }
+        state_block.add_key_bv(
+            Token::new("arable_resources", loc),
+            Comparator::Equals(Single),
+            BV::Block(list),
+        );
+        return (None, true);
+    }
+    let removed = modify_arable_resources(state_block, |original| {
+        (original != resources).then(|| resources.clone())
+    });
+    (removed, false)
+}
+
+/// Applies the `rules` for `state_name` to the `arable_land` of `state_block`,
+/// returning whether the arable land changed
+fn modify_arable_land(
+    state_name: &str,
+    state_block: &mut Block,
+    rules: &[ArableLandRule],
+    game_data: &GameData,
+) -> bool {
+    let rules = rules
+        .iter()
+        .filter(|rule| rule.applies_to(state_name, game_data))
+        .collect::<Vec<_>>();
+    if rules.is_empty() {
+        return false;
+    }
+    let mut modified = false;
+    let items = state_block.drain().collect::<Vec<_>>();
+    for item in items {
+        match item {
+            BlockItem::Field(Field(key, cmp, BV::Value(value)))
+                if key.as_str() == "arable_land" =>
+            {
+                let arable_land = value
+                    .get_integer()
+                    .and_then(|land| u32::try_from(land).ok());
+                let modded = arable_land.map(|arable_land| {
+                    rules
+                        .iter()
+                        .fold(arable_land, |land, rule| rule.apply(land))
+                });
+                let value = match modded {
+                    Some(modded) if Some(modded) != arable_land => {
+                        modified = true;
+                        Token::new(&modded.to_string(), value.loc)
+                    }
+                    _ => value,
+                };
+                state_block.add_key_bv(key, cmp, BV::Value(value));
+            }
+            item => state_block.add_item(item),
+        }
+    }
+    modified
+}
+
+/// Sets the amounts of the `capped_resources` of `state_block` to those in `capped`,
+/// removing resources set to 0 and adding a `capped_resources` block if the state has none.
+/// Returns whether any amounts changed.
+fn modify_capped_resources(state_block: &mut Block, capped: &BTreeMap<String, u32>) -> bool {
+    let mut modified = false;
+    let mut found = false;
+    let loc = state_block.loc;
+    let items = state_block.drain().collect::<Vec<_>>();
+    for item in items {
+        match item {
+            BlockItem::Field(Field(key, cmp, BV::Block(mut resources)))
+                if key.as_str() == "capped_resources" =>
+            {
+                found = true;
+                let mut remaining = capped.clone();
+                let mut modded_resources = Block::new(resources.loc);
+                for item in resources.drain() {
+                    let BlockItem::Field(Field(resource, cmp, BV::Value(amount))) = item else {
+                        modded_resources.add_item(item);
+                        continue;
+                    };
+                    match remaining.remove(resource.as_str()) {
+                        Some(0) => modified = true,
+                        Some(new_amount) => {
+                            let amount = if amount.get_integer() == Some(i64::from(new_amount)) {
+                                amount
+                            } else {
+                                modified = true;
+                                Token::new(&new_amount.to_string(), amount.loc)
+                            };
+                            modded_resources.add_key_bv(resource, cmp, BV::Value(amount));
+                        }
+                        None => modded_resources.add_key_bv(resource, cmp, BV::Value(amount)),
+                    }
+                }
+                modified |= add_capped_resources(&mut modded_resources, &remaining);
+                state_block.add_key_bv(key, cmp, BV::Block(modded_resources));
+            }
+            item => state_block.add_item(item),
+        }
+    }
+    if !found {
+        let mut resources = Block::new(loc);
+        if add_capped_resources(&mut resources, capped) {
+            modified = true;
+            state_block.add_key_bv(
+                Token::new("capped_resources", loc),
+                Comparator::Equals(Single),
+                BV::Block(resources),
+            );
+        }
+    }
+    modified
+}
+
+/// Adds the resources of `capped` with an amount above 0 to `resources`,
+/// returning whether any were added
+fn add_capped_resources(resources: &mut Block, capped: &BTreeMap<String, u32>) -> bool {
+    let mut added = false;
+    for (resource, amount) in capped.iter().filter(|(_, amount)| **amount > 0) {
+        resources.add_key_bv(
+            Token::new(resource, resources.loc),
+            Comparator::Equals(Single),
+            BV::Value(Token::new(&amount.to_string(), resources.loc)),
+        );
+        added = true;
+    }
+    added
+}
+
+/// Adds the `traits` that `state_block` doesn't have yet to its `traits`,
+/// adding a `traits` list if the state has none. Returns whether any traits were added.
+fn add_state_traits(state_block: &mut Block, traits: &[String]) -> bool {
+    let mut modified = false;
+    let mut found = false;
+    let loc = state_block.loc;
+    let items = state_block.drain().collect::<Vec<_>>();
+    for item in items {
+        match item {
+            BlockItem::Field(Field(key, cmp, BV::Block(mut state_traits)))
+                if key.as_str() == "traits" =>
+            {
+                found = true;
+                for state_trait in traits {
+                    if !state_traits
+                        .iter_values()
+                        .any(|t| t.as_str() == state_trait)
+                    {
+                        state_traits.add_value(Token::new(state_trait, state_traits.loc));
+                        modified = true;
+                    }
+                }
+                state_block.add_key_bv(key, cmp, BV::Block(state_traits));
+            }
+            item => state_block.add_item(item),
+        }
+    }
+    if !found && !traits.is_empty() {
+        let mut state_traits = Block::new(loc);
+        for state_trait in traits {
+            state_traits.add_value(Token::new(state_trait, loc));
+        }
+        state_block.add_key_bv(
+            Token::new("traits", loc),
+            Comparator::Equals(Single),
+            BV::Block(state_traits),
+        );
+        modified = true;
+    }
+    modified
+}
+
+/// Adds the `resources` for `state_name` to `state_block` as `resource` blocks, replacing the
+/// blocks of the same `type` it already has. Returns whether any blocks changed.
+fn modify_discoverable_resources(
+    state_name: &str,
+    state_block: &mut Block,
+    resources: &[DiscoverableResource],
+    game_data: &GameData,
+) -> bool {
+    let mut modified = false;
+    for resource in resources
+        .iter()
+        .filter(|resource| resource.applies_to(state_name, game_data))
+    {
+        let loc = state_block.loc;
+        let mut resource_block = Block::new(loc);
+        let mut add_value = |key: &str, value: String| {
+            resource_block.add_key_bv(
+                Token::new(key, loc),
+                Comparator::Equals(Single),
+                BV::Value(Token::new(&value, loc)),
+            );
+        };
+        add_value("type", resource.r#type.clone());
+        if let Some(depleted_type) = &resource.depleted_type {
+            add_value("depleted_type", depleted_type.clone());
+        }
+        if let Some(discovered_amount) = resource.discovered_amount {
+            add_value("discovered_amount", discovered_amount.to_string());
+        }
+        add_value(
+            "undiscovered_amount",
+            resource.undiscovered_amount.to_string(),
+        );
+
+        let items = state_block.drain().collect::<Vec<_>>();
+        let mut replaced = false;
+        for item in items {
+            match item {
+                BlockItem::Field(Field(key, cmp, BV::Block(block)))
+                    if !replaced
+                        && key.as_str() == "resource"
+                        && block
+                            .get_field_value("type")
+                            .is_some_and(|t| t.as_str() == resource.r#type) =>
+                {
+                    replaced = true;
+                    modified |= resource_fields(&block) != resource_fields(&resource_block);
+                    state_block.add_key_bv(key, cmp, BV::Block(resource_block.clone()));
+                }
+                item => state_block.add_item(item),
+            }
+        }
+        if !replaced {
+            modified = true;
+            state_block.add_key_bv(
+                Token::new("resource", loc),
+                Comparator::Equals(Single),
+                BV::Block(resource_block),
+            );
+        }
+    }
+    modified
+}
+
+/// Returns the fields of a `resource` block, to compare blocks by
+fn resource_fields(block: &Block) -> BTreeMap<&str, &str> {
+    block
+        .iter_assignments()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+/// Applies `rules` to the `original` arable resources of a state,
+/// returning the new resources or `None` if no resources changed
+fn modded_arable_resources<'a>(
+    original: &[&'a str],
+    rules: &[&'a ResourceRule],
+) -> Option<Vec<&'a str>> {
+    let mut resources = original.to_vec();
+    for rule in rules {
+        if rule.when.is_empty() {
+            resources.retain(|resource| !rule.remove.iter().any(|remove| remove == resource));
+            continue;
+        }
+        let Some(matched) = resources
+            .iter()
+            .position(|resource| rule.when.iter().any(|when| when == resource))
+        else {
+            continue;
+        };
+
+        // Added resources go where the matched resource was when replacing it
+        let mut insert_at = match rule.mode {
+            ResourceMode::Append => None,
+            ResourceMode::Replace => {
+                resources.remove(matched);
+                Some(matched)
+            }
+        };
+        resources.retain(|resource| !rule.remove.iter().any(|remove| remove == resource));
+        for add in &rule.add {
+            if resources.contains(&add.as_str())
+                || rule
+                    .max_resources
+                    .is_some_and(|max_resources| resources.len() >= max_resources)
+            {
+                continue;
+            }
+            match &mut insert_at {
+                Some(index) => {
+                    let index_in_bounds = (*index).min(resources.len());
+                    resources.insert(index_in_bounds, add);
+                    *index = index_in_bounds + 1;
+                }
+                None => resources.push(add),
+            }
+        }
+    }
+    // The game warns about resources listed more than once, so only the first is kept
+    let mut listed = HashSet::new();
+    resources.retain(|resource| listed.insert(*resource));
+    if resources == original {
+        return None;
+    }
+    Some(resources)
+}
+
+/// The game's states by name, with the states file each is defined in
+pub type VanillaStates = HashMap<String, (PathBuf, Block)>;
+
+/// Reads the states of the game's states files `paths`
+pub fn read_vanilla_states(paths: &[PathBuf]) -> anyhow::Result<VanillaStates> {
+    let mut states = HashMap::new();
+    for path in paths {
+        for (state_name, state_block) in read_pdx_file(path)?.iter_definitions() {
+            states.insert(state_name.to_string(), (path.clone(), state_block.clone()));
+        }
+    }
+    Ok(states)
+}
+
+/// Compares the modified states files `in_paths` with the game's `vanilla` states and generates
+/// a file for each modified file that overrides only its states that differ. Output files are
+/// only written if none of the input files failed.
+pub fn run_states_diff(
+    vanilla: &VanillaStates,
+    in_paths: &[PathBuf],
+    config: &Config,
+    output: &mut Output,
+) -> Report {
+    let progress = output.begin_run(in_paths.len());
+    let mut report = Report::default();
+    let contents = in_paths
+        .iter()
+        .map(|in_path| read_pdx_file(in_path))
+        .collect::<Vec<_>>();
+    // Every state of the modified files, to tell which of the game's states were removed
+    let modified_states = contents
+        .iter()
+        .flatten()
+        .flat_map(Block::iter_definitions)
+        .map(|(state_name, _)| state_name.as_str())
+        .collect::<HashSet<_>>();
+    let mut total_states_overridden = 0;
+    for (in_path, contents) in in_paths.iter().zip(&contents) {
+        let mut file_report = FileReport::new(in_path.clone());
+        let generated = contents
+            .as_ref()
+            .map_err(|err| anyhow::anyhow!("{:#}", err));
+        if let Err(err) = generated.and_then(|contents| {
+            diff_states_file(
+                in_path,
+                contents,
+                vanilla,
+                &modified_states,
+                config,
+                output,
+                &mut file_report,
+            )
+        }) {
+            file_report.error = Some(format!("{:#}", err));
+        }
+
+        total_states_overridden += file_report.states_modified.len();
+        report.files.push(file_report);
+        progress.set_message(format!("{} states overridden", total_states_overridden));
+        progress.inc(1);
+    }
+    report.commit(output);
+    progress.finish();
+
+    report
+}
+
+/// Generates the file overriding the states of the modified states file `contents` at `in_path`
+/// that differ from the game's `vanilla` states, and hands it to `output` if there are any.
+/// Changed states are `INJECT:` blocks of their changed keys, or `REPLACE:` blocks if keys were
+/// removed, and new states are written as they are. The game's states of the same file name
+/// that aren't in any of the `modified_states` are warned about, as they can't be removed.
+fn diff_states_file(
+    in_path: &Path,
+    contents: &Block,
+    vanilla: &VanillaStates,
+    modified_states: &HashSet<&str>,
+    config: &Config,
+    output: &mut Output,
+    report: &mut FileReport,
+) -> anyhow::Result<()> {
+    let mut states = Vec::new();
+    for (state_name, state_block) in contents.iter_definitions() {
+        let full_state = |prefix: &str| {
+            BlockItem::Field(Field(
+                Token::new(&format!("{}{}", prefix, state_name), state_name.loc),
+                Comparator::Equals(Single),
+                BV::Block(state_block.clone()),
+            ))
+        };
+        let Some((_, original)) = vanilla.get(state_name.as_str()) else {
+            report.states_modified.push(state_name.to_string());
+            report.state_changes.push(StateChange {
+                state: state_name.to_string(),
+                fields: fields_by_key(state_block)?
+                    .into_keys()
+                    .map(ToString::to_string)
+                    .collect(),
+            });
+            states.push(full_state(""));
+            continue;
+        };
+        let original_fields = fields_by_key(original)?;
+        let modded_fields = fields_by_key(state_block)?;
+        let changed = modded_fields
+            .iter()
+            .filter(|(key, written)| original_fields.get(*key) != Some(*written))
+            .map(|(key, _)| *key);
+        let removed = original_fields
+            .keys()
+            .filter(|key| !modded_fields.contains_key(*key))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut fields = changed.chain(removed.iter().copied()).collect::<Vec<_>>();
+        fields.sort_unstable();
+        if fields.is_empty() {
+            report.states_unchanged.push(state_name.to_string());
+            continue;
+        }
+
+        report.states_modified.push(state_name.to_string());
+        report.state_changes.push(StateChange {
+            state: state_name.to_string(),
+            fields: fields.into_iter().map(ToString::to_string).collect(),
+        });
+        // Injecting can only add and change keys, so states with removed keys are replaced
+        if removed.is_empty() {
+            states.push(injected_state(state_name, original, state_block)?);
+        } else {
+            states.push(full_state("REPLACE:"));
+        }
+    }
+
+    let mut removed = vanilla
+        .iter()
+        .filter(|(state_name, (path, _))| {
+            path.file_name() == in_path.file_name()
+                && !modified_states.contains(state_name.as_str())
+        })
+        .map(|(state_name, _)| state_name.as_str())
+        .collect::<Vec<_>>();
+    removed.sort_unstable();
+    for state_name in removed {
+        report.warnings.push(format!(
+            "{} was removed, which an override can't do, so it is left as the game defines it",
+            state_name
+        ));
+    }
+
+    if states.is_empty() {
+        return Ok(());
+    }
+    let format = SourceFormat::read(&std::fs::read(in_path)?, config.line_endings);
+    let mut buffer = Vec::new();
+    write_states_file(&format, &states, &mut buffer)?;
+    let out_name = output.file_name(in_path);
+    output.write(
+        &out_name,
+        &buffer,
+        &format!("{} states overridden", report.states_modified.len()),
+    )?;
+    report.output = Some(out_name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Block {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("states.txt");
+        std::fs::write(&path, contents).unwrap();
+        read_pdx_file(&path).unwrap()
+    }
+
+    fn value<'a>(block: &'a Block, key: &str) -> Option<&'a str> {
+        block.get_field_value(key).map(Token::as_str)
+    }
+
+    #[test]
+    fn merge_state_keeps_hand_edits() {
+        let original = parse("id = 1 arable_land = 10 city = x1");
+        let modded = parse("id = 1 arable_land = 5 city = x1 naval_exit_id = 3000");
+        let existing = parse("id = 1 arable_land = 10 city = x2 port = x3");
+        let merged = merge_state(&original, &modded, &existing).unwrap();
+        assert_eq!(value(&merged, "id"), Some("1"));
+        assert_eq!(value(&merged, "arable_land"), Some("5"));
+        assert_eq!(value(&merged, "city"), Some("x2"));
+        assert_eq!(value(&merged, "port"), Some("x3"));
+        assert_eq!(value(&merged, "naval_exit_id"), Some("3000"));
+        assert_eq!(merged.count_keys("arable_land"), 1);
+    }
+
+    #[test]
+    fn merge_state_without_base_keeps_existing() {
+        let modded = parse("arable_land = 5");
+        let existing = parse("arable_land = 10");
+        let merged = merge_state(&Block::new(existing.loc), &modded, &existing).unwrap();
+        assert_eq!(value(&merged, "arable_land"), Some("10"));
+    }
+
+    #[test]
+    fn merge_states_keeps_existing_only_states() {
+        let mut modded = parse("STATE_A = { id = 1 arable_land = 5 }");
+        let existing = parse("STATE_B = { id = 2 } STATE_A = { id = 1 arable_land = 10 }");
+        let originals = parse("STATE_A = { id = 1 arable_land = 10 }")
+            .iter_definitions()
+            .map(|(key, block)| (key.to_string(), block.clone()))
+            .collect();
+        let merged = merge_states(modded.drain().collect(), &originals, &existing).unwrap();
+        let keys = merged
+            .iter()
+            .filter_map(|item| match item {
+                BlockItem::Field(Field(key, _, _)) => Some(key.as_str()),
+                BlockItem::Value(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["STATE_A", "STATE_B"]);
+        let BlockItem::Field(Field(_, _, BV::Block(state_a))) = &merged[0] else {
+            panic!("STATE_A isn't a block");
+        };
+        assert_eq!(value(state_a, "arable_land"), Some("5"));
+    }
+
+    fn resource_rule(when: &[&str], add: &[&str], remove: &[&str]) -> ResourceRule {
+        let names = |names: &[&str]| names.iter().map(ToString::to_string).collect();
+        ResourceRule {
+            name: None,
+            when: names(when),
+            add: names(add),
+            remove: names(remove),
+            mode: ResourceMode::Append,
+            max_resources: None,
+            traits: Vec::new(),
+            exclude_traits: Vec::new(),
+            terrains: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn arable_resources_append_or_replace() {
+        let original = [
+            "bg_wheat_farms",
+            "bg_livestock_ranches",
+            "bg_coffee_plantations",
+        ];
+        let append = resource_rule(&["bg_livestock_ranches"], &["bg_wool"], &[]);
+        assert_eq!(
+            modded_arable_resources(&original, &[&append]),
+            Some(vec![
+                "bg_wheat_farms",
+                "bg_livestock_ranches",
+                "bg_coffee_plantations",
+                "bg_wool"
+            ])
+        );
+
+        let replace = ResourceRule {
+            mode: ResourceMode::Replace,
+            ..resource_rule(&["bg_livestock_ranches"], &["bg_wool", "bg_dairy"], &[])
+        };
+        assert_eq!(
+            modded_arable_resources(&original, &[&replace]),
+            Some(vec![
+                "bg_wheat_farms",
+                "bg_wool",
+                "bg_dairy",
+                "bg_coffee_plantations"
+            ])
+        );
+
+        // Rules whose `when` resources the state doesn't have change nothing
+        let unmatched = resource_rule(&["bg_rice_farms"], &["bg_wool"], &["bg_wheat_farms"]);
+        assert_eq!(modded_arable_resources(&original, &[&unmatched]), None);
+        // Rules without `when` resources only remove
+        let remove = resource_rule(&[], &["bg_wool"], &["bg_coffee_plantations"]);
+        assert_eq!(
+            modded_arable_resources(&original, &[&remove]),
+            Some(vec!["bg_wheat_farms", "bg_livestock_ranches"])
+        );
+    }
+
+    #[test]
+    fn arable_resources_max_resources() {
+        let original = ["bg_wheat_farms", "bg_livestock_ranches"];
+        let rule = ResourceRule {
+            max_resources: Some(3),
+            ..resource_rule(&["bg_wheat_farms"], &["bg_wool", "bg_dairy"], &[])
+        };
+        assert_eq!(
+            modded_arable_resources(&original, &[&rule]),
+            Some(vec!["bg_wheat_farms", "bg_livestock_ranches", "bg_wool"])
+        );
+        let full = ["bg_wheat_farms", "bg_livestock_ranches", "bg_maize_farms"];
+        assert_eq!(modded_arable_resources(&full, &[&rule]), None);
+    }
+
+    #[test]
+    fn arable_resources_are_listed_once() {
+        let original = ["bg_wheat_farms", "bg_livestock_ranches", "bg_wheat_farms"];
+        let rule = resource_rule(&["bg_wheat_farms"], &["bg_livestock_ranches"], &[]);
+        assert_eq!(
+            modded_arable_resources(&original, &[&rule]),
+            Some(vec!["bg_wheat_farms", "bg_livestock_ranches"])
+        );
+        let original = ["bg_wheat_farms", "bg_livestock_ranches"];
+        assert_eq!(modded_arable_resources(&original, &[&rule]), None);
+    }
+
+    #[test]
+    fn resource_names_by_plural() {
+        let game_data = GameData::default();
+        for (building, group) in [
+            ("building_wheat_farm", "bg_wheat_farms"),
+            ("building_livestock_ranch", "bg_livestock_ranches"),
+            ("building_fish", "bg_fishes"),
+            ("building_box", "bg_boxes"),
+            ("building_glass", "bg_glasses"),
+        ] {
+            assert_eq!(ResourceNames::Groups.rename(building, &game_data), group);
+            assert_eq!(ResourceNames::Buildings.rename(group, &game_data), building);
+        }
+        // Resources named the other way already, or not buildings at all, are left alone
+        assert_eq!(
+            ResourceNames::Groups.rename("bg_wheat_farms", &game_data),
+            "bg_wheat_farms"
+        );
+        assert_eq!(
+            ResourceNames::Buildings.rename("building_wheat_farm", &game_data),
+            "building_wheat_farm"
+        );
+        assert_eq!(ResourceNames::Groups.rename("wool", &game_data), "wool");
+    }
+
+    #[test]
+    fn resource_names_by_game_data() {
+        let mut game_data = GameData::default();
+        game_data.building_groups.insert(
+            "building_vineyard_plantation".to_string(),
+            "bg_vineyards".to_string(),
+        );
+        assert_eq!(
+            ResourceNames::Groups.rename("building_vineyard_plantation", &game_data),
+            "bg_vineyards"
+        );
+        assert_eq!(
+            ResourceNames::Buildings.rename("bg_vineyards", &game_data),
+            "building_vineyard_plantation"
+        );
+
+        // A group of several buildings has no single building to be named by
+        game_data.building_groups.insert(
+            "building_vineyard_estate".to_string(),
+            "bg_vineyards".to_string(),
+        );
+        assert_eq!(
+            ResourceNames::Buildings.rename("bg_vineyards", &game_data),
+            "building_vineyard"
+        );
+    }
+}
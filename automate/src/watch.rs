@@ -0,0 +1,90 @@
+use crate::input::{InputArgs, input_dirs, input_files};
+use crate::log::{Event, Log};
+use notify::{RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long to wait for further changes before regenerating,
+/// since editors often touch a file several times when saving it
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches `input_path` and the ratios config at `config_path`, if there is one, and calls
+/// `regenerate` with every input file whenever any of them changes, and whether the config
+/// changed, until the process is interrupted
+pub fn watch(
+    input_path: &Path,
+    input: &InputArgs,
+    config_path: Option<&Path>,
+    log: &Log,
+    mut regenerate: impl FnMut(&[PathBuf], bool) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    // Watch the parent of a single input file, so that editors saving by
+    // replacing the file don't stop the watch
+    if input_path.is_file() {
+        watcher.watch(
+            input_path.parent().unwrap_or(Path::new(".")),
+            RecursiveMode::NonRecursive,
+        )?;
+    } else {
+        for dir in input_dirs(input_path, input)? {
+            watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        }
+    }
+    // The config is watched the same way, by its absolute path to recognize its changes
+    let config_path = config_path.map(std::fs::canonicalize).transpose()?;
+    if let Some(config_path) = &config_path {
+        watcher.watch(
+            config_path.parent().unwrap_or(Path::new("/")),
+            RecursiveMode::NonRecursive,
+        )?;
+    }
+    log.event(&Event::Watching {
+        path: input_path.to_path_buf(),
+    });
+
+    while let Ok(event) = rx.recv() {
+        let mut changed = HashSet::<PathBuf>::new();
+        let mut collect = |event: notify::Result<notify::Event>| -> anyhow::Result<()> {
+            let event = event?;
+            if event.kind.is_create() || event.kind.is_modify() {
+                changed.extend(event.paths);
+            }
+            Ok(())
+        };
+        collect(event)?;
+        while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+            collect(event)?;
+        }
+
+        let config_changed = config_path
+            .as_ref()
+            .is_some_and(|config_path| changed.contains(config_path));
+        let changed_names = changed
+            .iter()
+            .filter_map(|path| path.file_name())
+            .collect::<HashSet<&OsStr>>();
+        // Every input file is regenerated, as generators read other files than the changed ones
+        let in_paths = input_files(input_path, input)?;
+        if !config_changed
+            && !in_paths.iter().any(|path| {
+                path.file_name()
+                    .is_some_and(|name| changed_names.contains(name))
+            })
+        {
+            continue;
+        }
+        // Keep watching after a failed regeneration, the input is likely mid-edit
+        if let Err(err) = regenerate(&in_paths, config_changed) {
+            log.event(&Event::Error {
+                message: format!("{:#}", err),
+            });
+        }
+    }
+
+    Ok(())
+}
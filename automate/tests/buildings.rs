@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs the buildings command on `files` with the ratios config `ratios`, returning the
+/// generated files by their name
+fn run_buildings(files: &[(&str, &str)], ratios: &str) -> Vec<(String, String)> {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    for (name, contents) in files {
+        std::fs::write(input.path().join(name), contents).unwrap();
+    }
+    let ratios_path = input.path().join("ratios.toml");
+    std::fs::write(&ratios_path, ratios).unwrap();
+
+    let result = Command::new(env!("CARGO_BIN_EXE_automate"))
+        .arg("buildings")
+        .arg(input.path())
+        .arg(output.path())
+        .arg("--ratios")
+        .arg(&ratios_path)
+        .output()
+        .unwrap();
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    generated_files(output.path())
+}
+
+fn generated_files(dir: &Path) -> Vec<(String, String)> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, std::fs::read_to_string(&path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Owners of split levels whose region doesn't get the target keep owning them as the source
+/// building, so the levels that source building owns don't change
+#[test]
+fn owner_region_without_target_keeps_its_levels() {
+    let split = "BUILDINGS = {
+\ts:STATE_A = {
+\t\tregion_state:GBR = {
+\t\t\tcreate_building = {
+\t\t\t\tbuilding = \"building_textile_mill\"
+\t\t\t\tadd_ownership = {
+\t\t\t\t\tbuilding = {
+\t\t\t\t\t\ttype = \"building_textile_mill\"
+\t\t\t\t\t\tcountry = \"c:GBR\"
+\t\t\t\t\t\tlevels = 8
+\t\t\t\t\t\tregion = \"STATE_B\"
+\t\t\t\t\t}
+\t\t\t\t}
+\t\t\t}
+\t\t}
+\t}
+}
+";
+    // Too small to be split, so STATE_B gets no tailoring workshop
+    let owner = "BUILDINGS = {
+\ts:STATE_B = {
+\t\tregion_state:GBR = {
+\t\t\tcreate_building = {
+\t\t\t\tbuilding = \"building_textile_mill\"
+\t\t\t\tadd_ownership = {
+\t\t\t\t\tcountry = {
+\t\t\t\t\t\tcountry = \"c:GBR\"
+\t\t\t\t\t\tlevels = 3
+\t\t\t\t\t}
+\t\t\t\t}
+\t\t\t}
+\t\t}
+\t}
+}
+";
+    let ratios = "ownership_buildings = [\"building_textile_mill\"]
+
+[[split]]
+source = \"building_textile_mill\"
+ratio = 4
+target = \"building_tailoring_workshop\"
+min_total_levels = 4
+";
+
+    let files = run_buildings(&[("00_a.txt", split), ("01_b.txt", owner)], ratios);
+    let (_, split) = files
+        .iter()
+        .find(|(name, _)| name == "ir_00_a.txt")
+        .unwrap();
+    let workshop = split
+        .split("building = \"building_tailoring_workshop\"")
+        .nth(1)
+        .unwrap();
+    assert!(
+        workshop.contains("type = \"building_textile_mill\""),
+        "{}",
+        split
+    );
+    assert!(
+        files
+            .iter()
+            .filter(|(name, _)| name != "ir_00_a.txt")
+            .all(|(_, contents)| !contents.contains("remove_building")),
+        "{:?}",
+        files
+    );
+}
+
+/// Levels of every building in the buildings `contents` by the state and the country owning
+/// them, for every building together
+fn owned_levels(contents: &str) -> BTreeMap<(String, String), u32> {
+    let mut levels = BTreeMap::new();
+    let (mut state, mut country) = (String::new(), String::new());
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with("s:") {
+            state = line.split(' ').next().unwrap().to_string();
+        } else if let Some(owner) = line.strip_prefix("country = \"") {
+            country = owner.trim_end_matches('"').to_string();
+        } else if let Some(owned) = line.strip_prefix("levels = ") {
+            *levels.entry((state.clone(), country.clone())).or_default() +=
+                owned.parse::<u32>().unwrap();
+        }
+    }
+    levels
+}
+
+/// Every owner of a building split into several targets keeps the levels it owned, and the
+/// building and its targets have as many levels as the building had
+#[test]
+fn multi_target_split_conserves_levels() {
+    let buildings = "BUILDINGS = {
+\ts:STATE_A = {
+\t\tregion_state:GBR = {
+\t\t\tcreate_building = {
+\t\t\t\tbuilding = \"building_food_industry\"
+\t\t\t\tadd_ownership = {
+\t\t\t\t\tcountry = {
+\t\t\t\t\t\tcountry = \"c:GBR\"
+\t\t\t\t\t\tlevels = 12
+\t\t\t\t\t}
+\t\t\t\t\tbuilding = {
+\t\t\t\t\t\ttype = \"building_food_industry\"
+\t\t\t\t\t\tcountry = \"c:GBR\"
+\t\t\t\t\t\tlevels = 8
+\t\t\t\t\t\tregion = \"STATE_A\"
+\t\t\t\t\t}
+\t\t\t\t\tcompany = {
+\t\t\t\t\t\ttype = \"company_basic_food\"
+\t\t\t\t\t\tcountry = \"c:GBR\"
+\t\t\t\t\t\tlevels = 4
+\t\t\t\t\t}
+\t\t\t\t}
+\t\t\t\treserves = 1
+\t\t\t}
+\t\t}
+\t}
+\ts:STATE_B = {
+\t\tregion_state:FRA = {
+\t\t\tcreate_building = {
+\t\t\t\tbuilding = \"building_food_industry\"
+\t\t\t\tadd_ownership = {
+\t\t\t\t\tcountry = {
+\t\t\t\t\t\tcountry = \"c:FRA\"
+\t\t\t\t\t\tlevels = 7
+\t\t\t\t\t}
+\t\t\t\t\tcountry = {
+\t\t\t\t\t\tcountry = \"c:GBR\"
+\t\t\t\t\t\tlevels = 3
+\t\t\t\t\t}
+\t\t\t\t\tbuilding = {
+\t\t\t\t\t\ttype = \"building_manor_house\"
+\t\t\t\t\t\tcountry = \"c:FRA\"
+\t\t\t\t\t\tlevels = 5
+\t\t\t\t\t\tregion = \"STATE_B\"
+\t\t\t\t\t}
+\t\t\t\t}
+\t\t\t\treserves = 1
+\t\t\t}
+\t\t}
+\t}
+}
+";
+    let ratios = "[[split]]
+source = \"building_food_industry\"
+keep = 60
+targets = [
+    { building = \"building_distillery\", weight = 25 },
+    { building = \"building_cannery\", weight = 15 },
+]
+";
+
+    let files = run_buildings(&[("00_food.txt", buildings)], ratios);
+    let [(name, contents)] = files.as_slice() else {
+        panic!("{:?}", files);
+    };
+    assert_eq!(name, "ir_00_food.txt");
+    for target in ["building_distillery", "building_cannery"] {
+        assert_eq!(
+            contents
+                .matches(&format!("building = \"{}\"", target))
+                .count(),
+            2,
+            "{}",
+            contents
+        );
+    }
+
+    let state = |state: &str, country: &str| (state.to_string(), country.to_string());
+    assert_eq!(
+        owned_levels(contents),
+        BTreeMap::from([
+            (state("s:STATE_A", "c:GBR"), 24),
+            (state("s:STATE_B", "c:FRA"), 12),
+            (state("s:STATE_B", "c:GBR"), 3),
+        ]),
+        "{}",
+        contents
+    );
+}
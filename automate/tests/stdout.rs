@@ -0,0 +1,70 @@
+use std::process::Command;
+
+const BUILDINGS: &str = "BUILDINGS = {
+\ts:STATE_HOME_COUNTIES = {
+\t\tregion_state:GBR = {
+\t\t\tcreate_building = {
+\t\t\t\tbuilding = \"building_textile_mill\"
+\t\t\t\tadd_ownership = {
+\t\t\t\t\tcountry = {
+\t\t\t\t\t\tcountry = \"c:GBR\"
+\t\t\t\t\t\tlevels = 8
+\t\t\t\t\t}
+\t\t\t\t}
+\t\t\t\treserves = 1
+\t\t\t\tactivate_production_methods = { \"pm_handsewn_clothes\" }
+\t\t\t}
+\t\t}
+\t}
+}
+";
+
+const RATIOS: &str = "[[split]]
+source = \"building_textile_mill\"
+ratio = 4
+target = \"building_tailoring_workshop\"
+";
+
+/// Streaming to stdout prints nothing but the generated files, while the messages of the run
+/// go to stderr
+#[test]
+fn stdout_only_has_generated_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("00_buildings.txt"), BUILDINGS).unwrap();
+    std::fs::write(dir.path().join("01_buildings.txt"), BUILDINGS).unwrap();
+    std::fs::write(dir.path().join("ratios.toml"), RATIOS).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_automate"))
+        .arg("buildings")
+        .arg(dir.path())
+        .arg("-")
+        .arg("--ratios")
+        .arg(dir.path().join("ratios.toml"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let headers = stdout
+        .lines()
+        .filter(|line| line.starts_with("# ==> "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        headers,
+        vec![
+            "# ==> ir_00_buildings.txt <==",
+            "# ==> ir_01_buildings.txt <=="
+        ]
+    );
+    assert!(stdout.starts_with("# ==> "), "{}", stdout);
+    assert!(stdout.contains("building_tailoring_workshop"), "{}", stdout);
+    assert!(!stdout.contains("files ok"), "{}", stdout);
+    assert!(!stdout.contains(" -> "), "{}", stdout);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("files ok"), "{}", stderr);
+}